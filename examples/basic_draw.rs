@@ -24,7 +24,7 @@ fn main() {
 
     app.clear(true);
     app.start_event_loop(true, false, false, |ctx, event| {
-        if let InputEvent::WacomEvent { event } = event {
+        if let InputEvent::WacomEvent { event, .. } = event {
             match event {
                 // The pen can have any number of attributes assigned to it at a
                 // time. For example, when drawing with the tip of the Marker,
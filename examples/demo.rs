@@ -5,10 +5,12 @@ use libremarkable::framebuffer::storage;
 use libremarkable::framebuffer::PartialRefreshMode;
 use libremarkable::framebuffer::{FramebufferDraw, FramebufferIO, FramebufferRefresh};
 use libremarkable::image::GenericImage;
+use libremarkable::input::palm::PalmRejection;
 use libremarkable::input::{InputDevice, InputEvent};
 use libremarkable::ui_extensions::element::{
     UIConstraintRefresh, UIElement, UIElementHandle, UIElementWrapper,
 };
+use libremarkable::ui_extensions::layout::{Alignment, Insets, LayoutNode};
 use libremarkable::{appctx, battery, image, input};
 use libremarkable::{end_bench, start_bench};
 
@@ -478,8 +480,10 @@ fn loop_update_topbar(app: &mut appctx::ApplicationContext<'_>, millis: u64) {
                 )
             );
         }
-        app.draw_element("time");
-        app.draw_element("battery");
+        // Both labels get redrawn and refreshed together
+        app.mark_dirty("time");
+        app.mark_dirty("battery");
+        app.render_frame();
         sleep(Duration::from_millis(millis));
     }
 }
@@ -491,9 +495,7 @@ fn loop_update_topbar(app: &mut appctx::ApplicationContext<'_>, millis: u64) {
 fn on_wacom_input(app: &mut appctx::ApplicationContext<'_>, input: input::WacomEvent) {
     match input {
         input::WacomEvent::Draw {
-            position,
-            pressure,
-            tilt: _,
+            position, pressure, ..
         } => {
             let mut wacom_stack = WACOM_HISTORY.lock().unwrap();
 
@@ -586,11 +588,7 @@ fn on_wacom_input(app: &mut appctx::ApplicationContext<'_>, input: input::WacomE
                 _ => {}
             }
         }
-        input::WacomEvent::Hover {
-            position: _,
-            distance,
-            tilt: _,
-        } => {
+        input::WacomEvent::Hover { distance, .. } => {
             // If the pen is hovering, don't record its coordinates as the origin of the next line
             if distance > 1 {
                 let mut wacom_stack = WACOM_HISTORY.lock().unwrap();
@@ -1119,7 +1117,6 @@ fn main() {
     app.add_element(
         "quickRedraw",
         UIElementWrapper {
-            position: cgmath::Point2 { x: 15, y: 1850 },
             refresh: UIConstraintRefresh::Refresh,
             onclick: if is_rm_2 {
                 Some(|app, _| quick_redraw(app))
@@ -1138,7 +1135,6 @@ fn main() {
     app.add_element(
         "fullRedraw",
         UIElementWrapper {
-            position: cgmath::Point2 { x: 565, y: 1850 },
             refresh: UIConstraintRefresh::Refresh,
             onclick: if is_rm_2 {
                 Some(|app, _| full_redraw(app))
@@ -1157,7 +1153,6 @@ fn main() {
     app.add_element(
         "toggleTouch",
         UIElementWrapper {
-            position: cgmath::Point2 { x: 1112, y: 1850 },
             refresh: UIConstraintRefresh::Refresh,
             onclick: if is_rm_2 {
                 Some(|app, _| toggle_touch(app))
@@ -1173,6 +1168,22 @@ fn main() {
             ..Default::default()
         },
     );
+    // Instead of hardcoding their position, let the redraw buttons be spread along the bottom
+    app.set_layout(
+        LayoutNode::vstack(vec![
+            LayoutNode::spacer(),
+            LayoutNode::hstack(vec![
+                LayoutNode::element("quickRedraw"),
+                LayoutNode::spacer(),
+                LayoutNode::element("fullRedraw"),
+                LayoutNode::spacer(),
+                LayoutNode::element("toggleTouch"),
+            ])
+            .padding(Insets::symmetric(10, 15)),
+        ])
+        .align(Alignment::Stretch),
+    );
+
     // Create the top bar's time and battery labels. We can mutate these later.
     let dt: DateTime<Local> = Local::now();
     app.add_element(
@@ -1250,13 +1261,16 @@ fn main() {
     "#,
     );
 
+    // Keep the palm resting on the display from drawing while writing with the pen
+    app.enable_palm_rejection(PalmRejection::default());
+
     info!("Init complete. Beginning event dispatch...");
 
     // Blocking call to process events from digitizer + touchscreen + physical buttons
     app.start_event_loop(true, true, true, |ctx, evt| match evt {
-        InputEvent::WacomEvent { event } => on_wacom_input(ctx, event),
-        InputEvent::MultitouchEvent { event } => on_touch_handler(ctx, event),
-        InputEvent::GPIO { event } => on_button_press(ctx, event),
+        InputEvent::WacomEvent { event, .. } => on_wacom_input(ctx, event),
+        InputEvent::MultitouchEvent { event, .. } => on_touch_handler(ctx, event),
+        InputEvent::GPIO { event, .. } => on_button_press(ctx, event),
         _ => {}
     });
     clock_thread.join().unwrap();
@@ -5,25 +5,46 @@ use std::collections::HashMap;
 use std::marker::PhantomData;
 use std::ops::DerefMut;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{RecvError, RecvTimeoutError};
 use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime};
 
 use aabb_quadtree::{geom, ItemId, QuadTree};
+use log::error;
 #[cfg(feature = "hlua")]
 use log::warn;
 
+#[cfg(feature = "battery")]
+use crate::battery::monitor::{BatteryMonitor, BatteryMonitorConfig};
 use crate::framebuffer::cgmath;
 use crate::framebuffer::common::*;
 use crate::framebuffer::core::Framebuffer;
+use crate::framebuffer::request::{RefreshPreset, RefreshRequest};
 use crate::framebuffer::FramebufferDraw;
+use crate::framebuffer::FramebufferIO;
 use crate::framebuffer::FramebufferRefresh;
 use crate::framebuffer::PartialRefreshMode;
-use crate::input::ev::EvDevContext;
-use crate::input::MultitouchEvent;
-use crate::input::{InputDevice, InputEvent};
+use crate::input::calibration::{self, AffineCalibration, CalibrationSession};
+use crate::input::ev::{EvDevContext, InputGrab};
+use crate::input::gesture::{GestureConfig, GestureRecognizer};
+use crate::input::palm::PalmRejection;
+use crate::input::pen::{PenConfig, PenTracker};
+use crate::input::reactor::InputReactor;
+use crate::input::record::{InputRecorder, SharedInputRecorder};
+use crate::input::source::InputSource;
+use crate::input::{GPIOEvent, MultitouchEvent, PhysicalButton, WacomEvent, WacomPen};
+use crate::input::{InputDevice, InputDeviceState, InputEvent};
+use crate::power::idle::{IdleConfig, IdleEvent, IdleTracker};
+use crate::power::{self, PowerEvent, SleepScreen, SuspendConfig};
+use crate::ui_extensions::damage::Damage;
 use crate::ui_extensions::element::{
-    ActiveRegionFunction, ActiveRegionHandler, UIConstraintRefresh, UIElementHandle,
+    ActiveRegionFunction, ActiveRegionHandler, UIConstraintRefresh, UIElement, UIElementHandle,
     UIElementWrapper,
 };
+use crate::ui_extensions::layout::LayoutNode;
+use crate::ui_extensions::pointer::{
+    EventPropagation, PointerDispatcher, PointerEvent, PointerEventKind, PointerSource,
+};
 
 #[cfg(feature = "hlua")]
 use hlua::Lua;
@@ -50,11 +71,27 @@ pub struct ApplicationContext<'a> {
     input_rx: std::sync::mpsc::Receiver<InputEvent>,
 
     button_ctx: RwLock<Option<EvDevContext>>,
+    keyboard_ctx: RwLock<Option<EvDevContext>>,
     wacom_ctx: RwLock<Option<EvDevContext>>,
     touch_ctx: RwLock<Option<EvDevContext>>,
+    /// Reads the Wacom, multitouch and GPIO devices unless they use an `InputSource`
+    input_reactor: Option<InputReactor>,
 
     active_regions: QuadTree<ActiveRegionHandler>,
     ui_elements: HashMap<String, UIElementHandle>,
+    layout: Option<LayoutNode>,
+    pointer_dispatcher: PointerDispatcher,
+    pointer_captures: HashMap<PointerSource, Vec<UIElementHandle>>,
+    refresh_presets: HashMap<RefreshPreset, RefreshRequest>,
+    gesture_recognizer: Option<GestureRecognizer>,
+    pen_tracker: Option<PenTracker>,
+    palm_rejection: Option<PalmRejection>,
+    input_recorder: SharedInputRecorder,
+    #[cfg(feature = "battery")]
+    battery_monitor: Option<BatteryMonitor>,
+    power_button_suspend: Option<SuspendConfig>,
+    idle_tracker: Option<IdleTracker>,
+    idle_suspend: Option<(Duration, SuspendConfig)>,
 }
 
 impl Default for ApplicationContext<'static> {
@@ -68,7 +105,9 @@ impl Default for ApplicationContext<'static> {
         let mut res = ApplicationContext {
             wacom_ctx: RwLock::new(None),
             button_ctx: RwLock::new(None),
+            keyboard_ctx: RwLock::new(None),
             touch_ctx: RwLock::new(None),
+            input_reactor: None,
             framebuffer,
             xres,
             yres,
@@ -80,6 +119,19 @@ impl Default for ApplicationContext<'static> {
             input_rx,
             input_tx,
             ui_elements: HashMap::new(),
+            layout: None,
+            pointer_dispatcher: PointerDispatcher::default(),
+            pointer_captures: HashMap::new(),
+            refresh_presets: HashMap::new(),
+            gesture_recognizer: None,
+            pen_tracker: None,
+            palm_rejection: None,
+            input_recorder: Default::default(),
+            #[cfg(feature = "battery")]
+            battery_monitor: None,
+            power_button_suspend: None,
+            idle_tracker: None,
+            idle_suspend: None,
             active_regions: QuadTree::default(geom::Rect::from_points(
                 &geom::Point { x: 0.0, y: 0.0 },
                 &geom::Point {
@@ -178,14 +230,11 @@ impl<'a> ApplicationContext<'a> {
 
         let marker = match refresh {
             UIConstraintRefresh::Refresh | UIConstraintRefresh::RefreshAndWait => framebuffer
-                .partial_refresh(
-                    &draw_area,
-                    PartialRefreshMode::Async,
-                    waveform_mode::WAVEFORM_MODE_GC16_FAST,
-                    display_temp::TEMP_USE_REMARKABLE_DRAW,
-                    dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
-                    0,
-                    false,
+                .refresh(
+                    &self
+                        .refresh_request(RefreshPreset::Ui)
+                        .region(draw_area)
+                        .mode(PartialRefreshMode::Async),
                 ),
             _ => return draw_area,
         };
@@ -210,14 +259,11 @@ impl<'a> ApplicationContext<'a> {
         let draw_area = mxcfb_rect::from(position.cast().unwrap(), size);
         let marker = match refresh {
             UIConstraintRefresh::Refresh | UIConstraintRefresh::RefreshAndWait => framebuffer
-                .partial_refresh(
-                    &draw_area,
-                    PartialRefreshMode::Async,
-                    waveform_mode::WAVEFORM_MODE_GC16_FAST,
-                    display_temp::TEMP_USE_REMARKABLE_DRAW,
-                    dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
-                    0,
-                    false,
+                .refresh(
+                    &self
+                        .refresh_request(RefreshPreset::Ui)
+                        .region(draw_area)
+                        .mode(PartialRefreshMode::Async),
                 ),
             _ => return draw_area,
         };
@@ -242,14 +288,11 @@ impl<'a> ApplicationContext<'a> {
         };
         let marker = match refresh {
             UIConstraintRefresh::Refresh | UIConstraintRefresh::RefreshAndWait => framebuffer
-                .partial_refresh(
-                    &draw_area,
-                    PartialRefreshMode::Async,
-                    waveform_mode::WAVEFORM_MODE_GC16_FAST,
-                    display_temp::TEMP_USE_REMARKABLE_DRAW,
-                    dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
-                    0,
-                    false,
+                .refresh(
+                    &self
+                        .refresh_request(RefreshPreset::Ui)
+                        .region(draw_area)
+                        .mode(PartialRefreshMode::Async),
                 ),
             _ => return draw_area,
         };
@@ -284,17 +327,24 @@ impl<'a> ApplicationContext<'a> {
 
     pub fn draw_element(&mut self, name: &str) -> bool {
         let appref = self.upgrade_ref();
-        match self.ui_elements.get(name) {
-            None => false,
-            Some(element) => {
+        if !self.ui_elements.contains_key(name) {
+            return false;
+        }
+
+        // The element may have changed its size, which can move others around it
+        let mut names = self.relayout();
+        names.retain(|n| n != name);
+        names.push(name.to_owned());
+        for n in names {
+            if let Some(element) = self.ui_elements.get(&n) {
                 let handler = element.read().onclick.map(|handler| ActiveRegionHandler {
                     handler,
                     element: element.clone(),
                 });
                 element.write().draw(appref, &handler);
-                true
             }
         }
+        true
     }
 
     pub fn get_element_by_name(&mut self, name: &str) -> Option<UIElementHandle> {
@@ -303,6 +353,7 @@ impl<'a> ApplicationContext<'a> {
 
     pub fn draw_elements(&mut self) {
         start_bench!(stopwatch, draw_elements);
+        self.relayout();
         let mut elems: Vec<_> = self.ui_elements.values().cloned().collect();
 
         for element in &mut elems {
@@ -315,6 +366,182 @@ impl<'a> ApplicationContext<'a> {
         end_bench!(draw_elements);
     }
 
+    /// Sets the layout tree used to position the elements it references. Elements that
+    /// aren't part of the tree keep their own `position`. The positions are computed on
+    /// the next call to `draw_elements`, `draw_element` or `relayout`.
+    pub fn set_layout(&mut self, layout: LayoutNode) {
+        self.layout = Some(layout);
+    }
+
+    /// Removes the layout tree. Elements stay where they were last placed.
+    pub fn remove_layout(&mut self) -> Option<LayoutNode> {
+        self.layout.take()
+    }
+
+    /// Recomputes the layout against the current resolution of the framebuffer and
+    /// updates the `position` (and for `UIElement::Region` the `size`) of the elements
+    /// in it. Returns the names of the elements that got moved or resized.
+    pub fn relayout(&mut self) -> Vec<String> {
+        let layout = match self.layout {
+            Some(ref layout) => layout.clone(),
+            None => return vec![],
+        };
+        let framebuffer = self.get_framebuffer_ref();
+        let bounds = mxcfb_rect {
+            top: 0,
+            left: 0,
+            width: framebuffer.var_screen_info.xres,
+            height: framebuffer.var_screen_info.yres,
+        };
+
+        let appref = self.upgrade_ref();
+        let mut measured = HashMap::new();
+        for name in layout.element_names() {
+            if let Some(element) = self.ui_elements.get(name) {
+                if let Some(extent) = element.read().measure(appref) {
+                    measured.insert(name.to_owned(), extent);
+                }
+            }
+        }
+
+        let mut changed = vec![];
+        let placed = layout.arrange(&bounds, &|name: &str| measured.get(name).map(|e| e.1));
+        for (name, rect) in placed {
+            let (element, (offset, size)) = match (self.ui_elements.get(&name), measured.get(&name))
+            {
+                (Some(element), Some(extent)) => (element, extent),
+                _ => continue,
+            };
+            let mut element = element.write();
+            let position = rect.top_left().cast::<i32>().unwrap() - offset;
+            let mut updated = element.position != position;
+            element.position = position;
+            if let UIElement::Region {
+                size: ref mut s, ..
+            } = element.inner
+            {
+                if *size != rect.size() {
+                    *s = rect.size();
+                    updated = true;
+                }
+            }
+            if updated {
+                element.dirty = true;
+                changed.push(name);
+            }
+        }
+        changed
+    }
+
+    /// Marks the element as changed so that the next `render_frame` redraws it.
+    /// Returns false if there is no element with that name.
+    pub fn mark_dirty(&mut self, name: &str) -> bool {
+        match self.ui_elements.get(name) {
+            Some(element) => {
+                element.write().dirty = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Redraws what changed since the last frame. The area previously covered by each dirty
+    /// element and the area it is going to cover make up the damage. That damage is cleared,
+    /// every element touching it is redrawn in z-order and only then the damaged areas get
+    /// refreshed, so no blank frame is shown in between.
+    ///
+    /// The refresh is skipped if all dirty elements use `UIConstraintRefresh::NoRefresh` and
+    /// waited for if any of them uses `UIConstraintRefresh::RefreshAndWait`.
+    /// Returns the refreshed areas.
+    pub fn render_frame(&mut self) -> Vec<mxcfb_rect> {
+        start_bench!(stopwatch, render_frame);
+        self.relayout();
+        let appref = self.upgrade_ref();
+        let framebuffer = self.get_framebuffer_ref();
+
+        // Back to front, with the name as tie breaker to get the same order on every frame
+        let mut elems: Vec<(i32, &String, &UIElementHandle)> = self
+            .ui_elements
+            .iter()
+            .map(|(name, element)| (element.read().z_index, name, element))
+            .collect();
+        elems.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+
+        let mut damage = Damage::default();
+        let (mut refresh, mut wait) = (false, false);
+        for (_, _, element) in &elems {
+            let element = element.read();
+            if !element.dirty {
+                continue;
+            }
+            if let Some(rect) = element.last_drawn_rect {
+                damage.add(&rect);
+            }
+            if let Some((offset, size)) = element.measure(appref) {
+                let top_left = element.position + offset;
+                damage.add(&mxcfb_rect::from(
+                    cgmath::Point2 {
+                        x: top_left.x.max(0) as u32,
+                        y: top_left.y.max(0) as u32,
+                    },
+                    size,
+                ));
+            }
+            match element.refresh {
+                UIConstraintRefresh::NoRefresh => {}
+                UIConstraintRefresh::Refresh => refresh = true,
+                UIConstraintRefresh::RefreshAndWait => {
+                    refresh = true;
+                    wait = true;
+                }
+            }
+        }
+        if damage.is_empty() {
+            return vec![];
+        }
+
+        for rect in damage.rects() {
+            framebuffer.fill_rect(rect.top_left().cast().unwrap(), rect.size(), color::WHITE);
+        }
+
+        for (_, _, element) in &elems {
+            let handler = element.read().onclick.map(|handler| ActiveRegionHandler {
+                handler,
+                element: (*element).clone(),
+            });
+            let mut element = element.write();
+            let affected = element.dirty
+                || element
+                    .last_drawn_rect
+                    .is_some_and(|rect| damage.intersects(&rect));
+            if affected {
+                element.render(appref, &handler);
+            }
+        }
+
+        if refresh {
+            let markers: Vec<u32> = damage
+                .rects()
+                .iter()
+                .map(|rect| {
+                    framebuffer.refresh(
+                        &self
+                            .refresh_request(RefreshPreset::Ui)
+                            .region(*rect)
+                            .mode(PartialRefreshMode::Async),
+                    )
+                })
+                .collect();
+            if wait {
+                for marker in markers {
+                    framebuffer.wait_refresh_complete(marker);
+                }
+            }
+        }
+        end_bench!(render_frame);
+        damage.rects().to_vec()
+    }
+
     /// Briefly flash the element's `last_drawn_rect`
     pub fn flash_element(&mut self, name: &str) {
         let framebuffer = self.get_framebuffer_ref();
@@ -322,14 +549,12 @@ impl<'a> ApplicationContext<'a> {
             let mut element = locked_element.write();
             if let Some(rect) = element.last_drawn_rect {
                 framebuffer.fill_rect(rect.top_left().cast().unwrap(), rect.size(), color::BLACK);
-                framebuffer.partial_refresh(
-                    &rect,
-                    PartialRefreshMode::Wait,
-                    waveform_mode::WAVEFORM_MODE_DU,
-                    display_temp::TEMP_USE_AMBIENT,
-                    dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
-                    0,
-                    false,
+                framebuffer.refresh(
+                    &self
+                        .refresh_request(RefreshPreset::FastDraw)
+                        .region(rect)
+                        .temperature(display_temp::TEMP_USE_AMBIENT)
+                        .mode(PartialRefreshMode::Wait),
                 );
 
                 // We can pass None as the `handler` here as we know this flashing is not
@@ -348,31 +573,44 @@ impl<'a> ApplicationContext<'a> {
         framebuffer.clear();
 
         if deep {
-            framebuffer.full_refresh(
-                waveform_mode::WAVEFORM_MODE_INIT,
-                display_temp::TEMP_USE_AMBIENT,
-                dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
-                0,
-                true,
+            framebuffer.refresh(
+                &self
+                    .refresh_request(RefreshPreset::FlashClear)
+                    .whole_screen()
+                    .mode(PartialRefreshMode::Wait),
             );
         } else {
-            framebuffer.partial_refresh(
-                &mxcfb_rect {
-                    top: 0,
-                    left: 0,
-                    height: yres,
-                    width: xres,
-                },
-                PartialRefreshMode::Wait,
-                waveform_mode::WAVEFORM_MODE_GC16_FAST,
-                display_temp::TEMP_USE_AMBIENT,
-                dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
-                0,
-                false,
+            framebuffer.refresh(
+                &self
+                    .refresh_request(RefreshPreset::Ui)
+                    .region(mxcfb_rect {
+                        top: 0,
+                        left: 0,
+                        height: yres,
+                        width: xres,
+                    })
+                    .temperature(display_temp::TEMP_USE_AMBIENT)
+                    .mode(PartialRefreshMode::Wait),
             );
         }
     }
 
+    /// The request the context and its elements refresh with for `preset`, without a
+    /// region. Unless changed with `set_refresh_preset`, that's `RefreshRequest::new(preset)`.
+    pub fn refresh_request(&self, preset: RefreshPreset) -> RefreshRequest {
+        self.refresh_presets
+            .get(&preset)
+            .copied()
+            .unwrap_or_else(|| RefreshRequest::new(preset))
+    }
+
+    /// Changes what `preset` means for this application, e.g. to use `TEMP_AUTO` for
+    /// every UI refresh. The region of `request` is ignored. The callers still choose
+    /// whether to wait for the refresh.
+    pub fn set_refresh_preset(&mut self, preset: RefreshPreset, request: RefreshRequest) {
+        self.refresh_presets.insert(preset, request.whole_screen());
+    }
+
     /// Sets an atomic flag to disable event dispatch. Exiting event dispatch loop will cause
     /// dispatch_events(..) function to reach completion.
     pub fn stop(&mut self) {
@@ -381,6 +619,10 @@ impl<'a> ApplicationContext<'a> {
         self.deactivate_input_device(InputDevice::Multitouch);
         self.deactivate_input_device(InputDevice::GPIO);
         self.deactivate_input_device(InputDevice::Wacom);
+        self.deactivate_input_device(InputDevice::Keyboard);
+        self.input_reactor = None;
+        #[cfg(feature = "battery")]
+        self.stop_battery_monitor();
 
         // This will make us stop consuming and dispatching the InputEvents.
         self.running.store(false, Ordering::Relaxed);
@@ -390,22 +632,27 @@ impl<'a> ApplicationContext<'a> {
     /// If it was disabled prior to calling this function, this function
     /// will immediately return `true`.
     ///
-    /// This function does not block until teardown is completed.
-    /// It will simply return after signalling the epoller, which is
-    /// guaranteed to exit completely, and can be verified by
-    /// calling `exited()`.
+    /// This function returns once the thread reading from the device has
+    /// exited, which only takes long for an `InputSource` that can't be interrupted.
     pub fn deactivate_input_device(&mut self, t: InputDevice) -> bool {
         // Return true if already disabled
         if !self.is_input_device_active(t) {
             return true;
         }
 
+        if let Some(ref reactor) = self.input_reactor {
+            if reactor.remove_device(t) {
+                return true;
+            }
+        }
+
         // Now we know that the device is active, we can move the context out of
         // the option and stop it.
         let mut dev = match t {
             InputDevice::Wacom => self.wacom_ctx.write().unwrap(),
             InputDevice::Multitouch => self.touch_ctx.write().unwrap(),
             InputDevice::GPIO => self.button_ctx.write().unwrap(),
+            InputDevice::Keyboard => self.keyboard_ctx.write().unwrap(),
             _ => return false,
         };
 
@@ -423,18 +670,28 @@ impl<'a> ApplicationContext<'a> {
             return true;
         }
 
+        // Devices found by the scan share the reactor thread
+        if matches!(
+            t,
+            InputDevice::Wacom | InputDevice::Multitouch | InputDevice::GPIO
+        ) {
+            return self.activate_on_reactor(t);
+        }
+
         // Now we know it isn't active, let's create and spawn
         // the producer thread
         let mut dev = match t {
             InputDevice::Wacom => self.wacom_ctx.write().unwrap(),
             InputDevice::Multitouch => self.touch_ctx.write().unwrap(),
             InputDevice::GPIO => self.button_ctx.write().unwrap(),
+            InputDevice::Keyboard => self.keyboard_ctx.write().unwrap(),
             _ => return false,
         };
 
         *dev = Some(EvDevContext::new(t, self.input_tx.clone()));
         match dev.as_mut() {
             Some(ref mut device) => {
+                device.set_recorder(self.input_recorder.clone());
                 device.start();
                 true
             }
@@ -442,13 +699,428 @@ impl<'a> ApplicationContext<'a> {
         }
     }
 
+    /// `t` must not be `InputDevice::Unknown`
+    fn input_context(&self, t: InputDevice) -> &RwLock<Option<EvDevContext>> {
+        match t {
+            InputDevice::Wacom => &self.wacom_ctx,
+            InputDevice::Multitouch => &self.touch_ctx,
+            InputDevice::GPIO => &self.button_ctx,
+            InputDevice::Keyboard => &self.keyboard_ctx,
+            InputDevice::Unknown => unreachable!(),
+        }
+    }
+
+    fn activate_on_reactor(&mut self, t: InputDevice) -> bool {
+        if self.input_reactor.is_none() {
+            match InputReactor::new(self.input_tx.clone(), self.input_recorder.clone()) {
+                Ok(reactor) => self.input_reactor = Some(reactor),
+                Err(e) => {
+                    error!("Failed to start the input reactor: {}", e);
+                    return false;
+                }
+            }
+        }
+        let reactor = self.input_reactor.as_ref().unwrap();
+        match reactor.add_device(t, InputDeviceState::new(t)) {
+            Ok(()) => true,
+            Err(e) => {
+                error!("Error while opening {:?}: {}", t, e);
+                false
+            }
+        }
+    }
+
+    /// Makes `start_event_loop` pass an `InputEvent::Battery` to its callback whenever
+    /// the capacity, charger or charging status changes. Replaces a monitor started before.
+    #[cfg(feature = "battery")]
+    pub fn start_battery_monitor(&mut self, config: BatteryMonitorConfig) -> std::io::Result<()> {
+        self.stop_battery_monitor();
+        let tx = self.input_tx.clone();
+        let monitor = BatteryMonitor::start(config, move |event| {
+            let event = InputEvent::Battery {
+                event,
+                timestamp: std::time::SystemTime::now(),
+            };
+            if let Err(e) = tx.send(event) {
+                error!("Failed to write InputEvent into the channel: {}", e);
+            }
+        })?;
+        self.battery_monitor = Some(monitor);
+        Ok(())
+    }
+
+    #[cfg(feature = "battery")]
+    pub fn stop_battery_monitor(&mut self) {
+        self.battery_monitor = None;
+    }
+
+    /// Shows the sleep screen, suspends the device to RAM and returns once it resumed,
+    /// with the time it slept. The devices read by the input reactor are closed before
+    /// suspending and opened again afterwards, as is the connection to rm2fb. Active
+    /// `EvDevContext`s, like the one reading keyboards, are stopped meanwhile. On resume,
+    /// an `InputEvent::Power` is queued for `start_event_loop`.
+    pub fn suspend(&mut self, config: &SuspendConfig) -> std::io::Result<Duration> {
+        let framebuffer = self.get_framebuffer_ref();
+        let screen = mxcfb_rect {
+            top: 0,
+            left: 0,
+            width: self.xres,
+            height: self.yres,
+        };
+        let saved = match config.restore_screen {
+            true => framebuffer.dump_region(screen).ok(),
+            false => None,
+        };
+        self.draw_sleep_screen(&config.sleep_screen);
+
+        let devices: Vec<InputDevice> = match self.input_reactor {
+            Some(ref reactor) => [
+                InputDevice::Wacom,
+                InputDevice::Multitouch,
+                InputDevice::GPIO,
+            ]
+            .into_iter()
+            .filter(|&t| reactor.remove_device(t))
+            .collect(),
+            None => vec![],
+        };
+        // The contexts keep their source, so keyboards and the sources set with
+        // `activate_input_source` carry on where they left off
+        let mut contexts = vec![];
+        for t in [
+            InputDevice::Wacom,
+            InputDevice::Multitouch,
+            InputDevice::GPIO,
+            InputDevice::Keyboard,
+        ] {
+            if let Some(ref mut ctx) = *self.input_context(t).write().unwrap() {
+                if !ctx.exited() && !ctx.exit_requested() {
+                    ctx.stop();
+                    contexts.push(t);
+                }
+            }
+        }
+
+        let slept = power::suspend(config.method);
+
+        if let Err(e) = framebuffer.reconnect() {
+            error!("Failed to reconnect the framebuffer after resuming: {}", e);
+        }
+        framebuffer.temperature_sensor.invalidate();
+        for t in devices {
+            self.activate_on_reactor(t);
+        }
+        for t in contexts {
+            if let Some(ref mut ctx) = *self.input_context(t).write().unwrap() {
+                ctx.start();
+            }
+        }
+        if let Some(saved) = saved {
+            if let Err(e) = framebuffer.restore_region(screen, &saved) {
+                error!("Failed to restore the screen after resuming: {}", e);
+            }
+            framebuffer.refresh(
+                &self
+                    .refresh_request(RefreshPreset::HighFidelity)
+                    .mode(PartialRefreshMode::Async)
+                    .full(true),
+            );
+        }
+        if let Ok(slept) = slept {
+            let event = InputEvent::Power {
+                event: PowerEvent::Resumed { slept },
+                timestamp: SystemTime::now(),
+            };
+            if let Err(e) = self.input_tx.send(event) {
+                error!("Failed to write InputEvent into the channel: {}", e);
+            }
+        }
+        slept
+    }
+
+    /// Draws the sleep screen and waits for it to be displayed
+    fn draw_sleep_screen(&mut self, sleep_screen: &SleepScreen) {
+        let framebuffer = self.get_framebuffer_ref();
+        let center = cgmath::Point2 {
+            x: self.xres as f32 / 2.0,
+            y: self.yres as f32 / 2.0,
+        };
+        match sleep_screen {
+            SleepScreen::Keep => return,
+            SleepScreen::Blank => framebuffer.clear(),
+            SleepScreen::Text(text) => {
+                framebuffer.clear();
+                let size = framebuffer.draw_text(center, text, 65.0, color::BLACK, true);
+                let position = cgmath::Point2 {
+                    x: center.x - size.width as f32 / 2.0,
+                    y: center.y + size.height as f32 / 2.0,
+                };
+                framebuffer.draw_text(position, text, 65.0, color::BLACK, false);
+            }
+            #[cfg(feature = "image")]
+            SleepScreen::Image(img) => {
+                framebuffer.clear();
+                let position = cgmath::Point2 {
+                    x: center.x as i32 - img.width() as i32 / 2,
+                    y: center.y as i32 - img.height() as i32 / 2,
+                };
+                framebuffer.draw_image(&img.to_rgb8(), position);
+            }
+        }
+        framebuffer.refresh(
+            &self
+                .refresh_request(RefreshPreset::HighFidelity)
+                .mode(PartialRefreshMode::Wait)
+                .full(true),
+        );
+    }
+
+    /// Makes `start_event_loop` call `suspend` with `config` whenever the power button
+    /// is pressed, after passing the press to its callback. `None` turns this off.
+    pub fn set_power_button_suspend(&mut self, config: Option<SuspendConfig>) {
+        self.power_button_suspend = config;
+    }
+
+    /// Makes `start_event_loop` pass an `InputEvent::Idle` to its callback whenever one
+    /// of the timeouts elapses without any input, and when the input resumes. With
+    /// `suspend_after` set, the device is also suspended after that timeout.
+    pub fn enable_idle_tracking(&mut self, config: IdleConfig) {
+        let mut timeouts = config.timeouts;
+        timeouts.extend(config.suspend_after);
+        self.idle_tracker = Some(IdleTracker::new(timeouts, Instant::now()));
+        self.idle_suspend = config.suspend_after.map(|t| (t, config.suspend));
+    }
+
+    pub fn disable_idle_tracking(&mut self) {
+        self.idle_tracker = None;
+        self.idle_suspend = None;
+    }
+
+    /// Also allows inhibiting the timeouts during long operations with `inhibit`
+    pub fn idle_tracker(&mut self) -> Option<&mut IdleTracker> {
+        self.idle_tracker.as_mut()
+    }
+
+    fn track_idle(&mut self, event: Option<&InputEvent>) -> Vec<InputEvent> {
+        let tracker = match self.idle_tracker {
+            Some(ref mut tracker) => tracker,
+            None => return vec![],
+        };
+        let now = Instant::now();
+        let mut events = vec![];
+        // Derived events like gestures count through the events causing them
+        if let Some(
+            InputEvent::WacomEvent { .. }
+            | InputEvent::MultitouchEvent { .. }
+            | InputEvent::GPIO { .. }
+            | InputEvent::Keyboard { .. }
+            | InputEvent::Power { .. },
+        ) = event
+        {
+            events.extend(tracker.activity(now));
+        }
+        events.extend(tracker.poll(now));
+        events
+            .into_iter()
+            .map(|event| InputEvent::Idle {
+                event,
+                timestamp: SystemTime::now(),
+            })
+            .collect()
+    }
+
+    fn suspend_when_idle(&mut self, events: &[InputEvent]) {
+        let (timeout, config) = match self.idle_suspend.take() {
+            Some(idle_suspend) => idle_suspend,
+            None => return,
+        };
+        let elapsed = events.iter().any(|e| match e {
+            InputEvent::Idle {
+                event: IdleEvent::Timeout { timeout: t },
+                ..
+            } => *t == timeout,
+            _ => false,
+        });
+        if elapsed {
+            if let Err(e) = self.suspend(&config) {
+                error!("Failed to suspend: {}", e);
+            }
+        }
+        self.idle_suspend = Some((timeout, config));
+    }
+
+    /// Records the raw events of all active input devices to `path` until
+    /// `stop_input_recording` is called. See `input::record::InputReplayer` for
+    /// playing them back.
+    pub fn start_input_recording<P: AsRef<std::path::Path>>(
+        &mut self,
+        path: P,
+    ) -> std::io::Result<()> {
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        let recorder = InputRecorder::new(Box::new(file) as Box<dyn std::io::Write + Send>)?;
+        *self.input_recorder.lock().unwrap() = Some(recorder);
+        Ok(())
+    }
+
+    pub fn stop_input_recording(&mut self) -> std::io::Result<()> {
+        match self.input_recorder.lock().unwrap().take() {
+            Some(recorder) => recorder.into_inner().map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    /// Like `activate_input_device`, but reads the events of `t` from `source` instead
+    /// of its evdev device. Any context already active for `t` is stopped first.
+    ///
+    /// This allows driving `start_event_loop` from a replay, a socket or generated events
+    /// (see `input::source`) instead of the hardware.
+    pub fn activate_input_source(&mut self, t: InputDevice, source: Box<dyn InputSource>) -> bool {
+        self.deactivate_input_device(t);
+
+        let mut dev = match t {
+            InputDevice::Wacom => self.wacom_ctx.write().unwrap(),
+            InputDevice::Multitouch => self.touch_ctx.write().unwrap(),
+            InputDevice::GPIO => self.button_ctx.write().unwrap(),
+            InputDevice::Keyboard => self.keyboard_ctx.write().unwrap(),
+            _ => return false,
+        };
+
+        let mut ctx = EvDevContext::new(t, self.input_tx.clone());
+        ctx.set_source(source);
+        ctx.start();
+        *dev = Some(ctx);
+        true
+    }
+
+    /// Runs the on-screen calibration of the Wacom digitizer or the touchscreen: shows
+    /// a series of targets to tap with the pen or a finger, then fits and applies a new
+    /// `AffineCalibration`. Blocks until all targets were tapped, consuming the input
+    /// events meanwhile. Persist the result with
+    /// `calibration::current().save(calibration::DEFAULT_CALIBRATION_PATH)`.
+    pub fn calibrate_input_device(&mut self, t: InputDevice) -> Result<AffineCalibration, String> {
+        if !matches!(t, InputDevice::Wacom | InputDevice::Multitouch) {
+            return Err(format!("{t:?} can't be calibrated"));
+        }
+        if !self.activate_input_device(t) {
+            return Err(format!("Failed to activate {t:?}"));
+        }
+        let size = cgmath::Vector2 {
+            x: self.xres as u16,
+            y: self.yres as u16,
+        };
+        let mut session = CalibrationSession::new(t, size);
+        let mut pen_position = None;
+        while let Some(target) = session.current_target() {
+            self.clear(false);
+            let framebuffer = self.get_framebuffer_ref();
+            let center: cgmath::Point2<i32> = target.cast().unwrap();
+            let arm = cgmath::Vector2 { x: 30, y: 0 };
+            framebuffer.draw_line(center - arm, center + arm, 3, color::BLACK);
+            let arm = cgmath::Vector2 { x: 0, y: 30 };
+            framebuffer.draw_line(center - arm, center + arm, 3, color::BLACK);
+            framebuffer.draw_circle(center, 12, color::BLACK);
+            self.clear_input_events();
+            framebuffer.refresh(
+                &self
+                    .refresh_request(RefreshPreset::Ui)
+                    .temperature(display_temp::TEMP_USE_AMBIENT)
+                    .mode(PartialRefreshMode::Wait)
+                    .full(true),
+            );
+
+            let tapped = loop {
+                let event = self.input_rx.recv().map_err(|e| e.to_string())?;
+                match (t, event) {
+                    (
+                        InputDevice::Wacom,
+                        InputEvent::WacomEvent {
+                            event: WacomEvent::Draw { position, .. },
+                            ..
+                        },
+                    ) => {
+                        // Where the pen first touched
+                        pen_position.get_or_insert(position);
+                    }
+                    (
+                        InputDevice::Wacom,
+                        InputEvent::WacomEvent {
+                            event:
+                                WacomEvent::InstrumentChange {
+                                    pen: WacomPen::Touch,
+                                    state: false,
+                                },
+                            ..
+                        },
+                    ) => {
+                        if let Some(position) = pen_position.take() {
+                            break position;
+                        }
+                    }
+                    (
+                        InputDevice::Multitouch,
+                        InputEvent::MultitouchEvent {
+                            event: MultitouchEvent::Release { finger },
+                            ..
+                        },
+                    ) => break finger.pos.cast().unwrap(),
+                    _ => {}
+                }
+            };
+            session.record(tapped);
+        }
+        self.clear(false);
+
+        let model = session.finish()?;
+        let mut current = calibration::current();
+        if let Some(device) = current.for_device_mut(t) {
+            *device = model;
+        }
+        calibration::set_current(current);
+        Ok(model)
+    }
+
+    fn clear_input_events(&self) {
+        while self.input_rx.try_recv().is_ok() {}
+    }
+
+    /// Takes exclusive access of an active input device, so that other processes
+    /// running alongside (e.g. xochitl or a launcher) stop reacting to its events.
+    /// The grab lasts until the returned guard is dropped.
+    pub fn grab_input_device(&self, t: InputDevice) -> std::io::Result<InputGrab> {
+        let ctx = match t {
+            InputDevice::Wacom => self.wacom_ctx.read().unwrap(),
+            InputDevice::Multitouch => self.touch_ctx.read().unwrap(),
+            InputDevice::GPIO => self.button_ctx.read().unwrap(),
+            InputDevice::Keyboard => self.keyboard_ctx.read().unwrap(),
+            InputDevice::Unknown => return Err(std::io::ErrorKind::InvalidInput.into()),
+        };
+        if let Some(ref reactor) = self.input_reactor {
+            if ctx.is_none() && reactor.has_device(t) {
+                return reactor.grab(t);
+            }
+        }
+        match *ctx {
+            Some(ref ctx) => ctx.grab(),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                format!("{t:?} is not active"),
+            )),
+        }
+    }
+
     /// Returns true if the given `InputDevice` is active, as in
-    /// there is an `EvDevContext` for it and that context has a
-    /// currently running `epoll` thread
+    /// the input reactor reads it, or there is an `EvDevContext` for it
+    /// and that context has a currently running thread
     pub fn is_input_device_active(&self, t: InputDevice) -> bool {
+        if let Some(ref reactor) = self.input_reactor {
+            if reactor.has_device(t) {
+                return true;
+            }
+        }
         let ctx = match t {
             InputDevice::Unknown => return false,
             InputDevice::GPIO => self.button_ctx.read().unwrap(),
+            InputDevice::Keyboard => self.keyboard_ctx.read().unwrap(),
             InputDevice::Multitouch => self.touch_ctx.read().unwrap(),
             InputDevice::Wacom => self.wacom_ctx.read().unwrap(),
         };
@@ -486,12 +1158,53 @@ impl<'a> ApplicationContext<'a> {
 
         let mut last_active_region_gesture_id: i32 = -1;
         while self.running.load(Ordering::Relaxed) {
-            let event = self.input_rx.recv();
+            // Wake up in time to deliver long presses of pointers that don't move
+            let deadline = [
+                self.pointer_dispatcher.next_deadline(),
+                self.gesture_recognizer
+                    .as_ref()
+                    .and_then(GestureRecognizer::next_deadline),
+                self.idle_tracker
+                    .as_ref()
+                    .and_then(IdleTracker::next_deadline),
+            ]
+            .into_iter()
+            .flatten()
+            .min();
+            let event = match deadline {
+                Some(deadline) => match self
+                    .input_rx
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                {
+                    Ok(event) => Ok(event),
+                    Err(RecvTimeoutError::Timeout) => {
+                        let events = self.pointer_dispatcher.expire(Instant::now());
+                        self.deliver_pointer_events(events);
+                        for gesture in self.recognize_gestures(None) {
+                            callback(appref, gesture);
+                        }
+                        let idle = self.track_idle(None);
+                        for event in &idle {
+                            callback(appref, event.clone());
+                        }
+                        self.suspend_when_idle(&idle);
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => Err(RecvError),
+                },
+                None => self.input_rx.recv(),
+            };
+            let event = match event.map(|event| self.reject_palms(event)) {
+                Ok(Some(event)) => Ok(event),
+                Ok(None) => continue,
+                Err(e) => Err(e),
+            };
             match event {
                 Err(e) => eprintln!("Error in input event consumer: {e}"),
                 Ok(event) => {
                     if let InputEvent::MultitouchEvent {
                         event: MultitouchEvent::Press { finger } | MultitouchEvent::Move { finger },
+                        ..
                     } = event
                     {
                         // Check for and notify clickable active regions for multitouch events
@@ -505,22 +1218,51 @@ impl<'a> ApplicationContext<'a> {
                             last_active_region_gesture_id = gseq;
                         }
                     }
+                    let derived = self.process_event(&event);
+                    let power_press = matches!(
+                        event,
+                        InputEvent::GPIO {
+                            event: GPIOEvent::Press {
+                                button: PhysicalButton::POWER
+                            },
+                            ..
+                        }
+                    );
 
                     callback(appref, event);
+                    for event in &derived {
+                        callback(appref, event.clone());
+                    }
+                    self.suspend_when_idle(&derived);
+                    if power_press {
+                        if let Some(config) = self.power_button_suspend.take() {
+                            if let Err(e) = self.suspend(&config) {
+                                error!("Failed to suspend: {}", e);
+                            }
+                            self.power_button_suspend = Some(config);
+                        }
+                    }
                 }
             };
         }
     }
 
-    pub fn handle_event(&mut self, event: InputEvent) {
+    /// Handles `event` like `start_event_loop` does, for applications reading the events
+    /// themselves. Returns the pen, gesture and idle events derived from it.
+    pub fn handle_event(&mut self, event: InputEvent) -> Vec<InputEvent> {
         let appref = self.upgrade_ref();
 
         // Now we consume the input events
         self.running.store(true, Ordering::Relaxed);
 
         if self.running.load(Ordering::Relaxed) {
+            let event = match self.reject_palms(event) {
+                Some(event) => event,
+                None => return vec![],
+            };
             if let InputEvent::MultitouchEvent {
                 event: MultitouchEvent::Press { finger } | MultitouchEvent::Move { finger },
+                ..
             } = event
             {
                 // Check for and notify clickable active regions for multitouch events
@@ -528,6 +1270,176 @@ impl<'a> ApplicationContext<'a> {
                     (h.handler)(appref, h.element.clone());
                 }
             }
+            let derived = self.process_event(&event);
+            self.suspend_when_idle(&derived);
+            return derived;
+        }
+        vec![]
+    }
+
+    /// Passes an event that got past palm rejection to the element handlers, the pen
+    /// tracker, the gesture recognizer and the idle tracker. Returns the events they
+    /// derived from it.
+    fn process_event(&mut self, event: &InputEvent) -> Vec<InputEvent> {
+        self.dispatch_pointer(event);
+        let mut derived = self.track_pen(event);
+        derived.extend(self.recognize_gestures(Some(event)));
+        derived.extend(self.track_idle(Some(event)));
+        derived
+    }
+
+    /// Allows configuring how touch and pen input is turned into `PointerEvent`s
+    pub fn pointer_dispatcher(&mut self) -> &mut PointerDispatcher {
+        &mut self.pointer_dispatcher
+    }
+
+    /// Makes `start_event_loop` pass an `InputEvent::GestureEvent` to its callback for
+    /// every gesture recognized in the multitouch events, right after the event that
+    /// completed it.
+    pub fn enable_gestures(&mut self, config: GestureConfig) {
+        self.gesture_recognizer = Some(GestureRecognizer::new(config));
+    }
+
+    pub fn disable_gestures(&mut self) {
+        self.gesture_recognizer = None;
+    }
+
+    pub fn gesture_recognizer(&mut self) -> Option<&mut GestureRecognizer> {
+        self.gesture_recognizer.as_mut()
+    }
+
+    /// Makes `start_event_loop` pass an `InputEvent::PenEvent` to its callback for
+    /// proximity, barrel button and stroke changes of the pen, right after the Wacom
+    /// event causing them.
+    pub fn enable_pen_tracking(&mut self, config: PenConfig) {
+        self.pen_tracker = Some(PenTracker::new(config));
+    }
+
+    pub fn disable_pen_tracking(&mut self) {
+        self.pen_tracker = None;
+    }
+
+    pub fn pen_tracker(&mut self) -> Option<&mut PenTracker> {
+        self.pen_tracker.as_mut()
+    }
+
+    fn track_pen(&mut self, event: &InputEvent) -> Vec<InputEvent> {
+        match (self.pen_tracker.as_mut(), event) {
+            (Some(tracker), InputEvent::WacomEvent { event, timestamp }) => tracker
+                .process(event)
+                .into_iter()
+                .map(|event| InputEvent::PenEvent {
+                    event,
+                    timestamp: *timestamp,
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Drops the touches of palms resting on the display before the event loop, the
+    /// active regions or the element handlers get to see them.
+    pub fn enable_palm_rejection(&mut self, palm_rejection: PalmRejection) {
+        self.palm_rejection = Some(palm_rejection);
+    }
+
+    pub fn disable_palm_rejection(&mut self) {
+        self.palm_rejection = None;
+    }
+
+    pub fn palm_rejection(&mut self) -> Option<&mut PalmRejection> {
+        self.palm_rejection.as_mut()
+    }
+
+    fn reject_palms(&mut self, event: InputEvent) -> Option<InputEvent> {
+        match self.palm_rejection {
+            Some(ref mut palm_rejection) => palm_rejection.filter(event, Instant::now()),
+            None => Some(event),
+        }
+    }
+
+    /// Returns the elements with pointer handlers whose `last_drawn_rect` contains
+    /// `position`, topmost first.
+    pub fn hit_test(&self, position: cgmath::Point2<u16>) -> Vec<UIElementHandle> {
+        let point = position.cast().unwrap();
+        let mut hits: Vec<(i32, &String, &UIElementHandle)> = self
+            .ui_elements
+            .iter()
+            .filter_map(|(name, element)| {
+                let e = element.read();
+                let hit = !e.handlers.is_empty()
+                    && e.last_drawn_rect.is_some_and(|r| r.contains_point(&point));
+                hit.then_some((e.z_index, name, element))
+            })
+            .collect();
+        hits.sort_by(|a, b| (b.0, b.1).cmp(&(a.0, a.1)));
+        hits.into_iter().map(|(_, _, e)| e.clone()).collect()
+    }
+
+    fn dispatch_pointer(&mut self, event: &InputEvent) {
+        let now = Instant::now();
+        let mut events = self.pointer_dispatcher.expire(now);
+        events.extend(self.pointer_dispatcher.process(event, now));
+        self.deliver_pointer_events(events);
+    }
+
+    fn recognize_gestures(&mut self, event: Option<&InputEvent>) -> Vec<InputEvent> {
+        let recognizer = match self.gesture_recognizer {
+            Some(ref mut recognizer) => recognizer,
+            None => return vec![],
+        };
+        let now = Instant::now();
+        let (gestures, timestamp) = match event {
+            Some(InputEvent::MultitouchEvent { event, timestamp }) => {
+                (recognizer.process(event, now), *timestamp)
+            }
+            _ => (recognizer.expire(now), std::time::SystemTime::now()),
+        };
+        gestures
+            .into_iter()
+            .map(|event| InputEvent::GestureEvent { event, timestamp })
+            .collect()
+    }
+
+    fn deliver_pointer_events(&mut self, events: Vec<PointerEvent>) {
+        let appref = self.upgrade_ref();
+        for event in events {
+            // The elements hit by the press keep receiving the events of that pointer
+            let targets = match event.kind {
+                PointerEventKind::Press => {
+                    let targets = self.hit_test(event.position);
+                    self.pointer_captures.insert(event.source, targets.clone());
+                    targets
+                }
+                PointerEventKind::Release => self
+                    .pointer_captures
+                    .remove(&event.source)
+                    .unwrap_or_default(),
+                _ => self
+                    .pointer_captures
+                    .get(&event.source)
+                    .cloned()
+                    .unwrap_or_default(),
+            };
+
+            for element in targets {
+                let mut event = event;
+                let handler = {
+                    let e = element.read();
+                    let inside = e
+                        .last_drawn_rect
+                        .is_some_and(|r| r.contains_point(&event.position.cast().unwrap()));
+                    if event.kind == PointerEventKind::Release && !inside {
+                        event.kind = PointerEventKind::Cancel;
+                    }
+                    e.handlers.get(event.kind).cloned()
+                };
+                if let Some(handler) = handler {
+                    if handler(appref, element.clone(), &event) == EventPropagation::Stop {
+                        break;
+                    }
+                }
+            }
         }
     }
 
@@ -0,0 +1,211 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::battery::{self, BatteryError, BatteryStatus, ChargingStatus};
+
+/// Charges are in µAh and currents in µA, like in `BatteryStatus`
+#[derive(Clone, Debug)]
+struct Sample {
+    at: Instant,
+    charge_now: i32,
+    /// Without sign, as the gauges don't agree on one
+    current: u32,
+}
+
+/// Keeps a smoothed charge or discharge rate over a rolling window of readings, and
+/// derives the time until the battery is empty or full from it.
+///
+/// `current_now` jumps around a lot, e.g. whenever the display refreshes. So the rate
+/// is the slope of `charge_now` over the window once the charge changed enough for
+/// it to mean anything, and the median of the currents until then. The window starts
+/// over whenever the battery switches between charging and discharging.
+pub struct BatteryEstimator {
+    /// How far back readings are taken into account
+    pub window: Duration,
+    samples: VecDeque<Sample>,
+    last: Option<BatteryStatus>,
+}
+
+impl ::std::default::Default for BatteryEstimator {
+    fn default() -> Self {
+        BatteryEstimator::new(Duration::from_secs(10 * 60))
+    }
+}
+
+impl BatteryEstimator {
+    pub fn new(window: Duration) -> BatteryEstimator {
+        BatteryEstimator {
+            window,
+            samples: VecDeque::new(),
+            last: None,
+        }
+    }
+
+    /// Reads the internal battery and adds the reading. Call this periodically, e.g.
+    /// every 30 seconds or on every `BatteryEvent`.
+    pub fn update(&mut self) -> Result<(), BatteryError> {
+        let status = battery::status()?;
+        self.add_reading(Instant::now(), status);
+        Ok(())
+    }
+
+    pub fn add_reading(&mut self, at: Instant, status: BatteryStatus) {
+        if let Some(ref last) = self.last {
+            if is_charging(last.status) != is_charging(status.status) {
+                self.samples.clear();
+            }
+        }
+        while let Some(oldest) = self.samples.front() {
+            match at.checked_duration_since(oldest.at) {
+                Some(age) if age > self.window => self.samples.pop_front(),
+                _ => break,
+            };
+        }
+        self.samples.push_back(Sample {
+            at,
+            charge_now: status.charge_now,
+            current: status.current.unsigned_abs(),
+        });
+        self.last = Some(status);
+    }
+
+    /// The latest reading, if any
+    pub fn last_reading(&self) -> Option<&BatteryStatus> {
+        self.last.as_ref()
+    }
+
+    /// The smoothed charge (positive) or discharge (negative) rate in µA
+    pub fn rate(&self) -> Option<f32> {
+        let last = self.last.as_ref()?;
+        let magnitude = self.charge_slope(last).or_else(|| self.median_current())?;
+        match is_charging(last.status) {
+            true => Some(magnitude),
+            false => Some(-magnitude),
+        }
+    }
+
+    /// The rate from the change of `charge_now`, if it changed by at least 1% of
+    /// the full charge within the window
+    fn charge_slope(&self, last: &BatteryStatus) -> Option<f32> {
+        let (first, newest) = (self.samples.front()?, self.samples.back()?);
+        let delta = (newest.charge_now - first.charge_now).unsigned_abs();
+        let hours = newest.at.duration_since(first.at).as_secs_f32() / 3600.0;
+        if hours <= 0.0 || delta < last.charge_full.unsigned_abs() / 100 {
+            return None;
+        }
+        Some(delta as f32 / hours)
+    }
+
+    fn median_current(&self) -> Option<f32> {
+        let mut currents: Vec<u32> = self.samples.iter().map(|s| s.current).collect();
+        currents.sort_unstable();
+        match currents.len() {
+            0 => None,
+            n if n % 2 == 0 => Some((currents[n / 2 - 1] as f32 + currents[n / 2] as f32) / 2.0),
+            n => Some(currents[n / 2] as f32),
+        }
+    }
+
+    /// How long until the battery is empty at the current rate. `None` while charging
+    /// or when there's no rate yet.
+    pub fn time_to_empty(&self) -> Option<Duration> {
+        let last = self.last.as_ref()?;
+        let rate = self.rate().filter(|&r| r < 0.0)?;
+        hours(last.charge_now as f32 / -rate)
+    }
+
+    /// How long until the battery is full at the current rate. `None` while discharging
+    /// or when there's no rate yet.
+    pub fn time_to_full(&self) -> Option<Duration> {
+        let last = self.last.as_ref()?;
+        if last.status == ChargingStatus::Full {
+            return Some(Duration::ZERO);
+        }
+        let rate = self.rate().filter(|&r| r > 0.0)?;
+        hours((last.charge_full - last.charge_now).max(0) as f32 / rate)
+    }
+
+    /// The full charge relative to the design capacity, e.g. `0.87` for a battery
+    /// that lost 13% of its capacity. Can be above 1 for new batteries.
+    pub fn health(&self) -> Option<f32> {
+        let last = self.last.as_ref()?;
+        match last.charge_full_design {
+            design if design > 0 => Some(last.charge_full as f32 / design as f32),
+            _ => None,
+        }
+    }
+}
+
+fn is_charging(status: ChargingStatus) -> bool {
+    matches!(status, ChargingStatus::Charging | ChargingStatus::Full)
+}
+
+fn hours(hours: f32) -> Option<Duration> {
+    Duration::try_from_secs_f32(hours * 3600.0).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::battery::CapacityLevel;
+
+    fn reading(status: ChargingStatus, charge_now: i32, current: i32) -> BatteryStatus {
+        BatteryStatus {
+            capacity: charge_now * 100 / 1_600_000,
+            level: CapacityLevel::Normal,
+            status,
+            charge_now,
+            charge_full: 1_600_000,
+            charge_full_design: 2_000_000,
+            voltage: 4_000_000,
+            current,
+            temperature: 25.0,
+            health: None,
+            cycle_count: None,
+        }
+    }
+
+    #[test]
+    fn test_estimates() {
+        let start = Instant::now();
+        let minute = Duration::from_secs(60);
+        let mut estimator = BatteryEstimator::default();
+        assert_eq!(estimator.time_to_empty(), None);
+
+        // Noisy currents around 200mA: the median ignores the spike
+        for (i, current) in [-200_000, -210_000, -1_500_000, -190_000, -200_000]
+            .into_iter()
+            .enumerate()
+        {
+            estimator.add_reading(
+                start + minute * i as u32,
+                reading(ChargingStatus::Discharging, 800_000, current),
+            );
+        }
+        assert_eq!(estimator.rate(), Some(-200_000.0));
+        assert_eq!(
+            estimator.time_to_empty(),
+            Some(Duration::from_secs(4 * 3600))
+        );
+        assert_eq!(estimator.time_to_full(), None);
+        assert_eq!(estimator.health(), Some(0.8));
+
+        // Once the charge moved enough, its slope wins: 16000µAh in 6 minutes
+        estimator.add_reading(
+            start + minute * 6,
+            reading(ChargingStatus::Discharging, 784_000, -5_000_000),
+        );
+        assert_eq!(estimator.rate(), Some(-160_000.0));
+
+        // Plugging in starts over
+        estimator.add_reading(
+            start + minute * 7,
+            reading(ChargingStatus::Charging, 784_000, 408_000),
+        );
+        assert_eq!(estimator.time_to_empty(), None);
+        assert_eq!(
+            estimator.time_to_full(),
+            Some(Duration::from_secs(2 * 3600))
+        );
+    }
+}
@@ -0,0 +1,408 @@
+use crate::device::CURRENT_DEVICE;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Estimates the time left until the battery is empty or full
+pub mod estimate;
+/// Notifies about capacity, charger and status changes as they happen
+pub mod monitor;
+
+// File tree containing the rM2 battery:
+// https://github.com/Eeems/oxide/issues/48#issue-698181952 (line 3166 of tree.txt)
+
+#[derive(Debug)]
+pub enum BatteryError {
+    /// The attribute couldn't be read, e.g. because the driver doesn't provide it
+    Read {
+        attribute: String,
+        error: io::Error,
+    },
+    Empty {
+        attribute: String,
+    },
+    Parse {
+        attribute: String,
+        value: String,
+    },
+}
+
+impl fmt::Display for BatteryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BatteryError::Read { attribute, error } => {
+                write!(
+                    f,
+                    "Unable to read the battery attribute '{attribute}': {error}"
+                )
+            }
+            BatteryError::Empty { attribute } => {
+                write!(f, "The battery attribute '{attribute}' is empty")
+            }
+            BatteryError::Parse { attribute, value } => write!(
+                f,
+                "Unable to parse the contents of '{attribute}' during a battery query: {value:?}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BatteryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BatteryError::Read { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// The `capacity_level` attribute
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum CapacityLevel {
+    Unknown,
+    Critical,
+    Low,
+    Normal,
+    High,
+    Full,
+}
+
+impl FromStr for CapacityLevel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        Ok(match s {
+            "Unknown" => CapacityLevel::Unknown,
+            "Critical" => CapacityLevel::Critical,
+            "Low" => CapacityLevel::Low,
+            "Normal" => CapacityLevel::Normal,
+            "High" => CapacityLevel::High,
+            "Full" => CapacityLevel::Full,
+            _ => return Err(()),
+        })
+    }
+}
+
+/// The `status` attribute
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum ChargingStatus {
+    Unknown,
+    Charging,
+    Discharging,
+    /// Plugged in, but not charging (e.g. because it is too hot)
+    NotCharging,
+    Full,
+}
+
+impl FromStr for ChargingStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        Ok(match s {
+            "Unknown" => ChargingStatus::Unknown,
+            "Charging" => ChargingStatus::Charging,
+            "Discharging" => ChargingStatus::Discharging,
+            "Not charging" => ChargingStatus::NotCharging,
+            "Full" => ChargingStatus::Full,
+            _ => return Err(()),
+        })
+    }
+}
+
+/// The `health` attribute
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum BatteryHealth {
+    /// Also used for values added to the kernel after these
+    Unknown,
+    Good,
+    Overheat,
+    Dead,
+    OverVoltage,
+    UnspecifiedFailure,
+    Cold,
+    WatchdogTimerExpire,
+    SafetyTimerExpire,
+    OverCurrent,
+    CalibrationRequired,
+    Warm,
+    Cool,
+    Hot,
+    NoBattery,
+}
+
+impl FromStr for BatteryHealth {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        Ok(match s {
+            "Good" => BatteryHealth::Good,
+            "Overheat" => BatteryHealth::Overheat,
+            "Dead" => BatteryHealth::Dead,
+            "Over voltage" => BatteryHealth::OverVoltage,
+            "Unspecified failure" => BatteryHealth::UnspecifiedFailure,
+            "Cold" => BatteryHealth::Cold,
+            "Watchdog timer expire" => BatteryHealth::WatchdogTimerExpire,
+            "Safety timer expire" => BatteryHealth::SafetyTimerExpire,
+            "Over current" => BatteryHealth::OverCurrent,
+            "Calibration required" => BatteryHealth::CalibrationRequired,
+            "Warm" => BatteryHealth::Warm,
+            "Cool" => BatteryHealth::Cool,
+            "Hot" => BatteryHealth::Hot,
+            "No battery" => BatteryHealth::NoBattery,
+            _ => BatteryHealth::Unknown,
+        })
+    }
+}
+
+/// Everything about the battery, read in one pass. Charges are in µAh, the voltage
+/// in µV and the current in µA (negative while discharging).
+#[derive(PartialEq, Clone, Debug)]
+pub struct BatteryStatus {
+    /// In percent
+    pub capacity: i32,
+    pub level: CapacityLevel,
+    pub status: ChargingStatus,
+    pub charge_now: i32,
+    pub charge_full: i32,
+    pub charge_full_design: i32,
+    pub voltage: i32,
+    pub current: i32,
+    /// In degrees Celsius
+    pub temperature: f32,
+    /// Not every fuel gauge reports these
+    pub health: Option<BatteryHealth>,
+    pub cycle_count: Option<i32>,
+}
+
+/// A battery in the `power_supply` class of sysfs
+#[derive(Clone, Debug)]
+pub struct Battery {
+    /// The directory containing the attributes
+    pub path: PathBuf,
+}
+
+impl Battery {
+    /// The internal battery of the device
+    pub fn internal() -> Battery {
+        Battery::with_sysfs_root("/sys")
+    }
+
+    /// The internal battery, looked up in `root` instead of `/sys` (e.g. a fixture directory)
+    pub fn with_sysfs_root<P: AsRef<Path>>(root: P) -> Battery {
+        Battery::with_sysfs_root_and_name(root, CURRENT_DEVICE.get_internal_battery_name())
+    }
+
+    /// The power supply called `name`, looked up in `root` instead of `/sys`
+    pub fn with_sysfs_root_and_name<P: AsRef<Path>>(root: P, name: &str) -> Battery {
+        Battery::at(root.as_ref().join("class/power_supply").join(name))
+    }
+
+    /// The power supply whose attributes are in `path`
+    pub fn at<P: Into<PathBuf>>(path: P) -> Battery {
+        Battery { path: path.into() }
+    }
+
+    pub fn read_attribute(&self, attribute: &str) -> Result<String, BatteryError> {
+        let data =
+            fs::read_to_string(self.path.join(attribute)).map_err(|error| BatteryError::Read {
+                attribute: attribute.to_owned(),
+                error,
+            })?;
+        match data.trim() {
+            "" => Err(BatteryError::Empty {
+                attribute: attribute.to_owned(),
+            }),
+            data => Ok(data.to_owned()),
+        }
+    }
+
+    fn parse_attribute<T: FromStr>(&self, attribute: &str) -> Result<T, BatteryError> {
+        let value = self.read_attribute(attribute)?;
+        value.parse().map_err(|_| BatteryError::Parse {
+            attribute: attribute.to_owned(),
+            value,
+        })
+    }
+
+    /// `None` if the attribute doesn't exist
+    fn parse_optional<T: FromStr>(&self, attribute: &str) -> Result<Option<T>, BatteryError> {
+        match self.parse_attribute(attribute) {
+            Ok(value) => Ok(Some(value)),
+            Err(BatteryError::Read { ref error, .. })
+                if error.kind() == io::ErrorKind::NotFound =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn status(&self) -> Result<BatteryStatus, BatteryError> {
+        Ok(BatteryStatus {
+            capacity: self.parse_attribute("capacity")?,
+            level: self.parse_attribute("capacity_level")?,
+            status: self.parse_attribute("status")?,
+            charge_now: self.parse_attribute("charge_now")?,
+            charge_full: self.parse_attribute("charge_full")?,
+            charge_full_design: self.parse_attribute("charge_full_design")?,
+            voltage: self.parse_attribute("voltage_now")?,
+            current: self.parse_attribute("current_now")?,
+            temperature: self.parse_attribute::<i32>("temp")? as f32 / 10.0,
+            health: self.parse_optional("health")?,
+            cycle_count: self.parse_optional("cycle_count")?,
+        })
+    }
+}
+
+/// Reads everything about the internal battery
+pub fn status() -> Result<BatteryStatus, BatteryError> {
+    Battery::internal().status()
+}
+
+/// $ cat /sys/class/power_supply/bq27441/capacity
+/// 97
+pub fn percentage() -> Result<i32, BatteryError> {
+    Battery::internal().parse_attribute("capacity")
+}
+
+/// $ cat /sys/class/power_supply/bq27441/capacity_level
+/// Normal
+pub fn human_readable_capacity_level() -> Result<String, BatteryError> {
+    Battery::internal().read_attribute("capacity_level")
+}
+
+/// $ cat /sys/class/power_supply/bq27441/charge_full
+/// 1635000
+pub fn charge_full() -> Result<i32, BatteryError> {
+    Battery::internal().parse_attribute("charge_full")
+}
+
+/// $ cat /sys/class/power_supply/bq27441/charge_full_design
+/// 1340000
+pub fn charge_full_design() -> Result<i32, BatteryError> {
+    Battery::internal().parse_attribute("charge_full_design")
+}
+
+/// $ cat /sys/class/power_supply/bq27441/charge_now
+/// 1528000
+pub fn charge() -> Result<i32, BatteryError> {
+    Battery::internal().parse_attribute("charge_now")
+}
+
+/// $ cat /sys/class/power_supply/bq27441/status
+/// Discharging
+pub fn human_readable_charging_status() -> Result<String, BatteryError> {
+    Battery::internal().read_attribute("status")
+}
+
+/// $ cat /sys/class/power_supply/bq27441/temp
+/// 201
+pub fn temperature() -> Result<i32, BatteryError> {
+    Battery::internal().parse_attribute("temp")
+}
+
+/// $ cat /sys/class/power_supply/bq27441/voltage_now
+/// 4164000
+pub fn voltage() -> Result<i32, BatteryError> {
+    Battery::internal().parse_attribute("voltage_now")
+}
+
+/// $ cat /sys/class/power_supply/bq27441/current_now
+/// -132000
+pub fn current() -> Result<i32, BatteryError> {
+    Battery::internal().parse_attribute("current_now")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fixture(name: &str, attributes: &[(&str, &str)]) -> Battery {
+        let dir = std::env::temp_dir().join(format!(
+            "libremarkable-battery-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (attribute, value) in attributes {
+            fs::write(dir.join(attribute), format!("{value}\n")).unwrap();
+        }
+        Battery::at(dir)
+    }
+
+    const RM2: &[(&str, &str)] = &[
+        ("capacity", "97"),
+        ("capacity_level", "Normal"),
+        ("status", "Not charging"),
+        ("charge_now", "1528000"),
+        ("charge_full", "1635000"),
+        ("charge_full_design", "1340000"),
+        ("voltage_now", "4164000"),
+        ("current_now", "-132000"),
+        ("temp", "201"),
+    ];
+
+    #[test]
+    fn test_status() {
+        let battery = fixture("status", RM2);
+        let status = battery.status().unwrap();
+        assert_eq!(status.capacity, 97);
+        assert_eq!(status.level, CapacityLevel::Normal);
+        assert_eq!(status.status, ChargingStatus::NotCharging);
+        assert_eq!(status.current, -132000);
+        assert!((status.temperature - 20.1).abs() < 1e-4);
+        assert_eq!(status.health, None);
+        assert_eq!(status.cycle_count, None);
+
+        fs::write(battery.path.join("health"), "Good\n").unwrap();
+        fs::write(battery.path.join("cycle_count"), "42\n").unwrap();
+        let status = battery.status().unwrap();
+        assert_eq!(status.health, Some(BatteryHealth::Good));
+        assert_eq!(status.cycle_count, Some(42));
+
+        fs::write(battery.path.join("health"), "Warm\n").unwrap();
+        assert_eq!(battery.status().unwrap().health, Some(BatteryHealth::Warm));
+        // Values the kernel may add later don't make the whole status unreadable
+        fs::write(battery.path.join("health"), "Too shiny\n").unwrap();
+        assert_eq!(
+            battery.status().unwrap().health,
+            Some(BatteryHealth::Unknown)
+        );
+        fs::remove_dir_all(&battery.path).unwrap();
+    }
+
+    #[test]
+    fn test_sysfs_root() {
+        let root =
+            std::env::temp_dir().join(format!("libremarkable-battery-root-{}", std::process::id()));
+        let dir = root.join("class/power_supply/max77818_battery");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("capacity"), "97\n").unwrap();
+
+        let battery = Battery::with_sysfs_root_and_name(&root, "max77818_battery");
+        assert_eq!(battery.path, dir);
+        assert_eq!(battery.parse_attribute::<i32>("capacity").unwrap(), 97);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_errors() {
+        let battery = fixture("errors", RM2);
+        fs::write(battery.path.join("temp"), "warm\n").unwrap();
+        let err = battery.status().unwrap_err();
+        assert!(matches!(err, BatteryError::Parse { ref attribute, .. } if attribute == "temp"));
+        assert!(err.to_string().contains("'temp'"));
+
+        fs::remove_file(battery.path.join("capacity")).unwrap();
+        assert!(matches!(
+            battery.status(),
+            Err(BatteryError::Read { ref attribute, .. }) if attribute == "capacity"
+        ));
+        fs::write(battery.path.join("capacity"), "\n").unwrap();
+        assert!(matches!(battery.status(), Err(BatteryError::Empty { .. })));
+        fs::remove_dir_all(&battery.path).unwrap();
+    }
+}
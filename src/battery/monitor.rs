@@ -0,0 +1,326 @@
+use std::fs;
+use std::io;
+use std::os::unix::prelude::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use log::{debug, error, warn};
+
+use crate::battery::{self, ChargingStatus};
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum BatteryEvent {
+    CapacityChanged {
+        percentage: i32,
+    },
+    ChargerConnected,
+    ChargerDisconnected,
+    /// The `status` attribute changed, e.g. from "Charging" to "Full"
+    StatusChanged {
+        status: ChargingStatus,
+    },
+    /// The capacity dropped to or below one of the `low_thresholds` while not charging
+    Low {
+        percentage: i32,
+        threshold: i32,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct BatteryMonitorConfig {
+    /// Percentages at which `BatteryEvent::Low` is reported
+    pub low_thresholds: Vec<i32>,
+    /// How often sysfs is read when no uevent arrives. Also the only way changes
+    /// are noticed if the uevent socket can't be opened.
+    pub poll_interval: Duration,
+}
+
+impl ::std::default::Default for BatteryMonitorConfig {
+    fn default() -> Self {
+        BatteryMonitorConfig {
+            low_thresholds: vec![20, 10, 5],
+            poll_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+/// The power supply attributes the monitor compares between reads
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+struct PowerSnapshot {
+    percentage: Option<i32>,
+    status: Option<ChargingStatus>,
+    charger_online: bool,
+}
+
+impl PowerSnapshot {
+    fn read() -> PowerSnapshot {
+        PowerSnapshot {
+            percentage: battery::percentage().ok(),
+            status: battery::human_readable_charging_status()
+                .ok()
+                .and_then(|status| status.parse().ok()),
+            charger_online: charger_online(),
+        }
+    }
+
+    /// The events to report for the change from `self` to `next`
+    fn diff(&self, next: &PowerSnapshot, low_thresholds: &[i32]) -> Vec<BatteryEvent> {
+        let mut events = vec![];
+        if next.charger_online != self.charger_online {
+            events.push(match next.charger_online {
+                true => BatteryEvent::ChargerConnected,
+                false => BatteryEvent::ChargerDisconnected,
+            });
+        }
+        if let Some(status) = next.status {
+            if next.status != self.status {
+                events.push(BatteryEvent::StatusChanged { status });
+            }
+        }
+        if let Some(percentage) = next.percentage {
+            if next.percentage != self.percentage {
+                events.push(BatteryEvent::CapacityChanged { percentage });
+                if let Some(previous) = self.percentage {
+                    // Only the lowest threshold crossed is reported
+                    let crossed = low_thresholds
+                        .iter()
+                        .filter(|&&t| previous > t && percentage <= t)
+                        .min();
+                    if let (Some(&threshold), false) = (crossed, next.charger_online) {
+                        events.push(BatteryEvent::Low {
+                            percentage,
+                            threshold,
+                        });
+                    }
+                }
+            }
+        }
+        events
+    }
+}
+
+/// Whether any power supply that isn't a battery (USB, mains) is online
+fn charger_online() -> bool {
+    let supplies = match Path::new("/sys/class/power_supply").read_dir() {
+        Ok(supplies) => supplies,
+        Err(_) => return false,
+    };
+    supplies.flatten().any(|supply| {
+        let path = supply.path();
+        let kind = fs::read_to_string(path.join("type")).unwrap_or_default();
+        let online = fs::read_to_string(path.join("online")).unwrap_or_default();
+        kind.trim() != "Battery" && online.trim() == "1"
+    })
+}
+
+/// Opens a netlink socket receiving the kernel uevents
+fn open_uevent_socket() -> io::Result<OwnedFd> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+            libc::NETLINK_KOBJECT_UEVENT,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+    let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+    addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    addr.nl_groups = 1;
+    let res = unsafe {
+        libc::bind(
+            socket.as_raw_fd(),
+            &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(socket)
+}
+
+/// Reads the pending uevents, returning whether any came from a power supply
+fn drain_uevents(socket: &OwnedFd) -> bool {
+    let mut buf = [0u8; 4096];
+    let mut power_supply = false;
+    loop {
+        let n = unsafe {
+            libc::recv(
+                socket.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                0,
+            )
+        };
+        if n <= 0 {
+            return power_supply;
+        }
+        // NUL separated KEY=value pairs
+        power_supply |= buf[..n as usize]
+            .split(|&b| b == 0)
+            .any(|field| field == b"SUBSYSTEM=power_supply");
+    }
+}
+
+/// Watches the battery and chargers in a thread, calling back with a `BatteryEvent`
+/// for every change. Changes are picked up from the kernel uevents as they happen,
+/// and by reading sysfs every `poll_interval`.
+pub struct BatteryMonitor {
+    wakeup: Arc<OwnedFd>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl BatteryMonitor {
+    pub fn start<F: FnMut(BatteryEvent) + Send + 'static>(
+        config: BatteryMonitorConfig,
+        mut callback: F,
+    ) -> io::Result<BatteryMonitor> {
+        let wakeup = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        if wakeup < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let wakeup = Arc::new(unsafe { OwnedFd::from_raw_fd(wakeup) });
+        let socket = match open_uevent_socket() {
+            Ok(socket) => Some(socket),
+            Err(e) => {
+                warn!("Only polling the battery, no uevents: {}", e);
+                None
+            }
+        };
+
+        let thread_wakeup = Arc::clone(&wakeup);
+        let thread = std::thread::spawn(move || {
+            let mut snapshot = PowerSnapshot::read();
+            // Uevents of other subsystems don't push this back
+            let mut next_poll = Instant::now() + config.poll_interval;
+            loop {
+                let timeout = next_poll
+                    .saturating_duration_since(Instant::now())
+                    .as_millis()
+                    .min(i32::MAX as u128) as i32;
+                let mut fds = [
+                    libc::pollfd {
+                        fd: thread_wakeup.as_raw_fd(),
+                        events: libc::POLLIN,
+                        revents: 0,
+                    },
+                    libc::pollfd {
+                        fd: socket.as_ref().map_or(-1, AsRawFd::as_raw_fd),
+                        events: libc::POLLIN,
+                        revents: 0,
+                    },
+                ];
+                let res = unsafe { libc::poll(fds.as_mut_ptr(), 2, timeout) };
+                if res < 0 {
+                    let err = io::Error::last_os_error();
+                    if err.kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    error!("Battery monitor failed: {}", err);
+                    return;
+                }
+                if fds[0].revents != 0 {
+                    return;
+                }
+                if fds[1].revents != 0 {
+                    match socket {
+                        Some(ref socket) if drain_uevents(socket) => {}
+                        // Some other subsystem
+                        _ => continue,
+                    }
+                }
+
+                next_poll = Instant::now() + config.poll_interval;
+                let next = PowerSnapshot::read();
+                for event in snapshot.diff(&next, &config.low_thresholds) {
+                    debug!("Battery event: {:?}", event);
+                    callback(event);
+                }
+                snapshot = next;
+            }
+        });
+        Ok(BatteryMonitor {
+            wakeup,
+            thread: Some(thread),
+        })
+    }
+
+    /// Stops the thread and waits for it to exit. Also done when dropped.
+    pub fn stop(&mut self) {
+        let one: u64 = 1;
+        unsafe {
+            libc::write(
+                self.wakeup.as_raw_fd(),
+                &one as *const u64 as *const libc::c_void,
+                std::mem::size_of::<u64>(),
+            )
+        };
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("The battery monitor thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for BatteryMonitor {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn snapshot(percentage: i32, status: ChargingStatus, charger_online: bool) -> PowerSnapshot {
+        PowerSnapshot {
+            percentage: Some(percentage),
+            status: Some(status),
+            charger_online,
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let thresholds = [20, 10, 5];
+        let discharging = snapshot(21, ChargingStatus::Discharging, false);
+        assert!(discharging.diff(&discharging, &thresholds).is_empty());
+
+        // Crossing two thresholds at once reports the lower one
+        assert_eq!(
+            discharging.diff(
+                &snapshot(9, ChargingStatus::Discharging, false),
+                &thresholds
+            ),
+            vec![
+                BatteryEvent::CapacityChanged { percentage: 9 },
+                BatteryEvent::Low {
+                    percentage: 9,
+                    threshold: 10
+                }
+            ]
+        );
+
+        assert_eq!(
+            discharging.diff(&snapshot(21, ChargingStatus::Charging, true), &thresholds),
+            vec![
+                BatteryEvent::ChargerConnected,
+                BatteryEvent::StatusChanged {
+                    status: ChargingStatus::Charging
+                }
+            ]
+        );
+        // No low battery warning while charging
+        let charging = snapshot(21, ChargingStatus::Charging, true);
+        assert_eq!(
+            charging.diff(&snapshot(20, ChargingStatus::Charging, true), &thresholds),
+            vec![BatteryEvent::CapacityChanged { percentage: 20 }]
+        );
+    }
+}
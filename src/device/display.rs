@@ -0,0 +1,264 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Why a display power control failed
+#[derive(Debug)]
+pub enum DisplayPowerError {
+    /// The device doesn't have the hardware, e.g. a frontlight on the reMarkable 1 and 2
+    Unsupported(&'static str),
+    IOError(io::Error),
+    /// A sysfs attribute didn't contain what was expected
+    InvalidValue {
+        path: PathBuf,
+        value: String,
+    },
+}
+
+impl From<io::Error> for DisplayPowerError {
+    fn from(err: io::Error) -> Self {
+        DisplayPowerError::IOError(err)
+    }
+}
+
+impl std::fmt::Display for DisplayPowerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisplayPowerError::Unsupported(what) => write!(f, "This device has no {}", what),
+            DisplayPowerError::IOError(err) => err.fmt(f),
+            DisplayPowerError::InvalidValue { path, value } => {
+                write!(f, "Unexpected value {:?} in {}", value, path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for DisplayPowerError {}
+
+pub(crate) fn read_number(path: &Path) -> Result<i64, DisplayPowerError> {
+    let value = fs::read_to_string(path)?;
+    value
+        .trim()
+        .parse()
+        .map_err(|_| DisplayPowerError::InvalidValue {
+            path: path.to_owned(),
+            value,
+        })
+}
+
+/// A frontlight in `/sys/class/backlight`. Frontlights with a color temperature have
+/// a second backlight driving the warm LEDs.
+#[derive(Clone, Debug)]
+pub struct Frontlight {
+    pub path: PathBuf,
+    pub warm_path: Option<PathBuf>,
+}
+
+impl Frontlight {
+    /// Looks for the frontlight in `root`, normally `/sys/class/backlight`
+    pub fn find<P: AsRef<Path>>(root: P) -> Result<Frontlight, DisplayPowerError> {
+        let mut backlights: Vec<PathBuf> = match root.as_ref().read_dir() {
+            Ok(entries) => entries.flatten().map(|e| e.path()).collect(),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e.into()),
+        };
+        backlights.sort();
+        let is_warm = |path: &PathBuf| {
+            path.file_name()
+                .is_some_and(|name| name.to_string_lossy().contains("warm"))
+        };
+        let warm_path = backlights.iter().find(|p| is_warm(p)).cloned();
+        match backlights.into_iter().find(|p| !is_warm(p)) {
+            Some(path) => Ok(Frontlight { path, warm_path }),
+            None => Err(DisplayPowerError::Unsupported("frontlight")),
+        }
+    }
+
+    fn max_brightness_of(path: &Path) -> Result<u32, DisplayPowerError> {
+        Ok(read_number(&path.join("max_brightness"))?.max(1) as u32)
+    }
+
+    /// In the range `0.0..=1.0`
+    pub fn brightness(&self) -> Result<f32, DisplayPowerError> {
+        let max = Frontlight::max_brightness_of(&self.path)?;
+        Ok(read_number(&self.path.join("brightness"))? as f32 / max as f32)
+    }
+
+    /// Clamped to `0.0..=1.0`
+    pub fn set_brightness(&self, brightness: f32) -> Result<(), DisplayPowerError> {
+        let max = Frontlight::max_brightness_of(&self.path)?;
+        let value = (brightness.clamp(0.0, 1.0) * max as f32).round() as u32;
+        fs::write(self.path.join("brightness"), value.to_string())?;
+        Ok(())
+    }
+
+    /// The share of the warm LEDs, from `0.0` (coldest) to `1.0` (warmest)
+    pub fn color_temperature(&self) -> Result<f32, DisplayPowerError> {
+        let warm = self
+            .warm_path
+            .as_ref()
+            .ok_or(DisplayPowerError::Unsupported(
+                "frontlight color temperature",
+            ))?;
+        let max = Frontlight::max_brightness_of(warm)?;
+        Ok(read_number(&warm.join("brightness"))? as f32 / max as f32)
+    }
+
+    /// Clamped to `0.0..=1.0`
+    pub fn set_color_temperature(&self, warmth: f32) -> Result<(), DisplayPowerError> {
+        let warm = self
+            .warm_path
+            .as_ref()
+            .ok_or(DisplayPowerError::Unsupported(
+                "frontlight color temperature",
+            ))?;
+        let max = Frontlight::max_brightness_of(warm)?;
+        let value = (warmth.clamp(0.0, 1.0) * max as f32).round() as u32;
+        fs::write(warm.join("brightness"), value.to_string())?;
+        Ok(())
+    }
+}
+
+/// The raw EPDC powerdown delay in milliseconds. `None` keeps the EPDC powered.
+#[cfg(feature = "framebuffer-types")]
+pub(crate) fn powerdown_delay_to_raw(delay: Option<std::time::Duration>) -> i32 {
+    match delay {
+        Some(delay) => i32::try_from(delay.as_millis()).unwrap_or(i32::MAX),
+        // FB_POWERDOWN_DISABLE
+        None => -1,
+    }
+}
+
+#[cfg(feature = "framebuffer-types")]
+pub(crate) fn powerdown_delay_from_raw(delay: i32) -> Option<std::time::Duration> {
+    u64::try_from(delay)
+        .ok()
+        .map(std::time::Duration::from_millis)
+}
+
+/// Sends an ioctl to the EPDC behind the framebuffer `fd`
+///
+/// # Safety
+///
+/// `arg` must be what the driver expects for `request`
+#[cfg(feature = "framebuffer-types")]
+pub(crate) unsafe fn epdc_ioctl<T>(
+    fd: std::os::unix::io::RawFd,
+    request: crate::framebuffer::common::NativeWidthType,
+    arg: *mut T,
+) -> io::Result<()> {
+    match libc::ioctl(fd, request, arg) {
+        res if res < 0 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Reads the temperature of the first hwmon device named like one of `sensors`, in
+/// degrees Celsius. Each sensor comes with the units its driver reports per degree,
+/// as some report degrees and others millidegrees.
+pub(crate) fn read_hwmon_temperature(
+    root: &Path,
+    sensors: &[(&str, f32)],
+) -> Result<f32, DisplayPowerError> {
+    let entries = match root.read_dir() {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(DisplayPowerError::Unsupported("panel temperature sensor"))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = fs::read_to_string(path.join("name")).unwrap_or_default();
+        if let Some(&(_, per_degree)) = sensors.iter().find(|(n, _)| *n == name.trim()) {
+            return Ok(read_number(&path.join("temp1_input"))? as f32 / per_degree);
+        }
+    }
+    Err(DisplayPowerError::Unsupported("panel temperature sensor"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frontlight() {
+        let root =
+            std::env::temp_dir().join(format!("libremarkable-backlight-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        assert!(matches!(
+            Frontlight::find(&root),
+            Err(DisplayPowerError::Unsupported(_))
+        ));
+
+        for name in ["frontlight", "frontlight_warm"] {
+            fs::create_dir_all(root.join(name)).unwrap();
+            fs::write(root.join(name).join("max_brightness"), "200\n").unwrap();
+            fs::write(root.join(name).join("brightness"), "50\n").unwrap();
+        }
+        let frontlight = Frontlight::find(&root).unwrap();
+        assert_eq!(frontlight.path, root.join("frontlight"));
+        assert_eq!(frontlight.brightness().unwrap(), 0.25);
+        frontlight.set_brightness(2.0).unwrap();
+        assert_eq!(frontlight.brightness().unwrap(), 1.0);
+        frontlight.set_color_temperature(0.5).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("frontlight_warm/brightness")).unwrap(),
+            "100"
+        );
+
+        fs::remove_dir_all(root.join("frontlight_warm")).unwrap();
+        let frontlight = Frontlight::find(&root).unwrap();
+        assert!(matches!(
+            frontlight.color_temperature(),
+            Err(DisplayPowerError::Unsupported(_))
+        ));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_hwmon_temperature() {
+        let root = std::env::temp_dir().join(format!("libremarkable-hwmon-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let sensors = [("max17135", 1.0), ("sy7636a", 1000.0)];
+        assert!(matches!(
+            read_hwmon_temperature(&root, &sensors),
+            Err(DisplayPowerError::Unsupported(_))
+        ));
+
+        for (hwmon, name, value) in [
+            ("hwmon0", "cpu_thermal", "45000"),
+            ("hwmon1", "max17135", "-5"),
+        ] {
+            fs::create_dir_all(root.join(hwmon)).unwrap();
+            fs::write(root.join(hwmon).join("name"), format!("{name}\n")).unwrap();
+            fs::write(root.join(hwmon).join("temp1_input"), format!("{value}\n")).unwrap();
+        }
+        assert_eq!(read_hwmon_temperature(&root, &sensors).unwrap(), -5.0);
+
+        fs::remove_dir_all(root.join("hwmon1")).unwrap();
+        fs::create_dir_all(root.join("hwmon2")).unwrap();
+        fs::write(root.join("hwmon2/name"), "sy7636a\n").unwrap();
+        fs::write(root.join("hwmon2/temp1_input"), "800\n").unwrap();
+        assert_eq!(read_hwmon_temperature(&root, &sensors).unwrap(), 0.8);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(feature = "framebuffer-types")]
+    #[test]
+    fn test_powerdown_delay() {
+        use std::time::Duration;
+
+        assert_eq!(powerdown_delay_to_raw(None), -1);
+        assert_eq!(powerdown_delay_from_raw(-1), None);
+        let delay = Duration::from_millis(500);
+        assert_eq!(
+            powerdown_delay_from_raw(powerdown_delay_to_raw(Some(delay))),
+            Some(delay)
+        );
+        assert_eq!(
+            powerdown_delay_to_raw(Some(Duration::from_secs(u64::MAX))),
+            i32::MAX
+        );
+    }
+}
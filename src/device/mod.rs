@@ -1,6 +1,11 @@
+use cgmath::{Point2, Vector2};
+use display::{DisplayPowerError, Frontlight};
 use rotate::InputDeviceRotation;
+use std::path::Path;
 use std::sync::LazyLock;
 
+/// Frontlight, panel temperature and EPDC power controls
+pub mod display;
 /// Utility for rotating
 pub mod rotate;
 
@@ -89,6 +94,7 @@ pub struct Device {
 /// rotation where the origin (0, 0) is at the top left.
 /// Scaling is not specified here, but Inputs will scale the axis to match the
 /// size of the framebuffer.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct InputDevicePlacement {
     /// What rotation is needed to get it into portrait rotation
     pub rotation: InputDeviceRotation,
@@ -98,6 +104,22 @@ pub struct InputDevicePlacement {
     pub invert_y: bool,
 }
 
+impl InputDevicePlacement {
+    /// Reverts this placement. Takes a point in portrait rotation (not scaled to the
+    /// framebuffer) and returns it as the device of size `orig_size` would report it.
+    pub fn unapply(&self, point: &Point2<u16>, orig_size: &Vector2<u16>) -> Point2<u16> {
+        let size = self.rotation.rotated_size(orig_size);
+        let mut point = *point;
+        if self.invert_x {
+            point.x = size.x - point.x;
+        }
+        if self.invert_y {
+            point.y = size.y - point.y;
+        }
+        self.rotation.inverse().rotate_point(&point, &size)
+    }
+}
+
 impl Device {
     fn new() -> Self {
         let model = Model::current_model()
@@ -141,4 +163,67 @@ impl Device {
     pub fn get_framebuffer_path(&self) -> &'static str {
         self.model.framebuffer_path()
     }
+
+    /// The frontlight, if the device has one
+    pub fn get_frontlight(&self) -> Result<Frontlight, DisplayPowerError> {
+        Frontlight::find("/sys/class/backlight")
+    }
+
+    /// Temperature of the display panel in degrees Celsius, as measured by the EPD
+    /// power management chip
+    pub fn get_panel_temperature(&self) -> Result<f32, DisplayPowerError> {
+        // The max17135 driver reports degrees, the sy7636a one millidegrees
+        let sensors: &[(&str, f32)] = match self.model {
+            Model::Gen1 => &[("max17135", 1.0)],
+            Model::Gen2 => &[("sy7636a_temperature", 1000.0), ("sy7636a", 1000.0)],
+        };
+        display::read_hwmon_temperature(Path::new("/sys/class/hwmon"), sensors)
+    }
+
+    /// How long the EPDC stays powered after the last update. `None` means it never
+    /// powers down. Only available where the EPDC is accessible (reMarkable 1).
+    #[cfg(feature = "framebuffer-types")]
+    pub fn get_epdc_powerdown_delay(
+        &self,
+    ) -> Result<Option<std::time::Duration>, DisplayPowerError> {
+        let mut delay: i32 = 0;
+        self.epdc_ioctl(
+            crate::framebuffer::common::MXCFB_GET_PWRDOWN_DELAY,
+            &mut delay,
+        )?;
+        Ok(display::powerdown_delay_from_raw(delay))
+    }
+
+    /// `None` keeps the EPDC powered. Delays are passed in milliseconds.
+    #[cfg(feature = "framebuffer-types")]
+    pub fn set_epdc_powerdown_delay(
+        &self,
+        delay: Option<std::time::Duration>,
+    ) -> Result<(), DisplayPowerError> {
+        let mut delay = display::powerdown_delay_to_raw(delay);
+        self.epdc_ioctl(
+            crate::framebuffer::common::MXCFB_SET_PWRDOWN_DELAY,
+            &mut delay,
+        )
+    }
+
+    #[cfg(feature = "framebuffer-types")]
+    fn epdc_ioctl(
+        &self,
+        request: crate::framebuffer::common::NativeWidthType,
+        arg: &mut i32,
+    ) -> Result<(), DisplayPowerError> {
+        use std::os::unix::io::AsRawFd;
+
+        if self.model != Model::Gen1 {
+            return Err(DisplayPowerError::Unsupported(
+                "EPDC accessible through ioctls",
+            ));
+        }
+        let fb = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(self.get_framebuffer_path())?;
+        unsafe { display::epdc_ioctl(fb.as_raw_fd(), request, arg) }.map_err(Into::into)
+    }
 }
@@ -1,6 +1,7 @@
 use cgmath::{Point2, Vector2};
 
 /// Describing the rotation of input devices.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum InputDeviceRotation {
     /// When viewing the device in the standard portrait roation,
     /// the origin of this input device is on the top left
@@ -66,6 +67,17 @@ impl InputDeviceRotation {
         unreachable!()
     }
 
+    /// The rotation that undoes this one. It has to be applied with the
+    /// `rotated_size` of the original size.
+    pub fn inverse(&self) -> InputDeviceRotation {
+        match self {
+            InputDeviceRotation::Rot0 => InputDeviceRotation::Rot0,
+            InputDeviceRotation::Rot90 => InputDeviceRotation::Rot270,
+            InputDeviceRotation::Rot180 => InputDeviceRotation::Rot180,
+            InputDeviceRotation::Rot270 => InputDeviceRotation::Rot90,
+        }
+    }
+
     /// Whether based on the original rotation, width and height should be swapped.
     pub fn should_swap_size_axes(&self) -> bool {
         match self {
@@ -105,4 +117,18 @@ mod test {
         );
         assert_eq!(Rot270.rotate_point(&point, &size), Point2 { x: 0, y: 200 });
     }
+
+    #[test]
+    fn check_inverse_rotations() {
+        let point = Point2 { x: 30, y: 70 };
+        let size = Vector2 { x: 200, y: 100 };
+        for rotation in [Rot0, Rot90, Rot180, Rot270] {
+            let rotated = rotation.rotate_point(&point, &size);
+            let rotated_size = rotation.rotated_size(&size);
+            assert_eq!(
+                rotation.inverse().rotate_point(&rotated, &rotated_size),
+                point
+            );
+        }
+    }
 }
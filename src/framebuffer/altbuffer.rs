@@ -0,0 +1,193 @@
+use std::io;
+use std::sync::atomic::Ordering;
+
+use memmap2::{MmapOptions, MmapRaw};
+
+use crate::framebuffer::common::{self, mxcfb_rect};
+use crate::framebuffer::core::{Framebuffer, FramebufferUpdate};
+use crate::framebuffer::mxcfb::{mxcfb_alt_buffer_data, mxcfb_update_data};
+use crate::framebuffer::request::RefreshRequest;
+use crate::framebuffer::{FramebufferIO, FramebufferRefresh, PartialRefreshMode};
+
+/// A back buffer in the video memory behind the visible frame, which the EPDC can
+/// update the display from. Drawing into it with `Framebuffer::draw_to_alt_buffer`
+/// and then updating with `Framebuffer::alt_buffer_refresh` flips the whole content
+/// of a region at once, without showing a half drawn page.
+pub struct AltBuffer {
+    /// Same size and layout as `Framebuffer::frame`
+    frame: MmapRaw,
+    /// The address the EPDC reads the buffer from
+    pub phys_addr: u32,
+    /// Including the padding at the end of the lines
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Framebuffer {
+    /// Maps the video memory right after the visible frame as an `AltBuffer`. Only
+    /// works with the ioctl interface, and only if the video memory has room for a
+    /// second frame. Every call maps the same memory.
+    pub fn allocate_alt_buffer(&self) -> io::Result<AltBuffer> {
+        let device = match &self.framebuffer_update {
+            FramebufferUpdate::Ioctl(device) => device,
+            FramebufferUpdate::Swtfb(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Alternate buffers are not supported by rm2fb",
+                ))
+            }
+        };
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+        let (offset, phys_addr) = alt_buffer_layout(
+            self.frame.len() as u64,
+            page_size,
+            self.fix_screen_info.smem_start,
+            self.fix_screen_info.smem_len,
+        )?;
+        let frame = MmapOptions::new()
+            .offset(offset)
+            .len(self.frame.len())
+            .map_raw(device)?;
+        Ok(AltBuffer {
+            frame,
+            phys_addr,
+            width: self.fix_screen_info.line_length / (self.var_screen_info.bits_per_pixel / 8),
+            height: self.var_screen_info.yres,
+        })
+    }
+
+    /// Runs `draw` with the framebuffer drawing into `alt` instead of the visible frame
+    pub fn draw_to_alt_buffer<R, F: FnOnce(&mut Framebuffer) -> R>(
+        &mut self,
+        alt: &mut AltBuffer,
+        draw: F,
+    ) -> R {
+        let swap = FrameSwap::new(self, alt);
+        draw(swap.fb)
+    }
+
+    /// Updates the region of `request` from the same region of `alt`. Like `refresh`,
+    /// returns the marker or, unless the mode is `Async`, waits. Fails with
+    /// `ErrorKind::Unsupported` on rm2fb.
+    ///
+    /// The visible frame isn't changed, so later updates from it bring back its old
+    /// content. Use `copy_from_alt_buffer` to keep it in sync.
+    pub fn alt_buffer_refresh(&self, alt: &AltBuffer, request: &RefreshRequest) -> io::Result<u32> {
+        let region = request.region.unwrap_or(mxcfb_rect {
+            top: 0,
+            left: 0,
+            height: self.var_screen_info.yres,
+            width: self.var_screen_info.xres,
+        });
+        let flags = request.flags | common::EPDC_FLAG_USE_ALT_BUFFER;
+        let marker = self.marker.fetch_add(1, Ordering::Relaxed);
+        let mut update = mxcfb_update_data {
+            update_mode: match request.full {
+                true => common::update_mode::UPDATE_MODE_FULL as u32,
+                false => common::update_mode::UPDATE_MODE_PARTIAL as u32,
+            },
+            update_marker: marker,
+            waveform_mode: request.waveform_mode as u32,
+            temp: self.resolve_temperature(request.temperature),
+            flags: match request.mode {
+                PartialRefreshMode::DryRun => flags | common::EPDC_FLAG_TEST_COLLISION,
+                _ => flags,
+            },
+            quant_bit: request.quant_bit,
+            dither_mode: request.dither_mode as i32,
+            update_region: region,
+            alt_buffer_data: mxcfb_alt_buffer_data {
+                phys_addr: alt.phys_addr,
+                width: alt.width,
+                height: alt.height,
+                alt_update_region: region,
+            },
+        };
+        self.epdc_ioctl("MXCFB_SEND_UPDATE", common::MXCFB_SEND_UPDATE, &mut update)?;
+        Ok(match request.mode {
+            PartialRefreshMode::Wait | PartialRefreshMode::DryRun => {
+                self.wait_refresh_complete(marker)
+            }
+            PartialRefreshMode::Async => marker,
+        })
+    }
+
+    /// Copies `region` of `alt` into the visible frame
+    pub fn copy_from_alt_buffer(
+        &mut self,
+        alt: &mut AltBuffer,
+        region: mxcfb_rect,
+    ) -> Result<(), &'static str> {
+        let data = self.draw_to_alt_buffer(alt, |fb| fb.dump_region(region))?;
+        self.restore_region(region, &data).map(|_| ())
+    }
+}
+
+/// Swaps the frames of a framebuffer and an `AltBuffer` until dropped, so they are
+/// swapped back even if the drawing code panics
+struct FrameSwap<'a> {
+    fb: &'a mut Framebuffer,
+    alt: &'a mut AltBuffer,
+}
+
+impl<'a> FrameSwap<'a> {
+    fn new(fb: &'a mut Framebuffer, alt: &'a mut AltBuffer) -> Self {
+        std::mem::swap(&mut fb.frame, &mut alt.frame);
+        FrameSwap { fb, alt }
+    }
+}
+
+impl Drop for FrameSwap<'_> {
+    fn drop(&mut self) {
+        std::mem::swap(&mut self.fb.frame, &mut self.alt.frame);
+    }
+}
+
+/// Where an alternate buffer for a frame of `frame_length` bytes goes: the offset
+/// into the video memory, at the first page boundary after the visible frame, and
+/// the physical address of that.
+fn alt_buffer_layout(
+    frame_length: u64,
+    page_size: u64,
+    smem_start: u32,
+    smem_len: u32,
+) -> io::Result<(u64, u32)> {
+    let offset = frame_length.div_ceil(page_size) * page_size;
+    let phys_addr = u32::try_from(offset)
+        .ok()
+        .and_then(|offset| smem_start.checked_add(offset));
+    match phys_addr {
+        Some(phys_addr) if offset + frame_length <= u64::from(smem_len) => Ok((offset, phys_addr)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::OutOfMemory,
+            format!("The video memory of {smem_len} bytes has no room for a second frame"),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_alt_buffer_layout() {
+        // The reMarkable 1 frame of 1408x1872 pixels with 2 bytes each
+        let frame_length = 1408 * 1872 * 2;
+        assert_eq!(
+            alt_buffer_layout(frame_length, 4096, 0x8800_0000, 0x0100_0000).unwrap(),
+            (5_271_552, 0x8800_0000 + 5_271_552)
+        );
+        // Padded to the next page
+        assert_eq!(
+            alt_buffer_layout(5000, 4096, 0x1000, 16384).unwrap(),
+            (8192, 0x1000 + 8192)
+        );
+        assert_eq!(
+            alt_buffer_layout(5000, 4096, 0x1000, 13191)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::OutOfMemory
+        );
+        assert!(alt_buffer_layout(8192, 4096, u32::MAX - 4096, 16384).is_err());
+    }
+}
@@ -27,6 +27,16 @@ pub const MXCFB_SEND_UPDATE: NativeWidthType =
     iow!(b'F', 0x2E, std::mem::size_of::<mxcfb_update_data>()) as NativeWidthType;
 pub const MXCFB_WAIT_FOR_UPDATE_COMPLETE: NativeWidthType =
     iowr!(b'F', 0x2F, std::mem::size_of::<mxcfb_update_marker_data>()) as NativeWidthType;
+pub const MXCFB_SET_WAVEFORM_MODES: NativeWidthType =
+    iow!(b'F', 0x2B, std::mem::size_of::<mxcfb_waveform_modes>()) as NativeWidthType;
+pub const MXCFB_GET_WORK_BUFFER: NativeWidthType =
+    iowr!(b'F', 0x34, std::mem::size_of::<libc::c_ulong>()) as NativeWidthType;
+pub const MXCFB_SET_TEMPERATURE: NativeWidthType =
+    iow!(b'F', 0x2C, std::mem::size_of::<i32>()) as NativeWidthType;
+pub const MXCFB_SET_PWRDOWN_DELAY: NativeWidthType =
+    iow!(b'F', 0x30, std::mem::size_of::<i32>()) as NativeWidthType;
+pub const MXCFB_GET_PWRDOWN_DELAY: NativeWidthType =
+    ior!(b'F', 0x31, std::mem::size_of::<i32>()) as NativeWidthType;
 pub const MXCFB_DISABLE_EPDC_ACCESS: NativeWidthType = io!(b'F', 0x35) as NativeWidthType;
 pub const MXCFB_ENABLE_EPDC_ACCESS: NativeWidthType = io!(b'F', 0x36) as NativeWidthType;
 
@@ -229,6 +239,18 @@ impl mxcfb_rect {
         })
     }
 
+    /// Whether the two rectangles share any pixels. Empty rectangles intersect nothing.
+    pub fn intersects(&self, rect: &mxcfb_rect) -> bool {
+        self.width > 0
+            && self.height > 0
+            && rect.width > 0
+            && rect.height > 0
+            && self.left < rect.left + rect.width
+            && rect.left < self.left + self.width
+            && self.top < rect.top + rect.height
+            && rect.top < self.top + self.height
+    }
+
     pub fn merge_pixel(&self, p: &cgmath::Point2<u32>) -> mxcfb_rect {
         let top = std::cmp::min(self.top, p.y);
         let left = std::cmp::min(self.left, p.x);
@@ -395,7 +417,7 @@ pub enum waveform_mode {
     WAVEFORM_MODE_AUTO = 257,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Default)]
 pub enum display_temp {
     /// Seems to have the best draw latency. Perhaps the rule of thumb here is the lower the faster.
     /// `xochitl` seems to use this value.
@@ -406,4 +428,8 @@ pub enum display_temp {
     TEMP_USE_PAPYRUS = 0x1001,
     /// High draw latency again
     TEMP_USE_MAX = 0xFFFF,
+    /// Resolved by the framebuffer to the value with the best draw latency that is still
+    /// safe for the measured panel temperature. Never passed to the driver as is.
+    #[default]
+    TEMP_AUTO = 0x1_0000,
 }
@@ -6,15 +6,23 @@ use std::os::unix::io::AsRawFd;
 use std::path::Path;
 use std::sync::atomic::AtomicU32;
 
+use log::warn;
+
 use crate::device;
+use crate::device::display;
 use crate::device::Model;
 use crate::framebuffer;
 use crate::framebuffer::common::{
-    FBIOGET_FSCREENINFO, FBIOGET_VSCREENINFO, FBIOPUT_VSCREENINFO, MXCFB_DISABLE_EPDC_ACCESS,
-    MXCFB_ENABLE_EPDC_ACCESS, MXCFB_SET_AUTO_UPDATE_MODE, MXCFB_SET_UPDATE_SCHEME,
+    auto_update_mode, display_temp, update_scheme, NativeWidthType, FBIOGET_FSCREENINFO,
+    FBIOGET_VSCREENINFO, FBIOPUT_VSCREENINFO, MXCFB_DISABLE_EPDC_ACCESS, MXCFB_ENABLE_EPDC_ACCESS,
+    MXCFB_GET_PWRDOWN_DELAY, MXCFB_GET_WORK_BUFFER, MXCFB_SET_AUTO_UPDATE_MODE,
+    MXCFB_SET_PWRDOWN_DELAY, MXCFB_SET_TEMPERATURE, MXCFB_SET_UPDATE_SCHEME,
+    MXCFB_SET_WAVEFORM_MODES,
 };
+use crate::framebuffer::mxcfb::mxcfb_waveform_modes;
 use crate::framebuffer::screeninfo::{FixScreeninfo, VarScreeninfo};
 use crate::framebuffer::swtfb_client::SwtfbClient;
+use crate::framebuffer::temperature::{self, TemperatureSensor};
 use crate::framebuffer::FramebufferBase;
 
 pub enum FramebufferUpdate {
@@ -33,6 +41,8 @@ pub struct Framebuffer {
     pub var_screen_info: VarScreeninfo,
     pub fix_screen_info: FixScreeninfo,
     pub framebuffer_update: FramebufferUpdate,
+    /// Used to resolve `display_temp::TEMP_AUTO`
+    pub temperature_sensor: TemperatureSensor,
 }
 
 unsafe impl Send for Framebuffer {}
@@ -93,6 +103,66 @@ impl Framebuffer {
         }
     }
 
+    /// Re-establishes the connection to the rm2fb server and maps its buffer again,
+    /// keeping the contents of `frame`. Does nothing for the ioctl interface.
+    pub fn reconnect(&mut self) -> std::io::Result<()> {
+        match self.framebuffer_update {
+            FramebufferUpdate::Ioctl(_) => Ok(()),
+            FramebufferUpdate::Swtfb(ref mut swtfb_client) => {
+                swtfb_client.reconnect()?;
+                let frame = swtfb_client.open_buffer()?;
+                let length = self.frame.len().min(frame.len());
+                unsafe {
+                    self.frame
+                        .as_ptr()
+                        .copy_to_nonoverlapping(frame.as_mut_ptr(), length)
+                };
+                self.frame = frame;
+                Ok(())
+            }
+        }
+    }
+
+    /// Sends an ioctl named `name` to the EPDC
+    pub(crate) fn epdc_ioctl<T>(
+        &self,
+        name: &str,
+        request: NativeWidthType,
+        arg: *mut T,
+    ) -> std::io::Result<()> {
+        match &self.framebuffer_update {
+            FramebufferUpdate::Ioctl(device) => unsafe {
+                display::epdc_ioctl(device.as_raw_fd(), request, arg)
+            },
+            FramebufferUpdate::Swtfb(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("{name} is not supported by rm2fb"),
+            )),
+        }
+    }
+
+    /// The temperature of the panel in degrees Celsius, if it can be measured. The
+    /// EPDC is told about every new measurement.
+    pub fn panel_temperature(&self) -> Option<f32> {
+        let (measured, fresh) = self.temperature_sensor.read();
+        if let (Some(measured), true, FramebufferUpdate::Ioctl(_)) =
+            (measured, fresh, &self.framebuffer_update)
+        {
+            if let Err(e) = self.set_temperature(measured.round() as i32) {
+                warn!("Failed to set the EPDC temperature: {}", e);
+            }
+        }
+        measured
+    }
+
+    /// The value to put into an update for `temperature`
+    pub(crate) fn resolve_temperature(&self, temperature: display_temp) -> i32 {
+        match temperature {
+            display_temp::TEMP_AUTO => temperature::auto_temperature(self.panel_temperature()),
+            other => other as i32,
+        }
+    }
+
     fn build(framebuffer_update: FramebufferUpdate) -> Framebuffer {
         let mut var_screen_info = match &framebuffer_update {
             FramebufferUpdate::Ioctl(device) => Framebuffer::get_var_screeninfo(device),
@@ -140,57 +210,72 @@ impl Framebuffer {
             var_screen_info,
             fix_screen_info,
             framebuffer_update,
+            temperature_sensor: TemperatureSensor::default(),
         }
     }
 }
 
 impl framebuffer::FramebufferBase for Framebuffer {
-    fn set_epdc_access(&mut self, state: bool) {
-        match &self.framebuffer_update {
-            FramebufferUpdate::Ioctl(device) => {
-                let request = if state {
-                    MXCFB_ENABLE_EPDC_ACCESS
-                } else {
-                    MXCFB_DISABLE_EPDC_ACCESS
-                };
-                unsafe {
-                    libc::ioctl(device.as_raw_fd(), request);
-                };
-            }
-            FramebufferUpdate::Swtfb(_) => {}
-        }
+    fn set_epdc_access(&mut self, state: bool) -> std::io::Result<()> {
+        let (name, request) = if state {
+            ("MXCFB_ENABLE_EPDC_ACCESS", MXCFB_ENABLE_EPDC_ACCESS)
+        } else {
+            ("MXCFB_DISABLE_EPDC_ACCESS", MXCFB_DISABLE_EPDC_ACCESS)
+        };
+        self.epdc_ioctl(name, request, std::ptr::null_mut::<u8>())
     }
 
-    fn set_autoupdate_mode(&mut self, mode: u32) {
-        match &self.framebuffer_update {
-            FramebufferUpdate::Ioctl(device) => {
-                let m = mode.to_owned();
-                unsafe {
-                    libc::ioctl(
-                        device.as_raw_fd(),
-                        MXCFB_SET_AUTO_UPDATE_MODE,
-                        &m as *const u32,
-                    );
-                };
-            }
-            FramebufferUpdate::Swtfb(_) => {}
-        }
+    fn set_autoupdate_mode(&mut self, mode: auto_update_mode) -> std::io::Result<()> {
+        let mut m = mode as u32;
+        self.epdc_ioctl(
+            "MXCFB_SET_AUTO_UPDATE_MODE",
+            MXCFB_SET_AUTO_UPDATE_MODE,
+            &mut m,
+        )
     }
 
-    fn set_update_scheme(&mut self, scheme: u32) {
-        match &self.framebuffer_update {
-            FramebufferUpdate::Ioctl(device) => {
-                let s = scheme.to_owned();
-                unsafe {
-                    libc::ioctl(
-                        device.as_raw_fd(),
-                        MXCFB_SET_UPDATE_SCHEME,
-                        &s as *const u32,
-                    );
-                };
-            }
-            FramebufferUpdate::Swtfb(_) => {}
-        }
+    fn set_update_scheme(&mut self, scheme: update_scheme) -> std::io::Result<()> {
+        let mut s = scheme as u32;
+        self.epdc_ioctl("MXCFB_SET_UPDATE_SCHEME", MXCFB_SET_UPDATE_SCHEME, &mut s)
+    }
+
+    fn set_waveform_modes(&mut self, modes: &mxcfb_waveform_modes) -> std::io::Result<()> {
+        let mut modes = *modes;
+        self.epdc_ioctl(
+            "MXCFB_SET_WAVEFORM_MODES",
+            MXCFB_SET_WAVEFORM_MODES,
+            &mut modes,
+        )
+    }
+
+    fn set_temperature(&self, temperature: i32) -> std::io::Result<()> {
+        let mut t = temperature;
+        self.epdc_ioctl("MXCFB_SET_TEMPERATURE", MXCFB_SET_TEMPERATURE, &mut t)
+    }
+
+    fn set_powerdown_delay(&mut self, delay: Option<std::time::Duration>) -> std::io::Result<()> {
+        let mut delay = display::powerdown_delay_to_raw(delay);
+        self.epdc_ioctl(
+            "MXCFB_SET_PWRDOWN_DELAY",
+            MXCFB_SET_PWRDOWN_DELAY,
+            &mut delay,
+        )
+    }
+
+    fn get_powerdown_delay(&self) -> std::io::Result<Option<std::time::Duration>> {
+        let mut delay: i32 = 0;
+        self.epdc_ioctl(
+            "MXCFB_GET_PWRDOWN_DELAY",
+            MXCFB_GET_PWRDOWN_DELAY,
+            &mut delay,
+        )?;
+        Ok(display::powerdown_delay_from_raw(delay))
+    }
+
+    fn get_work_buffer(&self) -> std::io::Result<libc::c_ulong> {
+        let mut address: libc::c_ulong = 0;
+        self.epdc_ioctl("MXCFB_GET_WORK_BUFFER", MXCFB_GET_WORK_BUFFER, &mut address)?;
+        Ok(address)
     }
 
     fn get_fix_screeninfo(device: &File) -> FixScreeninfo {
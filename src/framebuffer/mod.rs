@@ -1,5 +1,6 @@
 pub mod common;
 pub mod mxcfb;
+pub mod request;
 pub mod screeninfo;
 
 #[cfg(feature = "framebuffer-storage")]
@@ -11,6 +12,12 @@ pub mod io;
 #[cfg(feature = "framebuffer")]
 pub mod swtfb_client;
 
+#[cfg(feature = "framebuffer")]
+pub mod temperature;
+
+#[cfg(feature = "framebuffer")]
+pub mod altbuffer;
+
 pub use cgmath;
 
 pub trait FramebufferIO {
@@ -121,13 +128,25 @@ pub trait FramebufferDraw {
 
 #[cfg(feature = "framebuffer")]
 pub mod core;
+/// The EPDC controls fail with `ErrorKind::Unsupported` when the display is driven
+/// through rm2fb, as the EPDC isn't reachable that way.
 pub trait FramebufferBase {
     /// Toggles the EPD Controller (see https://wiki.mobileread.com/wiki/EPD_controller)
-    fn set_epdc_access(&mut self, state: bool);
+    fn set_epdc_access(&mut self, state: bool) -> std::io::Result<()>;
     /// Toggles autoupdate mode
-    fn set_autoupdate_mode(&mut self, mode: u32);
+    fn set_autoupdate_mode(&mut self, mode: common::auto_update_mode) -> std::io::Result<()>;
     /// Toggles update scheme
-    fn set_update_scheme(&mut self, scheme: u32);
+    fn set_update_scheme(&mut self, scheme: common::update_scheme) -> std::io::Result<()>;
+    /// Sets the waveform modes the EPDC picks from for the updates it does on its own
+    fn set_waveform_modes(&mut self, modes: &mxcfb::mxcfb_waveform_modes) -> std::io::Result<()>;
+    /// Sets the temperature in degrees Celsius that the EPDC uses for updates with
+    /// `TEMP_USE_AMBIENT`
+    fn set_temperature(&self, temperature: i32) -> std::io::Result<()>;
+    /// How long the EPDC stays powered after the last update, `None` keeping it powered
+    fn set_powerdown_delay(&mut self, delay: Option<std::time::Duration>) -> std::io::Result<()>;
+    fn get_powerdown_delay(&self) -> std::io::Result<Option<std::time::Duration>>;
+    /// The physical address of the working buffer of the EPDC
+    fn get_work_buffer(&self) -> std::io::Result<libc::c_ulong>;
     /// Creates a FixScreeninfo struct and fills it using ioctl
     fn get_fix_screeninfo(device: &std::fs::File) -> screeninfo::FixScreeninfo;
     /// Creates a VarScreeninfo struct and fills it using ioctl
@@ -143,6 +162,7 @@ pub trait FramebufferBase {
     fn update_var_screeninfo(&mut self) -> bool;
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum PartialRefreshMode {
     DryRun,
     Async,
@@ -207,4 +227,8 @@ pub trait FramebufferRefresh {
     /// Returns the collusion_test result which is supposed to be
     /// related to the collusion information.
     fn wait_refresh_complete(&self, marker: u32) -> u32;
+
+    /// Refreshes with all parameters taken from `request`. Like `partial_refresh`,
+    /// returns the marker for `PartialRefreshMode::Async` and waits otherwise.
+    fn refresh(&self, request: &request::RefreshRequest) -> u32;
 }
@@ -48,6 +48,24 @@ impl ::std::default::Default for mxcfb_alt_buffer_data {
     }
 }
 
+/// The waveform modes the EPDC uses for the updates it does on its own
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct mxcfb_waveform_modes {
+    pub mode_init: i32,
+    pub mode_du: i32,
+    pub mode_gc4: i32,
+    pub mode_gc8: i32,
+    pub mode_gc16: i32,
+    pub mode_gc32: i32,
+}
+
+impl ::std::default::Default for mxcfb_waveform_modes {
+    fn default() -> Self {
+        unsafe { ::std::mem::zeroed() }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct mxcfb_update_data {
@@ -7,6 +7,7 @@ use crate::framebuffer;
 use crate::framebuffer::core;
 use crate::framebuffer::core::FramebufferUpdate;
 use crate::framebuffer::mxcfb::*;
+use crate::framebuffer::request::RefreshRequest;
 use crate::framebuffer::{common, PartialRefreshMode};
 
 impl framebuffer::FramebufferRefresh for core::Framebuffer {
@@ -18,42 +19,19 @@ impl framebuffer::FramebufferRefresh for core::Framebuffer {
         quant_bit: i32,
         wait_completion: bool,
     ) -> u32 {
-        let screen = common::mxcfb_rect {
-            top: 0,
-            left: 0,
-            height: self.var_screen_info.yres,
-            width: self.var_screen_info.xres,
-        };
-        let marker = self.marker.fetch_add(1, Ordering::Relaxed);
-        let whole = mxcfb_update_data {
-            update_mode: common::update_mode::UPDATE_MODE_FULL as u32,
-            update_marker: marker,
-            waveform_mode: waveform_mode as u32,
-            temp: temperature as i32,
-            flags: 0,
+        self.refresh(&RefreshRequest {
+            region: None,
+            mode: match wait_completion {
+                true => PartialRefreshMode::Wait,
+                false => PartialRefreshMode::Async,
+            },
+            waveform_mode,
+            temperature,
+            dither_mode,
             quant_bit,
-            dither_mode: dither_mode as i32,
-            update_region: screen,
-            ..Default::default()
-        };
-
-        let update_succeeded = match &self.framebuffer_update {
-            FramebufferUpdate::Ioctl(device) => {
-                let pt: *const mxcfb_update_data = &whole;
-                (unsafe { libc::ioctl(device.as_raw_fd(), common::MXCFB_SEND_UPDATE, pt) }) >= 0
-            }
-            FramebufferUpdate::Swtfb(swtfb_client) => swtfb_client.send_mxcfb_update(&whole),
-        };
-
-        if !update_succeeded {
-            warn!("Sending full_refresh update failed!")
-        }
-
-        if wait_completion {
-            self.wait_refresh_complete(whole.update_marker)
-        } else {
-            whole.update_marker
-        }
+            full: true,
+            flags: 0,
+        })
     }
 
     fn partial_refresh(
@@ -66,7 +44,26 @@ impl framebuffer::FramebufferRefresh for core::Framebuffer {
         quant_bit: i32,
         force_full_refresh: bool,
     ) -> u32 {
-        let mut update_region = region.to_owned();
+        self.refresh(&RefreshRequest {
+            region: Some(*region),
+            mode,
+            waveform_mode,
+            temperature,
+            dither_mode,
+            quant_bit,
+            full: force_full_refresh,
+            flags: 0,
+        })
+    }
+
+    fn refresh(&self, request: &RefreshRequest) -> u32 {
+        let screen = common::mxcfb_rect {
+            top: 0,
+            left: 0,
+            height: self.var_screen_info.yres,
+            width: self.var_screen_info.xres,
+        };
+        let mut update_region = request.region.unwrap_or(screen);
 
         // No accounting for this, out of bounds, entirely ignored
         if update_region.left >= self.var_screen_info.xres
@@ -94,7 +91,7 @@ impl framebuffer::FramebufferRefresh for core::Framebuffer {
             update_region.height -= max_y - self.var_screen_info.yres;
         }
 
-        let update_mode = if force_full_refresh {
+        let update_mode = if request.full {
             common::update_mode::UPDATE_MODE_FULL as u32
         } else {
             common::update_mode::UPDATE_MODE_PARTIAL as u32
@@ -104,14 +101,14 @@ impl framebuffer::FramebufferRefresh for core::Framebuffer {
         let whole = mxcfb_update_data {
             update_mode,
             update_marker: marker,
-            waveform_mode: waveform_mode as u32,
-            temp: temperature as i32,
-            flags: match mode {
-                PartialRefreshMode::DryRun => common::EPDC_FLAG_TEST_COLLISION,
-                _ => 0,
+            waveform_mode: request.waveform_mode as u32,
+            temp: self.resolve_temperature(request.temperature),
+            flags: match request.mode {
+                PartialRefreshMode::DryRun => request.flags | common::EPDC_FLAG_TEST_COLLISION,
+                _ => request.flags,
             },
-            quant_bit,
-            dither_mode: dither_mode as i32,
+            quant_bit: request.quant_bit,
+            dither_mode: request.dither_mode as i32,
             update_region,
             ..Default::default()
         };
@@ -125,10 +122,10 @@ impl framebuffer::FramebufferRefresh for core::Framebuffer {
         };
 
         if !update_succeeded {
-            warn!("Sending partial_refresh update failed!")
+            warn!("Sending refresh update failed!")
         }
 
-        match mode {
+        match request.mode {
             PartialRefreshMode::Wait | PartialRefreshMode::DryRun => {
                 self.wait_refresh_complete(whole.update_marker)
             }
@@ -0,0 +1,162 @@
+use crate::framebuffer::common::{
+    display_temp, dither_mode, mxcfb_rect, waveform_mode, EPDC_FLAG_ENABLE_INVERSION,
+    EPDC_FLAG_FORCE_MONOCHROME, EPDC_FLAG_GROUP_UPDATE,
+};
+use crate::framebuffer::PartialRefreshMode;
+
+/// Common combinations of refresh parameters
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RefreshPreset {
+    /// Medium fidelity, as used for the UI by xochitl
+    Ui,
+    /// Direct update to black or white, for pen strokes
+    FastDraw,
+    /// Full range of grays, waiting for the refresh to complete
+    HighFidelity,
+    /// Flashes the whole screen to clear the ghosting
+    FlashClear,
+    /// Monochrome with the fastest transitions (A2), for animations and page turns
+    Animation,
+}
+
+/// All parameters of a refresh. Start from a preset and change what's needed:
+///
+/// ```no_run
+/// # use libremarkable::framebuffer::request::{RefreshPreset, RefreshRequest};
+/// # use libremarkable::framebuffer::common::mxcfb_rect;
+/// # use libremarkable::framebuffer::PartialRefreshMode;
+/// let request = RefreshRequest::new(RefreshPreset::Ui)
+///     .region(mxcfb_rect { top: 0, left: 0, width: 100, height: 100 })
+///     .mode(PartialRefreshMode::Wait)
+///     .inverted(true);
+/// ```
+///
+/// and pass it to `FramebufferRefresh::refresh`.
+#[derive(Copy, Clone, Debug)]
+pub struct RefreshRequest {
+    /// `None` refreshes the whole screen
+    pub region: Option<mxcfb_rect>,
+    pub mode: PartialRefreshMode,
+    pub waveform_mode: waveform_mode,
+    pub temperature: display_temp,
+    pub dither_mode: dither_mode,
+    pub quant_bit: i32,
+    /// `UPDATE_MODE_FULL` rather than `UPDATE_MODE_PARTIAL`
+    pub full: bool,
+    /// `EPDC_FLAG_*` bits. `EPDC_FLAG_TEST_COLLISION` is set for `PartialRefreshMode::DryRun`.
+    pub flags: u32,
+}
+
+impl RefreshRequest {
+    pub fn new(preset: RefreshPreset) -> RefreshRequest {
+        let request = RefreshRequest {
+            region: None,
+            mode: PartialRefreshMode::Async,
+            waveform_mode: waveform_mode::WAVEFORM_MODE_GC16_FAST,
+            temperature: display_temp::TEMP_USE_REMARKABLE_DRAW,
+            dither_mode: dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
+            quant_bit: 0,
+            full: false,
+            flags: 0,
+        };
+        match preset {
+            RefreshPreset::Ui => request,
+            RefreshPreset::FastDraw => request.waveform(waveform_mode::WAVEFORM_MODE_DU),
+            RefreshPreset::HighFidelity => request
+                .waveform(waveform_mode::WAVEFORM_MODE_GC16)
+                .temperature(display_temp::TEMP_USE_AMBIENT)
+                .mode(PartialRefreshMode::Wait),
+            RefreshPreset::FlashClear => request
+                .waveform(waveform_mode::WAVEFORM_MODE_INIT)
+                .temperature(display_temp::TEMP_USE_AMBIENT)
+                .mode(PartialRefreshMode::Wait)
+                .full(true),
+            RefreshPreset::Animation => request
+                .waveform(waveform_mode::WAVEFORM_MODE_GLR16)
+                .monochrome(true),
+        }
+    }
+
+    pub fn region(mut self, region: mxcfb_rect) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    pub fn whole_screen(mut self) -> Self {
+        self.region = None;
+        self
+    }
+
+    pub fn mode(mut self, mode: PartialRefreshMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn waveform(mut self, waveform_mode: waveform_mode) -> Self {
+        self.waveform_mode = waveform_mode;
+        self
+    }
+
+    pub fn temperature(mut self, temperature: display_temp) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    pub fn dither(mut self, dither_mode: dither_mode) -> Self {
+        self.dither_mode = dither_mode;
+        self
+    }
+
+    pub fn quant_bit(mut self, quant_bit: i32) -> Self {
+        self.quant_bit = quant_bit;
+        self
+    }
+
+    pub fn full(mut self, full: bool) -> Self {
+        self.full = full;
+        self
+    }
+
+    fn flag(mut self, flag: u32, set: bool) -> Self {
+        match set {
+            true => self.flags |= flag,
+            false => self.flags &= !flag,
+        }
+        self
+    }
+
+    /// `EPDC_FLAG_ENABLE_INVERSION`
+    pub fn inverted(self, inverted: bool) -> Self {
+        self.flag(EPDC_FLAG_ENABLE_INVERSION, inverted)
+    }
+
+    /// `EPDC_FLAG_FORCE_MONOCHROME`
+    pub fn monochrome(self, monochrome: bool) -> Self {
+        self.flag(EPDC_FLAG_FORCE_MONOCHROME, monochrome)
+    }
+
+    /// `EPDC_FLAG_GROUP_UPDATE`, letting the EPDC merge it with other grouped updates
+    pub fn group_update(self, group_update: bool) -> Self {
+        self.flag(EPDC_FLAG_GROUP_UPDATE, group_update)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_flags() {
+        let request = RefreshRequest::new(RefreshPreset::Animation).inverted(true);
+        assert_eq!(
+            request.flags,
+            EPDC_FLAG_FORCE_MONOCHROME | EPDC_FLAG_ENABLE_INVERSION
+        );
+        let request = request.monochrome(false).group_update(true);
+        assert_eq!(
+            request.flags,
+            EPDC_FLAG_ENABLE_INVERSION | EPDC_FLAG_GROUP_UPDATE
+        );
+        assert!(RefreshRequest::new(RefreshPreset::FlashClear).full);
+    }
+}
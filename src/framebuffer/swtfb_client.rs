@@ -109,6 +109,21 @@ impl SwtfbClient {
         }
     }
 
+    /// Looks up the message queue again, e.g. after the rm2fb server was restarted
+    pub fn reconnect(&mut self) -> Result<(), IoError> {
+        let msqid = unsafe {
+            libc::msgget(
+                SWTFB_MESSAGE_QUEUE_ID,
+                libc::IPC_CREAT | libc::SHM_R | libc::SHM_W,
+            )
+        };
+        if msqid < 0 {
+            return Err(IoError::last_os_error());
+        }
+        self.msqid = msqid;
+        Ok(())
+    }
+
     pub fn open_buffer(&self) -> Result<MmapRaw, IoError> {
         let device = OpenOptions::new().read(true).write(true).open(&self.path)?;
         #[allow(clippy::cast_lossless)]
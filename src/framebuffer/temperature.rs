@@ -0,0 +1,85 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::device::CURRENT_DEVICE;
+
+/// How long a measurement is reused before the sensors are read again
+pub const MEASUREMENT_LIFETIME: Duration = Duration::from_secs(60);
+/// The temperatures the waveforms of the panel are made for, in degrees Celsius
+pub const PANEL_TEMPERATURE_RANGE: (i32, i32) = (0, 50);
+/// How far the temperature picked for `TEMP_AUTO` may be from the measured one
+pub const AUTO_TOLERANCE: i32 = 5;
+/// `TEMP_USE_REMARKABLE_DRAW`, which has the lowest draw latency
+const BEST_LATENCY: i32 = 0x18;
+
+/// The temperature `display_temp::TEMP_AUTO` resolves to: the best-latency value
+/// within `AUTO_TOLERANCE` of the measured temperature and within the range of the
+/// panel. Without a measurement, the best-latency value itself.
+pub fn auto_temperature(measured: Option<f32>) -> i32 {
+    let (min, max) = PANEL_TEMPERATURE_RANGE;
+    match measured {
+        Some(measured) => {
+            let measured = (measured.round() as i32).clamp(min, max);
+            BEST_LATENCY.clamp(
+                (measured - AUTO_TOLERANCE).max(min),
+                (measured + AUTO_TOLERANCE).min(max),
+            )
+        }
+        None => BEST_LATENCY,
+    }
+}
+
+/// Reads the temperature of the panel from the EPD power management chip, or the
+/// battery temperature as a proxy where that's unavailable. Measurements are cached
+/// for `MEASUREMENT_LIFETIME`.
+#[derive(Default)]
+pub struct TemperatureSensor {
+    measurement: Mutex<Option<(Instant, Option<f32>)>>,
+}
+
+impl TemperatureSensor {
+    /// In degrees Celsius. Returns whether the sensors were read rather than the
+    /// cached value used.
+    pub fn read(&self) -> (Option<f32>, bool) {
+        let mut measurement = self.measurement.lock().unwrap();
+        match *measurement {
+            Some((at, value)) if at.elapsed() < MEASUREMENT_LIFETIME => (value, false),
+            _ => {
+                let value = TemperatureSensor::measure();
+                *measurement = Some((Instant::now(), value));
+                (value, true)
+            }
+        }
+    }
+
+    /// Forgets the cached measurement, e.g. after resuming
+    pub fn invalidate(&self) {
+        *self.measurement.lock().unwrap() = None;
+    }
+
+    fn measure() -> Option<f32> {
+        if let Ok(temperature) = CURRENT_DEVICE.get_panel_temperature() {
+            return Some(temperature);
+        }
+        #[cfg(feature = "battery")]
+        if let Ok(temperature) = crate::battery::temperature() {
+            return Some(temperature as f32 / 10.0);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_auto_temperature() {
+        assert_eq!(auto_temperature(None), BEST_LATENCY);
+        assert_eq!(auto_temperature(Some(22.4)), BEST_LATENCY);
+        // A cold panel needs the slower waveforms
+        assert_eq!(auto_temperature(Some(8.0)), 13);
+        assert_eq!(auto_temperature(Some(35.0)), 30);
+        assert_eq!(auto_temperature(Some(-20.0)), 5);
+    }
+}
@@ -0,0 +1,332 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{LazyLock, RwLock};
+
+use log::warn;
+
+use crate::input::InputDevice;
+
+/// Where the calibration is loaded from on first use
+pub const DEFAULT_CALIBRATION_PATH: &str = "/home/root/.config/libremarkable/calibration";
+
+static CURRENT: LazyLock<RwLock<Calibration>> = LazyLock::new(|| {
+    let calibration = match Calibration::load(DEFAULT_CALIBRATION_PATH) {
+        Ok(calibration) => calibration,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Calibration::default(),
+        Err(e) => {
+            warn!(
+                "Ignoring the calibration in {}: {}",
+                DEFAULT_CALIBRATION_PATH, e
+            );
+            Calibration::default()
+        }
+    };
+    RwLock::new(calibration)
+});
+
+/// The calibration the decoders apply to the positions they report
+pub fn current() -> Calibration {
+    *CURRENT.read().unwrap()
+}
+
+pub fn set_current(calibration: Calibration) {
+    *CURRENT.write().unwrap() = calibration;
+}
+
+/// Maps a position in display coordinates to the corrected one:
+/// `x' = a*x + b*y + c` and `y' = d*x + e*y + f`
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct AffineCalibration {
+    pub a: f32,
+    pub b: f32,
+    pub c: f32,
+    pub d: f32,
+    pub e: f32,
+    pub f: f32,
+}
+
+impl ::std::default::Default for AffineCalibration {
+    fn default() -> Self {
+        AffineCalibration {
+            a: 1.0,
+            b: 0.0,
+            c: 0.0,
+            d: 0.0,
+            e: 1.0,
+            f: 0.0,
+        }
+    }
+}
+
+impl AffineCalibration {
+    pub fn is_identity(&self) -> bool {
+        *self == AffineCalibration::default()
+    }
+
+    pub fn apply(&self, p: cgmath::Point2<f32>) -> cgmath::Point2<f32> {
+        cgmath::Point2 {
+            x: self.a * p.x + self.b * p.y + self.c,
+            y: self.d * p.x + self.e * p.y + self.f,
+        }
+    }
+
+    /// Applies `self` and then `next`
+    pub fn then(&self, next: &AffineCalibration) -> AffineCalibration {
+        AffineCalibration {
+            a: next.a * self.a + next.b * self.d,
+            b: next.a * self.b + next.b * self.e,
+            c: next.a * self.c + next.b * self.f + next.c,
+            d: next.d * self.a + next.e * self.d,
+            e: next.d * self.b + next.e * self.e,
+            f: next.d * self.c + next.e * self.f + next.f,
+        }
+    }
+
+    /// Least squares fit of the model mapping each measured position onto its target.
+    /// Needs at least three points that aren't on one line.
+    pub fn fit(
+        points: &[(cgmath::Point2<f32>, cgmath::Point2<f32>)],
+    ) -> Result<AffineCalibration, String> {
+        if points.len() < 3 {
+            return Err(format!(
+                "Calibrating needs at least 3 points, got {}",
+                points.len()
+            ));
+        }
+        // Normal equations, computed in f64 as the sums get large
+        let mut m = [[0f64; 3]; 3];
+        let mut bx = [0f64; 3];
+        let mut by = [0f64; 3];
+        for (measured, target) in points {
+            let row = [f64::from(measured.x), f64::from(measured.y), 1.0];
+            for i in 0..3 {
+                for j in 0..3 {
+                    m[i][j] += row[i] * row[j];
+                }
+                bx[i] += row[i] * f64::from(target.x);
+                by[i] += row[i] * f64::from(target.y);
+            }
+        }
+        let det = det3(&m);
+        if det.abs() < 1e-6 {
+            return Err("The calibration points are on one line".to_owned());
+        }
+        let solve = |b: &[f64; 3]| -> [f32; 3] {
+            let mut result = [0f32; 3];
+            for (col, r) in result.iter_mut().enumerate() {
+                let mut mc = m;
+                for row in 0..3 {
+                    mc[row][col] = b[row];
+                }
+                *r = (det3(&mc) / det) as f32;
+            }
+            result
+        };
+        let [a, b, c] = solve(&bx);
+        let [d, e, f] = solve(&by);
+        Ok(AffineCalibration { a, b, c, d, e, f })
+    }
+}
+
+fn det3(m: &[[f64; 3]; 3]) -> f64 {
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
+
+/// The corrections of both digitizers, stored as one line per device:
+/// `wacom <a> <b> <c> <d> <e> <f>` and `multitouch <a> <b> <c> <d> <e> <f>`
+#[derive(PartialEq, Copy, Clone, Debug, Default)]
+pub struct Calibration {
+    pub wacom: AffineCalibration,
+    pub multitouch: AffineCalibration,
+}
+
+impl Calibration {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Calibration> {
+        let text = fs::read_to_string(path)?;
+        Calibration::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Writes the calibration to `path`, creating its directory if needed
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        if let Some(dir) = path.as_ref().parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_string())
+    }
+
+    /// Lines starting with `#` are ignored, devices without a line aren't corrected
+    pub fn parse(text: &str) -> Result<Calibration, String> {
+        let mut calibration = Calibration::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let device = parts.next().unwrap();
+            let values = parts
+                .map(str::parse::<f32>)
+                .collect::<Result<Vec<f32>, _>>()
+                .map_err(|e| format!("Line {}: {}", i + 1, e))?;
+            let [a, b, c, d, e, f] = values[..] else {
+                return Err(format!("Line {}: expected 6 values", i + 1));
+            };
+            let model = AffineCalibration { a, b, c, d, e, f };
+            match device {
+                "wacom" => calibration.wacom = model,
+                "multitouch" => calibration.multitouch = model,
+                _ => return Err(format!("Line {}: unknown device {:?}", i + 1, device)),
+            }
+        }
+        Ok(calibration)
+    }
+
+    pub fn for_device(&self, device: InputDevice) -> Option<&AffineCalibration> {
+        match device {
+            InputDevice::Wacom => Some(&self.wacom),
+            InputDevice::Multitouch => Some(&self.multitouch),
+            _ => None,
+        }
+    }
+
+    pub fn for_device_mut(&mut self, device: InputDevice) -> Option<&mut AffineCalibration> {
+        match device {
+            InputDevice::Wacom => Some(&mut self.wacom),
+            InputDevice::Multitouch => Some(&mut self.multitouch),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Calibration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, m) in [("wacom", &self.wacom), ("multitouch", &self.multitouch)] {
+            writeln!(
+                f,
+                "{} {} {} {} {} {} {}",
+                name, m.a, m.b, m.c, m.d, m.e, m.f
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects the positions reported for a series of on-screen targets and turns them
+/// into a new calibration. The positions are the ones reported while the session
+/// runs, so the correction builds on top of the calibration current at its start.
+pub struct CalibrationSession {
+    pub device: InputDevice,
+    targets: Vec<cgmath::Point2<f32>>,
+    measured: Vec<cgmath::Point2<f32>>,
+    previous: AffineCalibration,
+}
+
+impl CalibrationSession {
+    /// Uses targets near the four corners and in the center of a display of `size`
+    pub fn new(device: InputDevice, size: cgmath::Vector2<u16>) -> CalibrationSession {
+        let (w, h) = (f32::from(size.x), f32::from(size.y));
+        let targets = [(0.1, 0.1), (0.9, 0.1), (0.5, 0.5), (0.1, 0.9), (0.9, 0.9)]
+            .into_iter()
+            .map(|(x, y)| cgmath::Point2 { x: w * x, y: h * y })
+            .collect();
+        CalibrationSession::with_targets(device, targets)
+    }
+
+    pub fn with_targets(
+        device: InputDevice,
+        targets: Vec<cgmath::Point2<f32>>,
+    ) -> CalibrationSession {
+        CalibrationSession {
+            device,
+            targets,
+            measured: vec![],
+            previous: current().for_device(device).copied().unwrap_or_default(),
+        }
+    }
+
+    /// The target the user should tap next, if any
+    pub fn current_target(&self) -> Option<cgmath::Point2<f32>> {
+        self.targets.get(self.measured.len()).copied()
+    }
+
+    /// Records the position reported for the current target
+    pub fn record(&mut self, position: cgmath::Point2<f32>) {
+        if self.current_target().is_some() {
+            self.measured.push(position);
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.current_target().is_none()
+    }
+
+    /// The calibration to use for the device from now on
+    pub fn finish(&self) -> Result<AffineCalibration, String> {
+        if !self.is_complete() {
+            return Err("Not all targets were tapped".to_owned());
+        }
+        let points: Vec<_> = self
+            .measured
+            .iter()
+            .copied()
+            .zip(self.targets.iter().copied())
+            .collect();
+        let correction = AffineCalibration::fit(&points)?;
+        Ok(self.previous.then(&correction))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn p(x: f32, y: f32) -> cgmath::Point2<f32> {
+        cgmath::Point2 { x, y }
+    }
+
+    #[test]
+    fn test_fit_recovers_model() {
+        let model = AffineCalibration {
+            a: 1.02,
+            b: 0.01,
+            c: -12.0,
+            d: -0.005,
+            e: 0.98,
+            f: 7.5,
+        };
+        let points: Vec<_> = [
+            p(100.0, 100.0),
+            p(1300.0, 150.0),
+            p(700.0, 900.0),
+            p(150.0, 1700.0),
+        ]
+        .into_iter()
+        .map(|m| (m, model.apply(m)))
+        .collect();
+        let fitted = AffineCalibration::fit(&points).unwrap();
+        let q = fitted.apply(p(400.0, 1200.0));
+        let expected = model.apply(p(400.0, 1200.0));
+        assert!((q.x - expected.x).abs() < 0.05 && (q.y - expected.y).abs() < 0.05);
+
+        let collinear = [p(0.0, 0.0), p(1.0, 1.0), p(2.0, 2.0)].map(|m| (m, m));
+        assert!(AffineCalibration::fit(&collinear).is_err());
+    }
+
+    #[test]
+    fn test_config_roundtrip() {
+        let calibration = Calibration {
+            wacom: AffineCalibration {
+                c: 4.5,
+                ..Default::default()
+            },
+            multitouch: Default::default(),
+        };
+        let text = calibration.to_string();
+        assert_eq!(Calibration::parse(&text).unwrap(), calibration);
+        assert!(Calibration::parse("wacom 1 0 0").is_err());
+        assert!(Calibration::parse("# comment\n\nmultitouch 1 0 0 0 1 0").is_ok());
+    }
+}
@@ -5,11 +5,15 @@
 pub const EV_SYN: u16 = 0x00;
 pub const EV_KEY: u16 = 0x01; // BTN prefixed constants are of type EV_KEY, too
 pub const EV_ABS: u16 = 0x03;
+pub const EV_MSC: u16 = 0x04;
 
 // Syn events seem to be used for syncing and configuring the events themselves
 pub const SYN_REPORT: u16 = 0x00;
 // SYN_CONFIG, MT_REPORT, SYN_DROPPED, SYN_MAX, SYN_CNT
 
+// Misc events (the pen serial number on the Wacom digitizer)
+pub const MSC_SERIAL: u16 = 0x00;
+
 // Absolute Multitouch (Touchpad on reMarkable)
 pub const ABS_MT_SLOT: u16 = 0x2f; // = 47
 pub const ABS_MT_TOUCH_MAJOR: u16 = 0x30; // = 48
@@ -24,6 +28,7 @@ pub const ABS_PRESSURE: u16 = 0x18; // = 24
 pub const ABS_DISTANCE: u16 = 0x19; // = 25
 pub const ABS_TILT_X: u16 = 0x1a; // = 26
 pub const ABS_TILT_Y: u16 = 0x1b; // = 27
+pub const ABS_MISC: u16 = 0x28; // = 40 (the tool ID of the pen)
 pub const ABS_X: u16 = 0x00; // = 0
 pub const ABS_Y: u16 = 0x01; // = 1
 
@@ -1,15 +1,133 @@
 use crate::input;
+use crate::input::keyboard::KeyboardSource;
+use crate::input::record::SharedInputRecorder;
+use crate::input::source::{EvdevSource, InputSource, StopSignal};
 
-use input::scan::SCANNED;
-use log::{error, info, warn};
-use std::os::unix::prelude::AsRawFd;
+use log::{error, warn};
+use std::io;
+use std::os::unix::prelude::{AsRawFd, OwnedFd};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// `_IOW('E', 0x90, int)`, typed like the request argument of `ioctl` in the libc in use
+#[cfg(target_env = "musl")]
+const EVIOCGRAB: libc::c_int = 0x4004_4590;
+#[cfg(not(target_env = "musl"))]
+const EVIOCGRAB: libc::c_ulong = 0x4004_4590;
+
+fn eviocgrab(fd: &OwnedFd, grab: bool) -> io::Result<()> {
+    let res = unsafe { libc::ioctl(fd.as_raw_fd(), EVIOCGRAB, libc::c_int::from(grab)) };
+    if res < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct DeviceFdState {
+    fd: Option<OwnedFd>,
+    grabbed: bool,
+}
+
+/// A duplicate of the fd of the evdev device being read, shared between the reader
+/// and the grabs taken on the device. When the device is reopened, the new fd takes
+/// the place of the old one and is grabbed again if the old one was.
+#[derive(Clone, Default)]
+pub(crate) struct SharedDeviceFd(Arc<Mutex<DeviceFdState>>);
+
+impl SharedDeviceFd {
+    /// Replaces the fd, e.g. after reopening the device
+    pub(crate) fn set(&self, device: input::InputDevice, fd: Option<OwnedFd>) {
+        let mut state = self.0.lock().unwrap();
+        match fd {
+            Some(ref fd) if state.grabbed => {
+                if let Err(e) = eviocgrab(fd, true) {
+                    warn!(
+                        "Failed to grab {:?} again after reopening it: {}",
+                        device, e
+                    );
+                }
+            }
+            _ => {}
+        }
+        state.fd = fd;
+    }
+
+    pub(crate) fn grab(&self, device: input::InputDevice) -> io::Result<InputGrab> {
+        let mut state = self.0.lock().unwrap();
+        match state.fd {
+            Some(ref fd) => eviocgrab(fd, true)?,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    format!("{device:?} is not open"),
+                ))
+            }
+        }
+        state.grabbed = true;
+        Ok(InputGrab {
+            device,
+            fd: Some(self.clone()),
+        })
+    }
+
+    fn ungrab(&self) -> io::Result<()> {
+        let mut state = self.0.lock().unwrap();
+        state.grabbed = false;
+        match state.fd {
+            Some(ref fd) => eviocgrab(fd, false),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Exclusive access to an input device. While it exists, no other process (like xochitl
+/// or a launcher) receives the events of the device. It lasts across the device being
+/// reopened, e.g. after a suspend.
+///
+/// The grab is released when this is dropped, including while unwinding from a panic.
+pub struct InputGrab {
+    device: input::InputDevice,
+    fd: Option<SharedDeviceFd>,
+}
+
+impl InputGrab {
+    pub fn device(&self) -> input::InputDevice {
+        self.device
+    }
+
+    /// Same as dropping the grab, but reports whether releasing it failed
+    pub fn release(mut self) -> io::Result<()> {
+        match self.fd.take() {
+            Some(fd) => fd.ungrab(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for InputGrab {
+    fn drop(&mut self) {
+        if let Some(fd) = self.fd.take() {
+            if let Err(e) = fd.ungrab() {
+                warn!("Failed to release the grab of {:?}: {}", self.device, e);
+            }
+        }
+    }
+}
 
 pub struct EvDevContext {
     device: input::InputDevice,
     pub state: input::InputDeviceState,
     pub tx: std::sync::mpsc::Sender<input::InputEvent>,
+    recorder: SharedInputRecorder,
+    source: Option<Box<dyn InputSource>>,
+    /// The fd of the evdev device being read, if any
+    device_fd: Option<SharedDeviceFd>,
+    /// Hands the source back when it stops, so restarting reuses it
+    thread: Option<JoinHandle<Option<Box<dyn InputSource>>>>,
+    stop_signal: Option<StopSignal>,
     exit_requested: Arc<AtomicBool>,
     exited: Arc<AtomicBool>,
     started: Arc<AtomicBool>,
@@ -24,14 +142,37 @@ impl EvDevContext {
         self.exited.load(Ordering::Relaxed)
     }
 
-    /// After exit is requested, there will be one more event read from the device before
-    /// it is closed.
+    /// Set by `stop()`. Unless the source can't be interrupted, the thread has
+    /// exited by the time `stop()` returns.
     pub fn exit_requested(&self) -> bool {
         self.exit_requested.load(Ordering::Relaxed)
     }
 
+    /// Grabs the evdev device this context reads from. Fails if it was started with
+    /// another `InputSource`, or not started at all.
+    pub fn grab(&self) -> io::Result<InputGrab> {
+        match self.device_fd {
+            Some(ref fd) => fd.grab(self.device),
+            None => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Not reading from an evdev device",
+            )),
+        }
+    }
+
+    /// Stops the thread reading from the source and waits for it to exit. The source
+    /// is kept open, so a later `start()` continues reading from it.
     pub fn stop(&mut self) {
         self.exit_requested.store(true, Ordering::Relaxed);
+        if let Some(stop_signal) = self.stop_signal.take() {
+            stop_signal.raise();
+        }
+        if let Some(thread) = self.thread.take() {
+            match thread.join() {
+                Ok(source) => self.source = source,
+                Err(_) => error!("The input thread of {:?} panicked", self.device),
+            }
+        }
     }
 
     pub fn new(
@@ -42,102 +183,113 @@ impl EvDevContext {
             device,
             tx,
             state: input::InputDeviceState::new(device),
+            recorder: Arc::new(Mutex::new(None)),
+            source: None,
+            device_fd: None,
+            thread: None,
+            stop_signal: None,
             started: Arc::new(AtomicBool::new(false)),
             exit_requested: Arc::new(AtomicBool::new(false)),
             exited: Arc::new(AtomicBool::new(false)),
         }
     }
 
-    /// Non-blocking function that will open the provided path and wait for more data with epoll
-    pub fn start(&mut self) {
-        let path = SCANNED.get_path(self.device);
+    /// Records the raw events read from the device into `recorder` whenever it holds
+    /// an `InputRecorder`. Takes effect on the next `start()`, and only when reading
+    /// from the evdev device.
+    pub fn set_recorder(&mut self, recorder: SharedInputRecorder) {
+        self.recorder = recorder;
+    }
 
+    /// Makes the next `start()` read from `source` instead of the evdev device. The
+    /// events of the source don't have to come from `device`.
+    pub fn set_source(&mut self, source: Box<dyn InputSource>) {
+        self.source = Some(source);
+        self.device_fd = None;
+    }
+
+    /// Non-blocking function that will spawn a thread reading from the `InputSource`.
+    /// Unless one was set with `set_source`, that is the evdev device, which will be
+    /// opened and waited on for more data with epoll.
+    ///
+    /// If reading fails for good, the thread sends an `InputEvent::DeviceError` and exits.
+    pub fn start(&mut self) {
+        if self.thread.is_some() {
+            // Restarting a running context
+            self.stop();
+        }
         self.started.store(true, Ordering::Relaxed);
         self.exited.store(false, Ordering::Relaxed);
         self.exit_requested.store(false, Ordering::Relaxed);
 
-        match SCANNED.get_device(self.device) {
-            Err(e) => error!("Error while reading events from epoll fd: {0}", e),
-            Ok(mut dev) => {
-                let mut v = [epoll::Event {
-                    events: (epoll::Events::EPOLLET
-                        | epoll::Events::EPOLLIN
-                        | epoll::Events::EPOLLPRI)
-                        .bits(),
-                    data: 0,
-                }];
-                let epfd = epoll::create(false).unwrap();
-                epoll::ctl(
-                    epfd,
-                    epoll::ControlOptions::EPOLL_CTL_ADD,
-                    dev.as_raw_fd(),
-                    v[0],
-                )
-                .unwrap();
-
-                // init callback
-                info!("Init complete for {:?}", path);
-
-                let exit_req = Arc::clone(&self.exit_requested);
-                let exited = Arc::clone(&self.exited);
-                let device_type = self.device;
-                let state = self.state.clone();
-                let tx = self.tx.clone();
-                let _ = std::thread::spawn(move || {
-                    while !exit_req.load(Ordering::Relaxed) {
-                        // -1 indefinite wait but it is okay because our EPOLL FD
-                        // is watching on ALL input devices at once.
-                        let res = match epoll::wait(epfd, -1, &mut v[0..1]) {
-                            Ok(res) => res,
-                            Err(err) => {
-                                warn!("epoll_wait failed: {}", err);
-                                continue;
-                            }
-                        };
-                        if res != 1 {
-                            warn!("epoll_wait returned {0}", res);
-                        }
-
-                        for ev in dev.fetch_events().unwrap() {
-                            // event callback
-                            match device_type {
-                                input::InputDevice::Multitouch => {
-                                    for event in input::multitouch::decode(&ev, &state) {
-                                        if let Err(e) = tx.send(event) {
-                                            error!(
-                                                "Failed to write InputEvent into the channel: {}",
-                                                e
-                                            );
-                                        }
-                                    }
-                                }
-                                input::InputDevice::Wacom => {
-                                    if let Some(event) = input::wacom::decode(&ev, &state) {
-                                        if let Err(e) = tx.send(event) {
-                                            error!(
-                                                "Failed to write InputEvent into the channel: {}",
-                                                e
-                                            );
-                                        }
-                                    }
-                                }
-                                input::InputDevice::GPIO => {
-                                    if let Some(event) = input::gpio::decode(&ev, &state) {
-                                        if let Err(e) = tx.send(event) {
-                                            error!(
-                                                "Failed to write InputEvent into the channel: {}",
-                                                e
-                                            );
-                                        }
-                                    }
-                                }
-                                _ => unreachable!(),
-                            };
-                        }
+        let mut source = match self.source.take() {
+            Some(source) => source,
+            None if self.device == input::InputDevice::Keyboard => {
+                match KeyboardSource::open(self.state.clone(), Arc::clone(&self.recorder)) {
+                    Ok(source) => Box::new(source),
+                    Err(e) => {
+                        error!("Error while watching for keyboards: {}", e);
+                        return;
+                    }
+                }
+            }
+            None => {
+                self.device_fd = None;
+                match EvdevSource::open(self.device, self.state.clone(), Arc::clone(&self.recorder))
+                {
+                    Ok(source) => {
+                        self.device_fd = Some(source.device_fd());
+                        Box::new(source)
                     }
-                    exited.store(true, Ordering::Relaxed);
-                });
+                    Err(e) => {
+                        error!("Error while opening {:?}: {}", self.device, e);
+                        return;
+                    }
+                }
             }
+        };
+
+        match StopSignal::new().and_then(|s| source.set_stop_signal(s.clone()).map(|_| s)) {
+            Ok(stop_signal) => self.stop_signal = Some(stop_signal),
+            Err(e) => warn!(
+                "Stopping {:?} will wait for its next event: {}",
+                self.device, e
+            ),
         }
+
+        let device = self.device;
+        let exit_req = Arc::clone(&self.exit_requested);
+        let exited = Arc::clone(&self.exited);
+        let tx = self.tx.clone();
+        self.thread = Some(std::thread::spawn(move || {
+            let source = loop {
+                if exit_req.load(Ordering::Relaxed) {
+                    break Some(source);
+                }
+                let events = match source.read_events() {
+                    Ok(Some(events)) => events,
+                    Ok(None) => break None,
+                    Err(e) => {
+                        error!("Error while reading input events of {:?}: {}", device, e);
+                        let event = input::InputEvent::DeviceError {
+                            device,
+                            error: e.to_string(),
+                            timestamp: std::time::SystemTime::now(),
+                        };
+                        if let Err(e) = tx.send(event) {
+                            error!("Failed to write InputEvent into the channel: {}", e);
+                        }
+                        break None;
+                    }
+                };
+                for event in events {
+                    if let Err(e) = tx.send(event) {
+                        error!("Failed to write InputEvent into the channel: {}", e);
+                    }
+                }
+            };
+            exited.store(true, Ordering::Relaxed);
+            source
+        }));
     }
 }
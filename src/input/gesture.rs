@@ -0,0 +1,592 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::dimensions::{DISPLAYHEIGHT, DISPLAYWIDTH};
+use crate::input::MultitouchEvent;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum SwipeDirection {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Edge {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Gesture {
+    Tap {
+        position: cgmath::Point2<u16>,
+    },
+    /// Reported on the second tap, after the `Tap` for the first one
+    DoubleTap {
+        position: cgmath::Point2<u16>,
+    },
+    LongPress {
+        position: cgmath::Point2<u16>,
+    },
+    /// A single finger moved by `delta` since the last `Pan`
+    Pan {
+        position: cgmath::Point2<u16>,
+        delta: cgmath::Vector2<i32>,
+    },
+    /// The finger of a pan was lifted. Followed by a `Swipe` or `EdgeSwipe` if it was fast enough.
+    PanEnd {
+        position: cgmath::Point2<u16>,
+    },
+    Swipe {
+        direction: SwipeDirection,
+        start: cgmath::Point2<u16>,
+        end: cgmath::Point2<u16>,
+        /// In pixels per second
+        velocity: f32,
+    },
+    /// A swipe that started at `edge` and moved away from it
+    EdgeSwipe {
+        edge: Edge,
+        start: cgmath::Point2<u16>,
+        end: cgmath::Point2<u16>,
+        velocity: f32,
+    },
+    /// Two fingers moved relative to each other. `scale` and `rotation` (in radians,
+    /// clockwise, within [-π, π]) are relative to where the fingers were when the second
+    /// one was pressed.
+    Pinch {
+        center: cgmath::Point2<f32>,
+        scale: f32,
+        rotation: f32,
+    },
+    /// One of the two fingers of a pinch was lifted, or a third finger was pressed
+    PinchEnd,
+}
+
+/// Thresholds used by the `GestureRecognizer`. Distances are in display pixels.
+#[derive(Clone, Debug)]
+pub struct GestureConfig {
+    pub tap_max_duration: Duration,
+    /// How far a finger may move before it is no longer a tap or long press
+    pub tap_max_distance: u16,
+    pub double_tap_max_interval: Duration,
+    pub double_tap_max_distance: u16,
+    pub long_press_duration: Duration,
+    pub swipe_min_distance: u16,
+    /// In pixels per second
+    pub swipe_min_velocity: f32,
+    /// How close to an edge a swipe has to start to be an `EdgeSwipe`
+    pub edge_width: u16,
+    pub screen_size: cgmath::Vector2<u16>,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        GestureConfig {
+            tap_max_duration: Duration::from_millis(300),
+            tap_max_distance: 20,
+            double_tap_max_interval: Duration::from_millis(350),
+            double_tap_max_distance: 50,
+            long_press_duration: Duration::from_millis(600),
+            swipe_min_distance: 150,
+            swipe_min_velocity: 400.0,
+            edge_width: 60,
+            screen_size: cgmath::Vector2 {
+                x: DISPLAYWIDTH,
+                y: DISPLAYHEIGHT,
+            },
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Track {
+    start: cgmath::Point2<u16>,
+    last: cgmath::Point2<u16>,
+    pressed_at: Instant,
+}
+
+struct PinchBase {
+    ids: (i32, i32),
+    distance: f32,
+    angle: f32,
+}
+
+/// Consumes `MultitouchEvent`s and emits the `Gesture`s they make up.
+///
+/// `ApplicationContext::enable_gestures(..)` runs one of these in its event loop, but it
+/// can be used on its own by feeding it every multitouch event along with the time it
+/// was received, plus calling `expire` to get long presses without further input.
+pub struct GestureRecognizer {
+    pub config: GestureConfig,
+    tracks: HashMap<i32, Track>,
+    /// Set once more than one finger took part, until all fingers are lifted again
+    multi_finger: bool,
+    panning: bool,
+    long_pressed: bool,
+    pinch: Option<PinchBase>,
+    last_tap: Option<(cgmath::Point2<u16>, Instant)>,
+}
+
+impl Default for GestureRecognizer {
+    fn default() -> Self {
+        GestureRecognizer::new(GestureConfig::default())
+    }
+}
+
+fn distance(a: cgmath::Point2<u16>, b: cgmath::Point2<u16>) -> f32 {
+    let dx = f32::from(a.x) - f32::from(b.x);
+    let dy = f32::from(a.y) - f32::from(b.y);
+    (dx * dx + dy * dy).sqrt()
+}
+
+fn angle(a: cgmath::Point2<u16>, b: cgmath::Point2<u16>) -> f32 {
+    (f32::from(b.y) - f32::from(a.y)).atan2(f32::from(b.x) - f32::from(a.x))
+}
+
+/// Wraps a difference of two angles into [-π, π]
+fn wrap_angle(angle: f32) -> f32 {
+    use std::f32::consts::PI;
+    (angle + PI).rem_euclid(2.0 * PI) - PI
+}
+
+impl GestureRecognizer {
+    pub fn new(config: GestureConfig) -> GestureRecognizer {
+        GestureRecognizer {
+            config,
+            tracks: HashMap::new(),
+            multi_finger: false,
+            panning: false,
+            long_pressed: false,
+            pinch: None,
+            last_tap: None,
+        }
+    }
+
+    pub fn process(&mut self, event: &MultitouchEvent, now: Instant) -> Vec<Gesture> {
+        let mut gestures = self.expire(now);
+        match event {
+            MultitouchEvent::Press { finger } => {
+                self.tracks.insert(
+                    finger.tracking_id,
+                    Track {
+                        start: finger.pos,
+                        last: finger.pos,
+                        pressed_at: now,
+                    },
+                );
+                if self.tracks.len() > 1 {
+                    if self.panning {
+                        // Only a single finger pans, so it's the one that was down before
+                        let panned = self
+                            .tracks
+                            .iter()
+                            .find(|(&id, _)| id != finger.tracking_id)
+                            .map(|(_, track)| track.last);
+                        gestures.extend(panned.map(|position| Gesture::PanEnd { position }));
+                    }
+                    self.multi_finger = true;
+                    self.panning = false;
+                    gestures.extend(self.start_pinch());
+                }
+            }
+            MultitouchEvent::Move { finger } => {
+                let track = match self.tracks.get_mut(&finger.tracking_id) {
+                    Some(track) => track,
+                    None => return gestures,
+                };
+                let previous = track.last;
+                track.last = finger.pos;
+                let track = *track;
+
+                if let Some(ref pinch) = self.pinch {
+                    if let (Some(a), Some(b)) =
+                        (self.tracks.get(&pinch.ids.0), self.tracks.get(&pinch.ids.1))
+                    {
+                        gestures.push(Gesture::Pinch {
+                            center: cgmath::Point2 {
+                                x: (f32::from(a.last.x) + f32::from(b.last.x)) / 2.0,
+                                y: (f32::from(a.last.y) + f32::from(b.last.y)) / 2.0,
+                            },
+                            scale: distance(a.last, b.last) / pinch.distance.max(1.0),
+                            rotation: wrap_angle(angle(a.last, b.last) - pinch.angle),
+                        });
+                    }
+                } else if !self.multi_finger && !self.long_pressed {
+                    if !self.panning
+                        && distance(track.start, track.last)
+                            > f32::from(self.config.tap_max_distance)
+                    {
+                        self.panning = true;
+                    }
+                    if self.panning && previous != track.last {
+                        gestures.push(Gesture::Pan {
+                            position: track.last,
+                            delta: track.last.cast::<i32>().unwrap()
+                                - previous.cast::<i32>().unwrap(),
+                        });
+                    }
+                }
+            }
+            MultitouchEvent::Release { finger } => {
+                if let Some(track) = self.tracks.remove(&finger.tracking_id) {
+                    if let Some(ref pinch) = self.pinch {
+                        if pinch.ids.0 == finger.tracking_id || pinch.ids.1 == finger.tracking_id {
+                            self.pinch = None;
+                            gestures.push(Gesture::PinchEnd);
+                        }
+                    } else if !self.multi_finger {
+                        gestures.extend(self.single_finger_release(&track, now));
+                    }
+                }
+                if self.tracks.is_empty() {
+                    self.multi_finger = false;
+                    self.panning = false;
+                    self.long_pressed = false;
+                    self.pinch = None;
+                }
+            }
+            MultitouchEvent::Unknown => {}
+        }
+        gestures
+    }
+
+    /// The next point in time at which `expire` may produce a `LongPress`
+    pub fn next_deadline(&self) -> Option<Instant> {
+        if self.multi_finger || self.panning || self.long_pressed {
+            return None;
+        }
+        self.tracks
+            .values()
+            .next()
+            .map(|t| t.pressed_at + self.config.long_press_duration)
+    }
+
+    /// Emits a `LongPress` if a single finger has been held still for long enough.
+    pub fn expire(&mut self, now: Instant) -> Vec<Gesture> {
+        match self.next_deadline() {
+            Some(deadline) if deadline <= now => {
+                self.long_pressed = true;
+                // There is exactly one track, otherwise there would be no deadline
+                let track = self.tracks.values().next().unwrap();
+                vec![Gesture::LongPress {
+                    position: track.last,
+                }]
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Starts tracking a pinch between the two fingers that are down. Returns the
+    /// `PinchEnd` of the pinch it replaces, if there was one.
+    fn start_pinch(&mut self) -> Option<Gesture> {
+        let mut ids: Vec<i32> = self.tracks.keys().copied().collect();
+        ids.sort_unstable();
+        let previous = self.pinch.take();
+        self.pinch = match ids[..] {
+            [a, b] => {
+                let (ta, tb) = (self.tracks[&a], self.tracks[&b]);
+                Some(PinchBase {
+                    ids: (a, b),
+                    distance: distance(ta.last, tb.last),
+                    angle: angle(ta.last, tb.last),
+                })
+            }
+            // Three or more fingers aren't a pinch
+            _ => None,
+        };
+        previous.map(|_| Gesture::PinchEnd)
+    }
+
+    fn single_finger_release(&mut self, track: &Track, now: Instant) -> Vec<Gesture> {
+        let elapsed = now - track.pressed_at;
+        if self.long_pressed {
+            return vec![];
+        }
+
+        if self.panning {
+            let mut gestures = vec![Gesture::PanEnd {
+                position: track.last,
+            }];
+            let travelled = distance(track.start, track.last);
+            let velocity = travelled / elapsed.as_secs_f32().max(0.001);
+            if travelled >= f32::from(self.config.swipe_min_distance)
+                && velocity >= self.config.swipe_min_velocity
+            {
+                gestures.push(self.swipe(track, velocity));
+            }
+            return gestures;
+        }
+
+        if elapsed > self.config.tap_max_duration {
+            return vec![];
+        }
+        if let Some((position, at)) = self.last_tap.take() {
+            if now - at <= self.config.double_tap_max_interval
+                && distance(position, track.last) <= f32::from(self.config.double_tap_max_distance)
+            {
+                return vec![Gesture::DoubleTap {
+                    position: track.last,
+                }];
+            }
+        }
+        self.last_tap = Some((track.last, now));
+        vec![Gesture::Tap {
+            position: track.last,
+        }]
+    }
+
+    fn swipe(&self, track: &Track, velocity: f32) -> Gesture {
+        let dx = i32::from(track.last.x) - i32::from(track.start.x);
+        let dy = i32::from(track.last.y) - i32::from(track.start.y);
+        let direction = if dx.abs() >= dy.abs() {
+            if dx > 0 {
+                SwipeDirection::Right
+            } else {
+                SwipeDirection::Left
+            }
+        } else if dy > 0 {
+            SwipeDirection::Down
+        } else {
+            SwipeDirection::Up
+        };
+
+        let edge_width = self.config.edge_width;
+        let size = self.config.screen_size;
+        let edge = match direction {
+            SwipeDirection::Right if track.start.x < edge_width => Some(Edge::Left),
+            SwipeDirection::Left if track.start.x >= size.x.saturating_sub(edge_width) => {
+                Some(Edge::Right)
+            }
+            SwipeDirection::Down if track.start.y < edge_width => Some(Edge::Top),
+            SwipeDirection::Up if track.start.y >= size.y.saturating_sub(edge_width) => {
+                Some(Edge::Bottom)
+            }
+            _ => None,
+        };
+
+        match edge {
+            Some(edge) => Gesture::EdgeSwipe {
+                edge,
+                start: track.start,
+                end: track.last,
+                velocity,
+            },
+            None => Gesture::Swipe {
+                direction,
+                start: track.start,
+                end: track.last,
+                velocity,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::Finger;
+
+    fn finger(tracking_id: i32, x: u16, y: u16) -> Finger {
+        Finger {
+            tracking_id,
+            pos: cgmath::Point2 { x, y },
+            pressed: true,
+            ..Default::default()
+        }
+    }
+
+    fn ms(start: Instant, millis: u64) -> Instant {
+        start + Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn test_tap_and_double_tap() {
+        let mut r = GestureRecognizer::default();
+        let t = Instant::now();
+        let f = finger(1, 500, 500);
+        r.process(&MultitouchEvent::Press { finger: f }, t);
+        let g = r.process(&MultitouchEvent::Release { finger: f }, ms(t, 80));
+        assert_eq!(g, vec![Gesture::Tap { position: f.pos }]);
+
+        let f = finger(2, 510, 495);
+        r.process(&MultitouchEvent::Press { finger: f }, ms(t, 200));
+        let g = r.process(&MultitouchEvent::Release { finger: f }, ms(t, 260));
+        assert_eq!(g, vec![Gesture::DoubleTap { position: f.pos }]);
+    }
+
+    #[test]
+    fn test_long_press_suppresses_tap() {
+        let mut r = GestureRecognizer::default();
+        let t = Instant::now();
+        let f = finger(1, 500, 500);
+        r.process(&MultitouchEvent::Press { finger: f }, t);
+        assert_eq!(r.next_deadline(), Some(ms(t, 600)));
+        assert_eq!(
+            r.expire(ms(t, 700)),
+            vec![Gesture::LongPress { position: f.pos }]
+        );
+        assert!(r
+            .process(&MultitouchEvent::Release { finger: f }, ms(t, 900))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_pan_into_edge_swipe() {
+        let mut r = GestureRecognizer::default();
+        let t = Instant::now();
+        r.process(
+            &MultitouchEvent::Press {
+                finger: finger(1, 10, 900),
+            },
+            t,
+        );
+        let g = r.process(
+            &MultitouchEvent::Move {
+                finger: finger(1, 200, 910),
+            },
+            ms(t, 100),
+        );
+        assert_eq!(
+            g,
+            vec![Gesture::Pan {
+                position: cgmath::Point2 { x: 200, y: 910 },
+                delta: cgmath::Vector2 { x: 190, y: 10 },
+            }]
+        );
+        let g = r.process(
+            &MultitouchEvent::Release {
+                finger: finger(1, 200, 910),
+            },
+            ms(t, 200),
+        );
+        assert_eq!(g.len(), 2);
+        assert_eq!(
+            g[0],
+            Gesture::PanEnd {
+                position: cgmath::Point2 { x: 200, y: 910 }
+            }
+        );
+        match g[1] {
+            Gesture::EdgeSwipe {
+                edge: Edge::Left,
+                velocity,
+                ..
+            } => assert!((velocity - 951.3).abs() < 1.0),
+            other => panic!("Expected an edge swipe, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_second_finger_ends_pan_where_the_first_one_is() {
+        let mut r = GestureRecognizer::default();
+        let t = Instant::now();
+        r.process(
+            &MultitouchEvent::Press {
+                finger: finger(1, 500, 500),
+            },
+            t,
+        );
+        r.process(
+            &MultitouchEvent::Move {
+                finger: finger(1, 600, 500),
+            },
+            ms(t, 50),
+        );
+        let g = r.process(
+            &MultitouchEvent::Press {
+                finger: finger(2, 100, 1200),
+            },
+            ms(t, 100),
+        );
+        assert_eq!(
+            g,
+            vec![Gesture::PanEnd {
+                position: cgmath::Point2 { x: 600, y: 500 }
+            }]
+        );
+    }
+
+    #[test]
+    fn test_pinch_scale_and_rotation() {
+        let mut r = GestureRecognizer::default();
+        let t = Instant::now();
+        r.process(
+            &MultitouchEvent::Press {
+                finger: finger(1, 400, 500),
+            },
+            t,
+        );
+        r.process(
+            &MultitouchEvent::Press {
+                finger: finger(2, 600, 500),
+            },
+            t,
+        );
+        let g = r.process(
+            &MultitouchEvent::Move {
+                finger: finger(2, 500, 700),
+            },
+            ms(t, 50),
+        );
+        match g[..] {
+            [Gesture::Pinch {
+                center,
+                scale,
+                rotation,
+            }] => {
+                assert_eq!(center, cgmath::Point2 { x: 450.0, y: 600.0 });
+                assert!((scale - 1.118).abs() < 0.001);
+                assert!((rotation - 1.107).abs() < 0.001);
+            }
+            ref other => panic!("Expected a pinch, got {:?}", other),
+        }
+        let g = r.process(
+            &MultitouchEvent::Release {
+                finger: finger(1, 400, 500),
+            },
+            ms(t, 80),
+        );
+        assert_eq!(g, vec![Gesture::PinchEnd]);
+    }
+
+    #[test]
+    fn test_pinch_rotation_wraps_and_third_finger_ends_it() {
+        let mut r = GestureRecognizer::default();
+        let t = Instant::now();
+        r.process(
+            &MultitouchEvent::Press {
+                finger: finger(1, 500, 500),
+            },
+            t,
+        );
+        r.process(
+            &MultitouchEvent::Press {
+                finger: finger(2, 400, 510),
+            },
+            t,
+        );
+        // Crossing the negative x axis turns the raw angle from about π into about -π
+        let g = r.process(
+            &MultitouchEvent::Move {
+                finger: finger(2, 400, 490),
+            },
+            ms(t, 50),
+        );
+        match g[..] {
+            [Gesture::Pinch { rotation, .. }] => assert!((rotation - 0.199).abs() < 0.001),
+            ref other => panic!("Expected a pinch, got {:?}", other),
+        }
+        let g = r.process(
+            &MultitouchEvent::Press {
+                finger: finger(3, 800, 800),
+            },
+            ms(t, 80),
+        );
+        assert_eq!(g, vec![Gesture::PinchEnd]);
+    }
+}
@@ -62,7 +62,10 @@ pub fn decode(ev: &EvInputEvent, outer_state: &InputDeviceState) -> Option<Input
             } else {
                 GPIOEvent::Unpress { button: p }
             };
-            Some(InputEvent::GPIO { event })
+            Some(InputEvent::GPIO {
+                event,
+                timestamp: ev.timestamp(),
+            })
         }
         _ => {
             // Shouldn't happen
@@ -0,0 +1,513 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::prelude::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+
+use evdev::{InputEvent as EvInputEvent, Key};
+use log::{debug, info, warn};
+
+use super::ecodes;
+use crate::input::record::SharedInputRecorder;
+use crate::input::scan::is_keyboard;
+use crate::input::source::{InputSource, StopSignal, STOP_TOKEN};
+use crate::input::{
+    InputDevice, InputDeviceState, InputEvent, KeyModifiers, KeyState, KeyboardEvent,
+};
+
+/// The characters a key produces without modifiers, with shift, and with AltGr
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeymapEntry {
+    pub base: char,
+    pub shifted: char,
+    pub altgr: Option<char>,
+}
+
+/// Maps evdev keycodes to text, similar to a (much simplified) xkb layout.
+///
+/// Caps lock applies to the keys whose `base` character is alphabetic.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keymap {
+    pub entries: HashMap<u16, KeymapEntry>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::us()
+    }
+}
+
+fn parse_char(token: &str) -> Option<char> {
+    match token.strip_prefix("U+") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+        None => {
+            let mut chars = token.chars();
+            let c = chars.next()?;
+            chars.next().is_none().then_some(c)
+        }
+    }
+}
+
+impl Keymap {
+    /// The US QWERTY layout
+    pub fn us() -> Keymap {
+        let rows: [(&[Key], &str, &str); 4] = [
+            (
+                &[
+                    Key::KEY_GRAVE,
+                    Key::KEY_1,
+                    Key::KEY_2,
+                    Key::KEY_3,
+                    Key::KEY_4,
+                    Key::KEY_5,
+                    Key::KEY_6,
+                    Key::KEY_7,
+                    Key::KEY_8,
+                    Key::KEY_9,
+                    Key::KEY_0,
+                    Key::KEY_MINUS,
+                    Key::KEY_EQUAL,
+                ],
+                "`1234567890-=",
+                "~!@#$%^&*()_+",
+            ),
+            (
+                &[
+                    Key::KEY_Q,
+                    Key::KEY_W,
+                    Key::KEY_E,
+                    Key::KEY_R,
+                    Key::KEY_T,
+                    Key::KEY_Y,
+                    Key::KEY_U,
+                    Key::KEY_I,
+                    Key::KEY_O,
+                    Key::KEY_P,
+                    Key::KEY_LEFTBRACE,
+                    Key::KEY_RIGHTBRACE,
+                    Key::KEY_BACKSLASH,
+                ],
+                "qwertyuiop[]\\",
+                "QWERTYUIOP{}|",
+            ),
+            (
+                &[
+                    Key::KEY_A,
+                    Key::KEY_S,
+                    Key::KEY_D,
+                    Key::KEY_F,
+                    Key::KEY_G,
+                    Key::KEY_H,
+                    Key::KEY_J,
+                    Key::KEY_K,
+                    Key::KEY_L,
+                    Key::KEY_SEMICOLON,
+                    Key::KEY_APOSTROPHE,
+                ],
+                "asdfghjkl;'",
+                "ASDFGHJKL:\"",
+            ),
+            (
+                &[
+                    Key::KEY_Z,
+                    Key::KEY_X,
+                    Key::KEY_C,
+                    Key::KEY_V,
+                    Key::KEY_B,
+                    Key::KEY_N,
+                    Key::KEY_M,
+                    Key::KEY_COMMA,
+                    Key::KEY_DOT,
+                    Key::KEY_SLASH,
+                ],
+                "zxcvbnm,./",
+                "ZXCVBNM<>?",
+            ),
+        ];
+
+        let mut entries = HashMap::new();
+        for (keys, base, shifted) in rows {
+            for ((key, base), shifted) in keys.iter().zip(base.chars()).zip(shifted.chars()) {
+                entries.insert(
+                    key.code(),
+                    KeymapEntry {
+                        base,
+                        shifted,
+                        altgr: None,
+                    },
+                );
+            }
+        }
+        for (key, c) in [
+            (Key::KEY_SPACE, ' '),
+            (Key::KEY_TAB, '\t'),
+            (Key::KEY_ENTER, '\n'),
+            (Key::KEY_KPENTER, '\n'),
+        ] {
+            entries.insert(
+                key.code(),
+                KeymapEntry {
+                    base: c,
+                    shifted: c,
+                    altgr: None,
+                },
+            );
+        }
+        Keymap { entries }
+    }
+
+    /// Parses a layout with one key per line, written as
+    /// `<keycode> <base> <shifted> [<altgr>]`. Characters are either given
+    /// literally or as `U+XXXX`. Empty lines and lines starting with `#` are skipped.
+    /// The entries are applied on top of `base`.
+    pub fn parse(base: Keymap, layout: &str) -> Result<Keymap, String> {
+        let mut keymap = base;
+        for (i, line) in layout.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let invalid = || format!("Invalid keymap entry on line {}: '{}'", i + 1, line);
+            if tokens.len() < 3 || tokens.len() > 4 {
+                return Err(invalid());
+            }
+            let keycode = tokens[0].parse::<u16>().map_err(|_| invalid())?;
+            let base = parse_char(tokens[1]).ok_or_else(invalid)?;
+            let shifted = parse_char(tokens[2]).ok_or_else(invalid)?;
+            let altgr = match tokens.get(3) {
+                Some(token) => Some(parse_char(token).ok_or_else(invalid)?),
+                None => None,
+            };
+            keymap.entries.insert(
+                keycode,
+                KeymapEntry {
+                    base,
+                    shifted,
+                    altgr,
+                },
+            );
+        }
+        Ok(keymap)
+    }
+
+    /// The text typed by `key` with `modifiers` held. Nothing is typed while
+    /// ctrl, alt or meta are held, as those are shortcuts.
+    pub fn text(&self, key: u16, modifiers: &KeyModifiers) -> Option<char> {
+        if modifiers.ctrl || modifiers.alt || modifiers.meta {
+            return None;
+        }
+        let entry = self.entries.get(&key)?;
+        if modifiers.altgr {
+            return entry.altgr;
+        }
+        let shift = modifiers.shift ^ (modifiers.caps_lock && entry.base.is_alphabetic());
+        Some(if shift { entry.shifted } else { entry.base })
+    }
+}
+
+pub struct KeyboardState {
+    modifiers: Mutex<KeyModifiers>,
+    pub keymap: RwLock<Keymap>,
+}
+
+impl ::std::default::Default for KeyboardState {
+    fn default() -> Self {
+        KeyboardState {
+            modifiers: Mutex::new(KeyModifiers::default()),
+            keymap: RwLock::new(Keymap::us()),
+        }
+    }
+}
+
+impl KeyboardState {
+    /// Forgets the held modifiers, e.g. because the keyboard holding them went away
+    fn reset_modifiers(&self) {
+        *self.modifiers.lock().unwrap() = KeyModifiers::default();
+    }
+}
+
+pub fn decode(ev: &EvInputEvent, outer_state: &InputDeviceState) -> Option<InputEvent> {
+    let state = match outer_state {
+        InputDeviceState::KeyboardState(ref state_arc) => state_arc,
+        _ => unreachable!(),
+    };
+    if ev.event_type().0 != ecodes::EV_KEY {
+        return None;
+    }
+    let key = ev.code();
+    let key_state = match ev.value() {
+        0 => KeyState::Release,
+        1 => KeyState::Press,
+        2 => KeyState::Repeat,
+        _ => return None,
+    };
+
+    let mut modifiers = state.modifiers.lock().unwrap();
+    let held = key_state != KeyState::Release;
+    match Key::new(key) {
+        Key::KEY_LEFTSHIFT | Key::KEY_RIGHTSHIFT => modifiers.shift = held,
+        Key::KEY_LEFTCTRL | Key::KEY_RIGHTCTRL => modifiers.ctrl = held,
+        Key::KEY_LEFTALT => modifiers.alt = held,
+        Key::KEY_RIGHTALT => modifiers.altgr = held,
+        Key::KEY_LEFTMETA | Key::KEY_RIGHTMETA => modifiers.meta = held,
+        Key::KEY_CAPSLOCK if key_state == KeyState::Press => {
+            modifiers.caps_lock = !modifiers.caps_lock
+        }
+        _ => {}
+    }
+
+    let text = match key_state {
+        KeyState::Release => None,
+        _ => state.keymap.read().unwrap().text(key, &modifiers),
+    };
+    Some(InputEvent::Keyboard {
+        event: KeyboardEvent {
+            key,
+            state: key_state,
+            modifiers: *modifiers,
+            text,
+        },
+        timestamp: ev.timestamp(),
+    })
+}
+
+/// Value of the epoll data for the inotify fd, keyboards use their own fd
+const INOTIFY_TOKEN: u64 = u64::MAX;
+
+/// Reads from every keyboard connected (e.g. the Type Folio, or USB and bluetooth
+/// keyboards), picking up keyboards as they are connected by watching `/dev/input`
+/// with inotify and dropping them when they go away.
+pub struct KeyboardSource {
+    epfd: i32,
+    inotify: std::os::unix::prelude::OwnedFd,
+    keyboards: HashMap<u64, (PathBuf, evdev::Device)>,
+    state: InputDeviceState,
+    recorder: SharedInputRecorder,
+    stop: Option<StopSignal>,
+}
+
+impl KeyboardSource {
+    pub fn open(state: InputDeviceState, recorder: SharedInputRecorder) -> io::Result<Self> {
+        use std::os::unix::prelude::FromRawFd;
+
+        let inotify = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if inotify < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let inotify = unsafe { std::os::unix::prelude::OwnedFd::from_raw_fd(inotify) };
+        let dir = CString::new(Path::new("/dev/input").as_os_str().as_bytes())?;
+        // udev changes the permissions after creating the node, hence IN_ATTRIB
+        let mask = libc::IN_CREATE | libc::IN_ATTRIB | libc::IN_DELETE;
+        if unsafe { libc::inotify_add_watch(inotify.as_raw_fd(), dir.as_ptr(), mask) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let epfd = epoll::create(true)?;
+        let mut source = KeyboardSource {
+            epfd,
+            inotify,
+            keyboards: HashMap::new(),
+            state,
+            recorder,
+            stop: None,
+        };
+        source.watch(source.inotify.as_raw_fd(), INOTIFY_TOKEN)?;
+        source.rescan();
+        Ok(source)
+    }
+
+    fn watch(&self, fd: i32, token: u64) -> io::Result<()> {
+        let event = epoll::Event {
+            events: (epoll::Events::EPOLLIN | epoll::Events::EPOLLPRI).bits(),
+            data: token,
+        };
+        epoll::ctl(self.epfd, epoll::ControlOptions::EPOLL_CTL_ADD, fd, event)
+    }
+
+    /// Opens the keyboards in /dev/input that aren't open yet
+    fn rescan(&mut self) {
+        let entries = match Path::new("/dev/input").read_dir() {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Failed to list /dev/input: {}", e);
+                return;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_event_node = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("event"));
+            if !is_event_node || self.keyboards.values().any(|(p, _)| *p == path) {
+                continue;
+            }
+            let dev = match evdev::Device::open(&path) {
+                Ok(dev) => dev,
+                Err(e) => {
+                    debug!("Failed to open {:?}: {}", path, e);
+                    continue;
+                }
+            };
+            if !is_keyboard(&dev) {
+                continue;
+            }
+            let token = dev.as_raw_fd() as u64;
+            if let Err(e) = self.watch(dev.as_raw_fd(), token) {
+                warn!("Failed to watch keyboard {:?}: {}", path, e);
+                continue;
+            }
+            info!("Keyboard connected: {:?} ({:?})", dev.name(), path);
+            self.keyboards.insert(token, (path, dev));
+        }
+    }
+
+    fn drain_inotify(&self) {
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = unsafe {
+                libc::read(
+                    self.inotify.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                )
+            };
+            if n <= 0 {
+                break;
+            }
+        }
+    }
+}
+
+impl InputSource for KeyboardSource {
+    fn read_events(&mut self) -> io::Result<Option<Vec<InputEvent>>> {
+        let mut ready = [epoll::Event { events: 0, data: 0 }; 8];
+        let count = match epoll::wait(self.epfd, -1, &mut ready) {
+            Ok(count) => count,
+            Err(err) => {
+                warn!("epoll_wait failed: {}", err);
+                return Ok(Some(vec![]));
+            }
+        };
+
+        let mut events = vec![];
+        for ready in &ready[..count] {
+            let token = ready.data;
+            if token == STOP_TOKEN {
+                return Ok(Some(events));
+            }
+            if token == INOTIFY_TOKEN {
+                self.drain_inotify();
+                self.rescan();
+                continue;
+            }
+            let (path, dev) = match self.keyboards.get_mut(&token) {
+                Some(keyboard) => keyboard,
+                None => continue,
+            };
+            let fetched: io::Result<Vec<EvInputEvent>> = dev.fetch_events().map(Iterator::collect);
+            match fetched {
+                Ok(fetched) => {
+                    for ev in fetched {
+                        if let Some(ref mut recorder) = *self.recorder.lock().unwrap() {
+                            if let Err(e) = recorder.record(InputDevice::Keyboard, &ev) {
+                                warn!("Failed to record input event: {}", e);
+                            }
+                        }
+                        events.extend(decode(&ev, &self.state));
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => {
+                    // Typically ENODEV once the keyboard is disconnected
+                    info!("Keyboard disconnected: {:?} ({})", path, e);
+                    // Closing the fd removes it from the epoll set
+                    self.keyboards.remove(&token);
+                    // Modifiers held on it would otherwise stick to the other keyboards
+                    if let InputDeviceState::KeyboardState(ref state) = self.state {
+                        state.reset_modifiers();
+                    }
+                }
+            }
+        }
+        Ok(Some(events))
+    }
+
+    fn set_stop_signal(&mut self, stop: StopSignal) -> io::Result<()> {
+        self.watch(stop.as_raw_fd(), STOP_TOKEN)?;
+        self.stop = Some(stop);
+        Ok(())
+    }
+}
+
+impl Drop for KeyboardSource {
+    fn drop(&mut self) {
+        let _ = epoll::close(self.epfd);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use evdev::EventType;
+    use std::sync::Arc;
+
+    fn key(state: &InputDeviceState, key: Key, value: i32) -> KeyboardEvent {
+        match decode(&EvInputEvent::new(EventType::KEY, key.code(), value), state) {
+            Some(InputEvent::Keyboard { event, .. }) => event,
+            other => panic!("Expected a keyboard event, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_modifiers_and_text() {
+        let state = InputDeviceState::KeyboardState(Arc::new(KeyboardState::default()));
+        assert_eq!(key(&state, Key::KEY_A, 1).text, Some('a'));
+        assert_eq!(key(&state, Key::KEY_A, 0).text, None);
+
+        key(&state, Key::KEY_LEFTSHIFT, 1);
+        let event = key(&state, Key::KEY_1, 2);
+        assert_eq!(event.state, KeyState::Repeat);
+        assert!(event.modifiers.shift);
+        assert_eq!(event.text, Some('!'));
+        key(&state, Key::KEY_LEFTSHIFT, 0);
+
+        key(&state, Key::KEY_CAPSLOCK, 1);
+        key(&state, Key::KEY_CAPSLOCK, 0);
+        assert_eq!(key(&state, Key::KEY_Q, 1).text, Some('Q'));
+        assert_eq!(key(&state, Key::KEY_SEMICOLON, 1).text, Some(';'));
+
+        key(&state, Key::KEY_LEFTCTRL, 1);
+        let event = key(&state, Key::KEY_C, 1);
+        assert!(event.modifiers.ctrl);
+        assert_eq!(event.text, None);
+
+        if let InputDeviceState::KeyboardState(ref keyboard) = state {
+            keyboard.reset_modifiers();
+        }
+        let event = key(&state, Key::KEY_C, 1);
+        assert!(!event.modifiers.ctrl);
+        assert_eq!(event.text, Some('c'));
+    }
+
+    #[test]
+    fn test_parse_keymap() {
+        let layout = "# German umlauts\n39 ö Ö\n16 q Q @\n40 U+E4 U+C4\n";
+        let keymap = Keymap::parse(Keymap::us(), layout).unwrap();
+        let mut modifiers = KeyModifiers::default();
+        assert_eq!(
+            keymap.text(Key::KEY_SEMICOLON.code(), &modifiers),
+            Some('ö')
+        );
+        assert_eq!(
+            keymap.text(Key::KEY_APOSTROPHE.code(), &modifiers),
+            Some('ä')
+        );
+        modifiers.altgr = true;
+        assert_eq!(keymap.text(Key::KEY_Q.code(), &modifiers), Some('@'));
+        assert!(Keymap::parse(Keymap::us(), "16 q").is_err());
+    }
+}
@@ -15,19 +15,55 @@ pub mod gpio;
 #[cfg(feature = "input")]
 pub mod multitouch;
 
+/// Contains the code to decode keyboard events and to pick up keyboards as they connect
+#[cfg(feature = "input")]
+pub mod keyboard;
+
+/// Abstracts over where an `EvDevContext` gets its events from
+#[cfg(feature = "input")]
+pub mod source;
+
+/// Reads the scanned input devices on a single thread, in the order of their timestamps
+#[cfg(feature = "input")]
+pub mod reactor;
+
+/// Creates virtual input devices to simulate pen, touch and button input
+#[cfg(feature = "input")]
+pub mod uinput;
+
+/// Records raw input events to a file and replays them through the decoders
+#[cfg(feature = "input")]
+pub mod record;
+
 /// Contains the ev codes in use
 pub mod ecodes;
 
+/// Suppresses touches made by the palm resting on the display while writing
+pub mod palm;
+
+/// Corrects the positions reported by worn or replaced digitizers
+pub mod calibration;
+
+/// Tracks the tool, buttons and strokes of the pen in the Wacom events
+pub mod pen;
+
+/// Recognizes taps, swipes, pinches and other gestures in multitouch events
+pub mod gesture;
+
 /// Figures out where the input devices are as well as
 /// device dependant properties
 #[cfg(feature = "scan")]
 pub mod scan;
 
+use std::time::SystemTime;
+
 #[derive(PartialEq, Copy, Clone, Debug, Hash, Eq)]
 pub enum InputDevice {
     Wacom,
     Multitouch,
     GPIO,
+    /// Any keyboard, like the Type Folio or USB and bluetooth keyboards
+    Keyboard,
     Unknown,
 }
 
@@ -36,6 +72,7 @@ pub enum InputDeviceState {
     WacomState(std::sync::Arc<wacom::WacomState>),
     MultitouchState(std::sync::Arc<multitouch::MultitouchState>),
     GPIOState(std::sync::Arc<gpio::GPIOState>),
+    KeyboardState(std::sync::Arc<keyboard::KeyboardState>),
 }
 
 #[cfg(feature = "input")]
@@ -53,6 +90,9 @@ impl Clone for InputDeviceState {
             InputDeviceState::GPIOState(ref state) => {
                 InputDeviceState::GPIOState(Arc::clone(state))
             }
+            InputDeviceState::KeyboardState(ref state) => {
+                InputDeviceState::KeyboardState(Arc::clone(state))
+            }
         }
     }
 }
@@ -68,9 +108,26 @@ impl InputDeviceState {
             InputDevice::Multitouch => {
                 InputDeviceState::MultitouchState(Arc::new(multitouch::MultitouchState::default()))
             }
+            InputDevice::Keyboard => {
+                InputDeviceState::KeyboardState(Arc::new(keyboard::KeyboardState::default()))
+            }
             _ => unreachable!(),
         }
     }
+
+    /// Like `new`, but the Wacom and multitouch decoders map positions with `geometry`
+    /// instead of the geometry of the scanned devices
+    pub fn with_geometry(dev: InputDevice, geometry: scan::InputGeometry) -> InputDeviceState {
+        match dev {
+            InputDevice::Wacom => {
+                InputDeviceState::WacomState(Arc::new(wacom::WacomState::with_geometry(geometry)))
+            }
+            InputDevice::Multitouch => InputDeviceState::MultitouchState(Arc::new(
+                multitouch::MultitouchState::with_geometry(geometry),
+            )),
+            _ => InputDeviceState::new(dev),
+        }
+    }
 }
 
 #[repr(u16)]
@@ -106,12 +163,23 @@ pub enum WacomEvent {
     Hover {
         position: cgmath::Point2<f32>,
         distance: u16,
-        tilt: cgmath::Vector2<u16>,
+        /// Tilt of the pen in degrees, from -90 to 90 on each axis
+        tilt: cgmath::Vector2<f32>,
+        /// The tool ID (`ABS_MISC`) of the pen, if the digitizer reports one
+        tool_id: Option<u32>,
+        /// The serial number (`MSC_SERIAL`) of the pen, if the digitizer reports one
+        serial: Option<u32>,
     },
     Draw {
         position: cgmath::Point2<f32>,
+        /// The pressure as reported by the digitizer
         pressure: u16,
-        tilt: cgmath::Vector2<u16>,
+        /// `pressure` scaled to 0.0-1.0 using the maximum of the digitizer
+        normalized_pressure: f32,
+        /// Tilt of the pen in degrees, from -90 to 90 on each axis
+        tilt: cgmath::Vector2<f32>,
+        tool_id: Option<u32>,
+        serial: Option<u32>,
     },
     Unknown,
 }
@@ -125,6 +193,16 @@ pub struct Finger {
 
     pub(crate) last_pressed: bool,
     pub pressed: bool,
+
+    /// The pressure as reported by the touch sensor, see `normalized_pressure()`
+    pub pressure: u16,
+
+    /// Length of the major axis of the contact ellipse, in touch sensor units
+    pub touch_major: u16,
+    /// Length of the minor axis of the contact ellipse, in touch sensor units
+    pub touch_minor: u16,
+    /// Orientation of the contact ellipse as reported by the touch sensor
+    pub orientation: i32,
 }
 
 impl Default for Finger {
@@ -138,10 +216,22 @@ impl Default for Finger {
             pos_updated: false,
             last_pressed: false,
             pressed: false,
+            pressure: 0,
+            touch_major: 0,
+            touch_minor: 0,
+            orientation: 0,
         }
     }
 }
 
+impl Finger {
+    /// `pressure` scaled to 0.0-1.0 using the maximum of the touch sensor
+    #[cfg(feature = "scan")]
+    pub fn normalized_pressure(&self) -> f32 {
+        (f32::from(self.pressure) / f32::from(scan::SCANNED.mt_max_pressure)).min(1.0)
+    }
+}
+
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
 pub enum MultitouchEvent {
     Press { finger: Finger },
@@ -177,14 +267,114 @@ pub enum GPIOEvent {
     Unknown,
 }
 
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Default)]
+pub struct KeyModifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    /// The right alt key, selecting the third level of the keymap
+    pub altgr: bool,
+    pub meta: bool,
+    pub caps_lock: bool,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum KeyState {
+    Press,
+    Release,
+    /// Sent by the kernel while the key is held down
+    Repeat,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct KeyboardEvent {
+    /// The evdev keycode (`KEY_*`)
+    pub key: u16,
+    pub state: KeyState,
+    /// The modifiers held, including this key if it is one
+    pub modifiers: KeyModifiers,
+    /// What the key types according to the keymap, if anything
+    pub text: Option<char>,
+}
+
+/// The `timestamp` of the events is the time the kernel reported them at
 #[derive(PartialEq, Clone, Debug)]
 pub enum InputEvent {
-    WacomEvent { event: WacomEvent },
-    MultitouchEvent { event: MultitouchEvent },
-    GPIO { event: GPIOEvent },
+    WacomEvent {
+        event: WacomEvent,
+        timestamp: SystemTime,
+    },
+    MultitouchEvent {
+        event: MultitouchEvent,
+        timestamp: SystemTime,
+    },
+    GPIO {
+        event: GPIOEvent,
+        timestamp: SystemTime,
+    },
+    Keyboard {
+        event: KeyboardEvent,
+        timestamp: SystemTime,
+    },
+    /// Only produced by an `ApplicationContext` with gestures enabled. The timestamp
+    /// is that of the event completing the gesture.
+    GestureEvent {
+        event: gesture::Gesture,
+        timestamp: SystemTime,
+    },
+    /// Only produced by an `ApplicationContext` with pen tracking enabled. The
+    /// timestamp is that of the Wacom event causing it.
+    PenEvent {
+        event: pen::PenEvent,
+        timestamp: SystemTime,
+    },
+    /// Only produced by an `ApplicationContext` with the battery monitor started.
+    /// The timestamp is the time the change was noticed.
+    #[cfg(feature = "battery")]
+    Battery {
+        event: crate::battery::monitor::BatteryEvent,
+        timestamp: SystemTime,
+    },
+    /// Only produced by an `ApplicationContext` when it resumes from `suspend`. The
+    /// timestamp is the time of the resume.
+    Power {
+        event: crate::power::PowerEvent,
+        timestamp: SystemTime,
+    },
+    /// Only produced by an `ApplicationContext` with idle tracking enabled. The
+    /// timestamp is the time the timeout elapsed or the activity was noticed.
+    Idle {
+        event: crate::power::idle::IdleEvent,
+        timestamp: SystemTime,
+    },
+    /// Reading from the device failed for good and its `EvDevContext` has exited
+    DeviceError {
+        device: InputDevice,
+        error: String,
+        timestamp: SystemTime,
+    },
     Unknown {},
 }
 
+impl InputEvent {
+    pub fn timestamp(&self) -> Option<SystemTime> {
+        match self {
+            InputEvent::WacomEvent { timestamp, .. }
+            | InputEvent::MultitouchEvent { timestamp, .. }
+            | InputEvent::GPIO { timestamp, .. }
+            | InputEvent::Keyboard { timestamp, .. }
+            | InputEvent::GestureEvent { timestamp, .. }
+            | InputEvent::PenEvent { timestamp, .. }
+            | InputEvent::Power { timestamp, .. }
+            | InputEvent::Idle { timestamp, .. }
+            | InputEvent::DeviceError { timestamp, .. } => Some(*timestamp),
+            #[cfg(feature = "battery")]
+            InputEvent::Battery { timestamp, .. } => Some(*timestamp),
+            InputEvent::Unknown {} => None,
+        }
+    }
+}
+
 impl Default for InputEvent {
     fn default() -> InputEvent {
         InputEvent::Unknown {}
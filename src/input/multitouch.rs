@@ -1,8 +1,8 @@
 use super::ecodes;
 use crate::device::rotate::CoordinatePart;
-use crate::device::CURRENT_DEVICE;
-use crate::dimensions::{DISPLAYHEIGHT, DISPLAYWIDTH, MTHEIGHT, MTWIDTH};
-use crate::input::scan::SCANNED;
+use crate::dimensions::{DISPLAYHEIGHT, DISPLAYWIDTH};
+use crate::input::calibration;
+use crate::input::scan::{InputGeometry, SCANNED};
 use crate::input::{Finger, InputDeviceState, InputEvent, MultitouchEvent};
 use std::sync::LazyLock;
 
@@ -14,13 +14,13 @@ use std::sync::{
     Mutex,
 };
 
-static MT_HSCALAR: LazyLock<f32> = LazyLock::new(|| f32::from(DISPLAYWIDTH) / f32::from(*MTWIDTH));
-static MT_VSCALAR: LazyLock<f32> =
-    LazyLock::new(|| f32::from(DISPLAYHEIGHT) / f32::from(*MTHEIGHT));
+static MT_GEOMETRY: LazyLock<InputGeometry> = LazyLock::new(|| SCANNED.multitouch_geometry());
 
 pub struct MultitouchState {
     fingers: Mutex<FxHashMap<i32 /* slot */, Finger>>,
     current_slot: AtomicI32,
+    /// `None` to use the geometry of the scanned touchscreen
+    geometry: Option<InputGeometry>,
 }
 
 impl ::std::default::Default for MultitouchState {
@@ -28,15 +28,31 @@ impl ::std::default::Default for MultitouchState {
         MultitouchState {
             fingers: Mutex::new(FxHashMap::default()),
             current_slot: AtomicI32::new(0),
+            geometry: None,
         }
     }
 }
 
+impl MultitouchState {
+    /// A state that maps positions with `geometry` instead of the one of the scanned touchscreen
+    pub fn with_geometry(geometry: InputGeometry) -> MultitouchState {
+        MultitouchState {
+            geometry: Some(geometry),
+            ..Default::default()
+        }
+    }
+
+    fn geometry(&self) -> InputGeometry {
+        self.geometry.unwrap_or_else(|| *MT_GEOMETRY)
+    }
+}
+
 pub fn decode(ev: &EvInputEvent, outer_state: &InputDeviceState) -> Vec<InputEvent> {
     let state = match outer_state {
         InputDeviceState::MultitouchState(ref state_arc) => state_arc,
         _ => unreachable!(),
     };
+    let timestamp = ev.timestamp();
     let mut fingers = state.fingers.lock().unwrap();
     let current_slot = state.current_slot.load(Ordering::Relaxed);
     match ev.event_type().0 {
@@ -44,22 +60,44 @@ pub fn decode(ev: &EvInputEvent, outer_state: &InputDeviceState) -> Vec<InputEve
             match ev.code() {
                 ecodes::SYN_REPORT => {
                     let mut events: Vec<InputEvent> = vec![];
+                    let calibration = calibration::current().multitouch;
+                    let calibrated = |finger: &Finger| {
+                        let mut finger = *finger;
+                        if !calibration.is_identity() {
+                            let pos = calibration.apply(finger.pos.cast().unwrap());
+                            // Keep the position on the display
+                            finger.pos = cgmath::Point2 {
+                                x: pos.x.round().clamp(0.0, f32::from(DISPLAYWIDTH)) as u16,
+                                y: pos.y.round().clamp(0.0, f32::from(DISPLAYHEIGHT)) as u16,
+                            };
+                        }
+                        finger
+                    };
                     for (_slot, finger) in fingers.iter_mut() {
                         if !finger.last_pressed && finger.pressed {
                             // Pressed
                             finger.last_pressed = finger.pressed;
                             events.push(InputEvent::MultitouchEvent {
-                                event: MultitouchEvent::Press { finger: *finger },
+                                event: MultitouchEvent::Press {
+                                    finger: calibrated(finger),
+                                },
+                                timestamp,
                             });
                         } else if finger.last_pressed && !finger.pressed {
                             // Released
                             finger.last_pressed = finger.pressed;
                             events.push(InputEvent::MultitouchEvent {
-                                event: MultitouchEvent::Release { finger: *finger },
+                                event: MultitouchEvent::Release {
+                                    finger: calibrated(finger),
+                                },
+                                timestamp,
                             });
                         } else if finger.last_pressed && finger.pressed && finger.pos_updated {
                             events.push(InputEvent::MultitouchEvent {
-                                event: MultitouchEvent::Move { finger: *finger },
+                                event: MultitouchEvent::Move {
+                                    finger: calibrated(finger),
+                                },
+                                timestamp,
                             });
                         }
 
@@ -89,66 +127,31 @@ pub fn decode(ev: &EvInputEvent, outer_state: &InputDeviceState) -> Vec<InputEve
                     // necessary to change the local current_slot variable.
                     vec![]
                 }
-                ecodes::ABS_MT_POSITION_X => {
-                    let placement = CURRENT_DEVICE.get_multitouch_placement();
-                    let mut rotated_part = placement.rotation.rotate_part(
-                        CoordinatePart::X(ev.value() as u16),
-                        &SCANNED.multitouch_orig_size,
-                    );
-                    if placement.invert_x {
-                        if let CoordinatePart::X(ref mut x_value) = rotated_part {
-                            *x_value = *MTWIDTH - *x_value;
-                        }
-                    }
-                    if placement.invert_y {
-                        if let CoordinatePart::Y(ref mut y_value) = rotated_part {
-                            *y_value = *MTHEIGHT - *y_value;
-                        }
-                    }
+                ecodes::ABS_MT_POSITION_X | ecodes::ABS_MT_POSITION_Y => {
+                    let part = match ev.code() {
+                        ecodes::ABS_MT_POSITION_X => CoordinatePart::X(ev.value() as u16),
+                        _ => CoordinatePart::Y(ev.value() as u16),
+                    };
+                    let geometry = state.geometry();
+                    let scale = geometry.scale();
                     let finger: &mut Finger = fingers.entry(current_slot).or_default();
-                    match rotated_part {
-                        CoordinatePart::X(rotated_value) => {
-                            finger.pos.x = (f32::from(rotated_value) * *MT_HSCALAR) as u16;
+                    match geometry.place_part(part) {
+                        CoordinatePart::X(placed_value) => {
+                            finger.pos.x = (f32::from(placed_value) * scale.x) as u16;
                         }
-                        CoordinatePart::Y(rotated_value) => {
-                            finger.pos.y = (f32::from(rotated_value) * *MT_VSCALAR) as u16;
-                        }
-                    }
-                    finger.pos_updated = true;
-                    vec![]
-                }
-                ecodes::ABS_MT_POSITION_Y => {
-                    let placement = CURRENT_DEVICE.get_multitouch_placement();
-                    let mut rotated_part = placement.rotation.rotate_part(
-                        CoordinatePart::Y(ev.value() as u16),
-                        &SCANNED.multitouch_orig_size,
-                    );
-                    if placement.invert_x {
-                        if let CoordinatePart::X(ref mut x_value) = rotated_part {
-                            *x_value = *MTWIDTH - *x_value;
-                        }
-                    }
-                    if placement.invert_y {
-                        if let CoordinatePart::Y(ref mut y_value) = rotated_part {
-                            *y_value = *MTHEIGHT - *y_value;
-                        }
-                    }
-                    let finger: &mut Finger = fingers.entry(current_slot).or_default();
-                    match rotated_part {
-                        CoordinatePart::X(rotated_value) => {
-                            finger.pos.x = (f32::from(rotated_value) * *MT_HSCALAR) as u16;
-                        }
-                        CoordinatePart::Y(rotated_value) => {
-                            finger.pos.y = (f32::from(rotated_value) * *MT_VSCALAR) as u16;
+                        CoordinatePart::Y(placed_value) => {
+                            finger.pos.y = (f32::from(placed_value) * scale.y) as u16;
                         }
                     }
                     finger.pos_updated = true;
                     vec![]
                 }
                 ecodes::ABS_MT_PRESSURE => {
+                    let finger = fingers.entry(current_slot).or_default();
+                    finger.pressure = ev.value() as u16;
                     if ev.value() > 0 {
                         // Pretty much always true, but who knows
-                        fingers.entry(current_slot).or_default().pressed = true;
+                        finger.pressed = true;
                     }
                     vec![]
                 }
@@ -162,9 +165,18 @@ pub fn decode(ev: &EvInputEvent, outer_state: &InputDeviceState) -> Vec<InputEve
                         vec![]
                     }
                 },
-                ecodes::ABS_MT_ORIENTATION
-                | ecodes::ABS_MT_TOUCH_MAJOR
-                | ecodes::ABS_MT_TOUCH_MINOR => vec![], // Currently not needed
+                ecodes::ABS_MT_TOUCH_MAJOR => {
+                    fingers.entry(current_slot).or_default().touch_major = ev.value() as u16;
+                    vec![]
+                }
+                ecodes::ABS_MT_TOUCH_MINOR => {
+                    fingers.entry(current_slot).or_default().touch_minor = ev.value() as u16;
+                    vec![]
+                }
+                ecodes::ABS_MT_ORIENTATION => {
+                    fingers.entry(current_slot).or_default().orientation = ev.value();
+                    vec![]
+                }
                 // very unlikely
                 // Technically possible (but maybe not for the reMarkable):
                 // ABS_MT_DISTANCE, ABS_MT_TOOL_X, ABS_MT_TOOL_Y, ABS_MT_WIDTH_MAJOR,
@@ -0,0 +1,167 @@
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use crate::input::{InputEvent, MultitouchEvent, WacomEvent};
+
+/// Drops the `MultitouchEvent`s of contacts that are likely a palm rather than a finger.
+///
+/// A contact is rejected when its major axis exceeds `max_touch_major`, or when it is
+/// pressed within `pen_radius` of where the pen was last seen hovering or drawing,
+/// for up to `pen_timeout` after the pen was seen. A contact that was already
+/// reported and grows palm sized is ended with a `Release` and ignored afterwards.
+///
+/// `ApplicationContext::enable_palm_rejection(..)` runs this in front of everything
+/// else in the event loop, but it can be used on its own by passing every event
+/// through `filter`.
+#[derive(Clone, Debug)]
+pub struct PalmRejection {
+    /// In touch sensor units, see `Finger::touch_major`
+    pub max_touch_major: u16,
+    /// In display pixels. A radius of `u16::MAX` rejects all touches while the pen is near.
+    pub pen_radius: u16,
+    pub pen_timeout: Duration,
+    pen_seen: Option<(cgmath::Point2<f32>, Instant)>,
+    rejected: HashSet<i32>,
+}
+
+impl Default for PalmRejection {
+    fn default() -> Self {
+        PalmRejection {
+            max_touch_major: 40,
+            pen_radius: 600,
+            pen_timeout: Duration::from_millis(500),
+            pen_seen: None,
+            rejected: HashSet::new(),
+        }
+    }
+}
+
+impl PalmRejection {
+    /// Returns the event to pass on, if any
+    pub fn filter(&mut self, event: InputEvent, now: Instant) -> Option<InputEvent> {
+        let timestamp = match event.timestamp() {
+            Some(timestamp) => timestamp,
+            None => return Some(event),
+        };
+        let event = match event {
+            InputEvent::WacomEvent {
+                event: WacomEvent::Hover { position, .. } | WacomEvent::Draw { position, .. },
+                ..
+            } => {
+                self.pen_seen = Some((position, now));
+                return Some(event);
+            }
+            InputEvent::MultitouchEvent { event, .. } => event,
+            _ => return Some(event),
+        };
+
+        let passed = match event {
+            MultitouchEvent::Press { finger } => {
+                if self.is_palm_sized(finger.touch_major) || self.is_near_pen(finger.pos, now) {
+                    self.rejected.insert(finger.tracking_id);
+                    None
+                } else {
+                    Some(event)
+                }
+            }
+            MultitouchEvent::Move { finger } => {
+                if self.rejected.contains(&finger.tracking_id) {
+                    None
+                } else if self.is_palm_sized(finger.touch_major) {
+                    self.rejected.insert(finger.tracking_id);
+                    Some(MultitouchEvent::Release { finger })
+                } else {
+                    Some(event)
+                }
+            }
+            MultitouchEvent::Release { finger } => {
+                if self.rejected.remove(&finger.tracking_id) {
+                    None
+                } else {
+                    Some(event)
+                }
+            }
+            MultitouchEvent::Unknown => Some(event),
+        };
+        passed.map(|event| InputEvent::MultitouchEvent { event, timestamp })
+    }
+
+    fn is_palm_sized(&self, touch_major: u16) -> bool {
+        touch_major > self.max_touch_major
+    }
+
+    fn is_near_pen(&self, pos: cgmath::Point2<u16>, now: Instant) -> bool {
+        match self.pen_seen {
+            Some((pen, seen)) if now - seen <= self.pen_timeout => {
+                let dx = f32::from(pos.x) - pen.x;
+                let dy = f32::from(pos.y) - pen.y;
+                (dx * dx + dy * dy).sqrt() <= f32::from(self.pen_radius)
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::Finger;
+
+    fn touch(kind: fn(Finger) -> MultitouchEvent, id: i32, x: u16, major: u16) -> InputEvent {
+        InputEvent::MultitouchEvent {
+            event: kind(Finger {
+                tracking_id: id,
+                pos: cgmath::Point2 { x, y: 1000 },
+                pressed: true,
+                touch_major: major,
+                ..Default::default()
+            }),
+            timestamp: std::time::SystemTime::UNIX_EPOCH,
+        }
+    }
+
+    #[test]
+    fn test_rejects_touches_near_hovering_pen() {
+        let mut palm = PalmRejection::default();
+        let t = Instant::now();
+        let press = |finger| MultitouchEvent::Press { finger };
+        let release = |finger| MultitouchEvent::Release { finger };
+
+        let hover = InputEvent::WacomEvent {
+            event: WacomEvent::Hover {
+                position: cgmath::Point2 { x: 700.0, y: 900.0 },
+                distance: 20,
+                tilt: cgmath::Vector2 { x: 0.0, y: 0.0 },
+                tool_id: None,
+                serial: None,
+            },
+            timestamp: std::time::SystemTime::UNIX_EPOCH,
+        };
+        assert!(palm.filter(hover, t).is_some());
+        assert!(palm.filter(touch(press, 1, 900, 10), t).is_none());
+        assert!(palm.filter(touch(release, 1, 900, 10), t).is_none());
+        // Far away from the pen
+        assert!(palm.filter(touch(press, 2, 1390, 10), t).is_some());
+        // Once the pen is gone for long enough, touches near it are fine again
+        let later = t + Duration::from_secs(1);
+        assert!(palm.filter(touch(press, 3, 900, 10), later).is_some());
+    }
+
+    #[test]
+    fn test_growing_contact_is_released() {
+        let mut palm = PalmRejection::default();
+        let t = Instant::now();
+        let press = |finger| MultitouchEvent::Press { finger };
+        let moved = |finger| MultitouchEvent::Move { finger };
+        let release = |finger| MultitouchEvent::Release { finger };
+
+        assert!(palm.filter(touch(press, 1, 100, 90), t).is_none());
+        assert!(palm.filter(touch(press, 2, 100, 10), t).is_some());
+        assert_eq!(
+            palm.filter(touch(moved, 2, 100, 90), t),
+            Some(touch(release, 2, 100, 90))
+        );
+        assert!(palm.filter(touch(moved, 2, 100, 20), t).is_none());
+        assert!(palm.filter(touch(release, 2, 100, 20), t).is_none());
+    }
+}
@@ -0,0 +1,300 @@
+use crate::input::{WacomEvent, WacomPen};
+
+/// The end of the pen near the display
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum PenTool {
+    Tip,
+    Eraser,
+}
+
+/// The buttons on the side of some pens
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum BarrelButton {
+    /// `BTN_STYLUS`
+    Primary,
+    /// `BTN_STYLUS2`
+    Secondary,
+}
+
+/// What a stroke is meant to do
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum PenAction {
+    Draw,
+    Erase,
+    Select,
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum PenEvent {
+    /// The pen came into the range of the digitizer
+    ProximityEnter {
+        tool: PenTool,
+    },
+    /// The pen left the range of the digitizer, after ending any stroke in progress
+    ProximityLeave,
+    /// A barrel button was pressed or released. `action` is what a stroke started
+    /// now would do.
+    Button {
+        button: BarrelButton,
+        pressed: bool,
+        action: PenAction,
+    },
+    /// The pen touched the display. The action is kept until the stroke ends, even
+    /// when buttons change in the meantime.
+    StrokeBegin {
+        stroke_id: u32,
+        action: PenAction,
+        position: cgmath::Point2<f32>,
+    },
+    StrokeEnd {
+        stroke_id: u32,
+        action: PenAction,
+    },
+}
+
+/// Which actions the barrel buttons select while held. `None` leaves the button
+/// to the application. When both are held, the secondary button wins.
+#[derive(Clone, Debug)]
+pub struct PenConfig {
+    pub primary_button: Option<PenAction>,
+    pub secondary_button: Option<PenAction>,
+}
+
+impl ::std::default::Default for PenConfig {
+    fn default() -> Self {
+        PenConfig {
+            primary_button: Some(PenAction::Erase),
+            secondary_button: Some(PenAction::Select),
+        }
+    }
+}
+
+/// Turns the raw `WacomEvent`s into the tool in use, proximity changes and numbered
+/// strokes.
+pub struct PenTracker {
+    pub config: PenConfig,
+    tool: Option<PenTool>,
+    primary_held: bool,
+    secondary_held: bool,
+    /// The pen touched the display, the stroke begins with the next `Draw`
+    touch_pending: bool,
+    stroke: Option<(u32, PenAction)>,
+    next_stroke_id: u32,
+}
+
+impl PenTracker {
+    pub fn new(config: PenConfig) -> PenTracker {
+        PenTracker {
+            config,
+            tool: None,
+            primary_held: false,
+            secondary_held: false,
+            touch_pending: false,
+            stroke: None,
+            next_stroke_id: 0,
+        }
+    }
+
+    /// The tool in range of the digitizer, if any
+    pub fn tool(&self) -> Option<PenTool> {
+        self.tool
+    }
+
+    /// The id and action of the stroke in progress
+    pub fn stroke(&self) -> Option<(u32, PenAction)> {
+        self.stroke
+    }
+
+    pub fn button_held(&self, button: BarrelButton) -> bool {
+        match button {
+            BarrelButton::Primary => self.primary_held,
+            BarrelButton::Secondary => self.secondary_held,
+        }
+    }
+
+    /// What a stroke started now would do
+    pub fn action(&self) -> PenAction {
+        if self.tool == Some(PenTool::Eraser) {
+            return PenAction::Erase;
+        }
+        let secondary = self.secondary_held.then_some(self.config.secondary_button);
+        let primary = self.primary_held.then_some(self.config.primary_button);
+        secondary
+            .flatten()
+            .or(primary.flatten())
+            .unwrap_or(PenAction::Draw)
+    }
+
+    pub fn process(&mut self, event: &WacomEvent) -> Vec<PenEvent> {
+        match *event {
+            WacomEvent::InstrumentChange { pen, state } => self.instrument_change(pen, state),
+            WacomEvent::Draw { position, .. } if self.touch_pending => {
+                self.touch_pending = false;
+                let stroke_id = self.next_stroke_id;
+                self.next_stroke_id = self.next_stroke_id.wrapping_add(1);
+                let action = self.action();
+                self.stroke = Some((stroke_id, action));
+                vec![PenEvent::StrokeBegin {
+                    stroke_id,
+                    action,
+                    position,
+                }]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn instrument_change(&mut self, pen: WacomPen, state: bool) -> Vec<PenEvent> {
+        match (pen, state) {
+            (WacomPen::ToolPen | WacomPen::ToolRubber, true) => {
+                let tool = match pen {
+                    WacomPen::ToolRubber => PenTool::Eraser,
+                    _ => PenTool::Tip,
+                };
+                let mut events = vec![];
+                if self.tool.is_some() {
+                    events.extend(self.leave());
+                }
+                self.tool = Some(tool);
+                events.push(PenEvent::ProximityEnter { tool });
+                events
+            }
+            (WacomPen::ToolPen | WacomPen::ToolRubber, false) => self.leave(),
+            (WacomPen::Touch, true) => {
+                self.touch_pending = true;
+                vec![]
+            }
+            (WacomPen::Touch, false) => {
+                self.touch_pending = false;
+                self.end_stroke().into_iter().collect()
+            }
+            (WacomPen::Stylus | WacomPen::Stylus2, pressed) => {
+                let button = match pen {
+                    WacomPen::Stylus => BarrelButton::Primary,
+                    _ => BarrelButton::Secondary,
+                };
+                match button {
+                    BarrelButton::Primary => self.primary_held = pressed,
+                    BarrelButton::Secondary => self.secondary_held = pressed,
+                }
+                vec![PenEvent::Button {
+                    button,
+                    pressed,
+                    action: self.action(),
+                }]
+            }
+        }
+    }
+
+    fn end_stroke(&mut self) -> Option<PenEvent> {
+        self.stroke
+            .take()
+            .map(|(stroke_id, action)| PenEvent::StrokeEnd { stroke_id, action })
+    }
+
+    fn leave(&mut self) -> Vec<PenEvent> {
+        if self.tool.is_none() {
+            return vec![];
+        }
+        let mut events: Vec<PenEvent> = self.end_stroke().into_iter().collect();
+        self.tool = None;
+        self.touch_pending = false;
+        self.primary_held = false;
+        self.secondary_held = false;
+        events.push(PenEvent::ProximityLeave);
+        events
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn change(pen: WacomPen, state: bool) -> WacomEvent {
+        WacomEvent::InstrumentChange { pen, state }
+    }
+
+    fn draw(x: f32) -> WacomEvent {
+        WacomEvent::Draw {
+            position: cgmath::Point2 { x, y: 100.0 },
+            pressure: 2000,
+            normalized_pressure: 0.5,
+            tilt: cgmath::Vector2 { x: 0.0, y: 0.0 },
+            tool_id: None,
+            serial: None,
+        }
+    }
+
+    #[test]
+    fn test_strokes() {
+        let mut pen = PenTracker::new(PenConfig::default());
+        let tool = PenTool::Tip;
+        assert_eq!(
+            pen.process(&change(WacomPen::ToolPen, true)),
+            vec![PenEvent::ProximityEnter { tool }]
+        );
+        assert!(pen.process(&change(WacomPen::Touch, true)).is_empty());
+        let begin = pen.process(&draw(10.0));
+        assert!(matches!(
+            begin[..],
+            [PenEvent::StrokeBegin {
+                stroke_id: 0,
+                action: PenAction::Draw,
+                ..
+            }]
+        ));
+        assert!(pen.process(&draw(11.0)).is_empty());
+
+        // The button changes the action of the next stroke only
+        let button = BarrelButton::Primary;
+        let action = PenAction::Erase;
+        assert_eq!(
+            pen.process(&change(WacomPen::Stylus, true)),
+            vec![PenEvent::Button {
+                button,
+                pressed: true,
+                action
+            }]
+        );
+        assert_eq!(
+            pen.process(&change(WacomPen::Touch, false)),
+            vec![PenEvent::StrokeEnd {
+                stroke_id: 0,
+                action: PenAction::Draw
+            }]
+        );
+        pen.process(&change(WacomPen::Touch, true));
+        assert!(matches!(
+            pen.process(&draw(20.0))[..],
+            [PenEvent::StrokeBegin {
+                stroke_id: 1,
+                action: PenAction::Erase,
+                ..
+            }]
+        ));
+
+        // Leaving the range ends the stroke
+        assert_eq!(
+            pen.process(&change(WacomPen::ToolPen, false)),
+            vec![
+                PenEvent::StrokeEnd {
+                    stroke_id: 1,
+                    action
+                },
+                PenEvent::ProximityLeave
+            ]
+        );
+        assert!(!pen.button_held(button));
+    }
+
+    #[test]
+    fn test_eraser_end() {
+        let mut pen = PenTracker::new(PenConfig::default());
+        pen.process(&change(WacomPen::ToolRubber, true));
+        assert_eq!(pen.tool(), Some(PenTool::Eraser));
+        assert_eq!(pen.action(), PenAction::Erase);
+        pen.process(&change(WacomPen::ToolRubber, false));
+        assert_eq!(pen.tool(), None);
+        assert_eq!(pen.action(), PenAction::Draw);
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::prelude::{AsRawFd, BorrowedFd};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::SystemTime;
+
+use evdev::EventType;
+use log::{error, warn};
+
+use crate::input::ev::{InputGrab, SharedDeviceFd};
+use crate::input::record::SharedInputRecorder;
+use crate::input::scan::SCANNED;
+use crate::input::source::{self, epoll_add, epoll_del, StopSignal, STOP_TOKEN};
+use crate::input::{ecodes, InputDevice, InputDeviceState, InputEvent};
+
+struct WatchedDevice {
+    dev: evdev::Device,
+    fd: SharedDeviceFd,
+    state: InputDeviceState,
+    /// Events read since the last SYN_REPORT
+    pending: Vec<evdev::InputEvent>,
+}
+
+/// The events of one device up to and including a SYN_REPORT
+struct Frame {
+    timestamp: SystemTime,
+    device: InputDevice,
+    state: InputDeviceState,
+    events: Vec<evdev::InputEvent>,
+}
+
+type WatchedDevices = Arc<Mutex<HashMap<InputDevice, WatchedDevice>>>;
+
+fn token(device: InputDevice) -> u64 {
+    device as u64
+}
+
+/// Reads the Wacom digitizer, the touchscreen and the buttons on a single thread,
+/// all waited on in one epoll set. Devices can be added and removed while it runs.
+///
+/// Everything read in one wakeup is split into frames at SYN_REPORT, and the frames
+/// are decoded in the order of their kernel timestamps. So when the pen and a finger
+/// report at the same time, the order is that of the hardware rather than whichever
+/// thread got scheduled first. Events arriving in a later wakeup are never reordered
+/// before ones already sent.
+///
+/// Keyboards come and go and are read with a `KeyboardSource` in an `EvDevContext`.
+///
+/// Unlike an `EvdevSource`, the reactor doesn't reopen devices it fails to read from.
+/// They are removed and reported with an `InputEvent::DeviceError`, after which
+/// `add_device` opens them again.
+pub struct InputReactor {
+    epfd: i32,
+    devices: WatchedDevices,
+    /// Kept while devices are removed, so grabs are taken again when they are re-added
+    fds: Mutex<HashMap<InputDevice, SharedDeviceFd>>,
+    stop_signal: StopSignal,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl InputReactor {
+    /// Starts the reactor thread, initially without any devices
+    pub fn new(tx: Sender<InputEvent>, recorder: SharedInputRecorder) -> io::Result<Self> {
+        let epfd = epoll::create(true)?;
+        let stop_signal = match StopSignal::new()
+            .and_then(|s| epoll_add(epfd, s.as_raw_fd(), STOP_TOKEN).map(|_| s))
+        {
+            Ok(stop_signal) => stop_signal,
+            Err(e) => {
+                let _ = epoll::close(epfd);
+                return Err(e);
+            }
+        };
+
+        let devices: WatchedDevices = Arc::new(Mutex::new(HashMap::new()));
+        let thread = {
+            let devices = Arc::clone(&devices);
+            std::thread::spawn(move || Self::run(epfd, devices, recorder, tx))
+        };
+        Ok(InputReactor {
+            epfd,
+            devices,
+            fds: Mutex::new(HashMap::new()),
+            stop_signal,
+            thread: Some(thread),
+        })
+    }
+
+    /// Opens the evdev device of `device` and starts reading from it, decoding with `state`.
+    /// If the device was grabbed before it was removed, it is grabbed again.
+    pub fn add_device(&self, device: InputDevice, state: InputDeviceState) -> io::Result<()> {
+        if matches!(device, InputDevice::Keyboard | InputDevice::Unknown) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{device:?} can't be read by the input reactor"),
+            ));
+        }
+        let mut devices = self.devices.lock().unwrap();
+        if devices.contains_key(&device) {
+            return Ok(());
+        }
+        let dev = SCANNED.get_device(device).map_err(io::Error::other)?;
+        // The device keeps its fd open for as long as it exists
+        let dup = unsafe { BorrowedFd::borrow_raw(dev.as_raw_fd()) }.try_clone_to_owned()?;
+        epoll_add(self.epfd, dev.as_raw_fd(), token(device))?;
+        let fd = self.fds.lock().unwrap().entry(device).or_default().clone();
+        fd.set(device, Some(dup));
+        devices.insert(
+            device,
+            WatchedDevice {
+                dev,
+                fd,
+                state,
+                pending: vec![],
+            },
+        );
+        Ok(())
+    }
+
+    /// Stops reading from `device`. Returns whether it was being read.
+    pub fn remove_device(&self, device: InputDevice) -> bool {
+        match self.devices.lock().unwrap().remove(&device) {
+            Some(watched) => {
+                Self::forget(self.epfd, device, &watched);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn forget(epfd: i32, device: InputDevice, watched: &WatchedDevice) {
+        epoll_del(epfd, watched.dev.as_raw_fd());
+        watched.fd.set(device, None);
+    }
+
+    /// Whether `device` is being read. Devices are dropped when reading them fails.
+    pub fn has_device(&self, device: InputDevice) -> bool {
+        self.devices.lock().unwrap().contains_key(&device)
+    }
+
+    /// Grabs the evdev device of `device`, see `InputGrab`
+    pub fn grab(&self, device: InputDevice) -> io::Result<InputGrab> {
+        let devices = self.devices.lock().unwrap();
+        match devices.get(&device) {
+            Some(watched) => watched.fd.grab(device),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                format!("{device:?} is not being read"),
+            )),
+        }
+    }
+
+    /// Stops the reactor thread and waits for it to exit. Also done when dropped.
+    pub fn stop(&mut self) {
+        self.stop_signal.raise();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("The input reactor thread panicked");
+            }
+        }
+    }
+
+    fn run(
+        epfd: i32,
+        devices: WatchedDevices,
+        recorder: SharedInputRecorder,
+        tx: Sender<InputEvent>,
+    ) {
+        let mut ready = [epoll::Event { events: 0, data: 0 }; 8];
+        loop {
+            let count = match epoll::wait(epfd, -1, &mut ready) {
+                Ok(count) => count,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    error!("epoll_wait failed in the input reactor: {}", e);
+                    return;
+                }
+            };
+            if ready[..count].iter().any(|e| e.data == STOP_TOKEN) {
+                return;
+            }
+
+            let mut frames = vec![];
+            let mut events = vec![];
+            {
+                let mut devices = devices.lock().unwrap();
+                for ready in &ready[..count] {
+                    let device = match devices.keys().find(|&&d| token(d) == ready.data) {
+                        Some(&device) => device,
+                        // Removed since epoll_wait returned
+                        None => continue,
+                    };
+                    let watched = devices.get_mut(&device).unwrap();
+                    let fetched: io::Result<Vec<evdev::InputEvent>> =
+                        watched.dev.fetch_events().map(Iterator::collect);
+                    match fetched {
+                        Ok(fetched) => {
+                            Self::split_frames(device, watched, fetched, &recorder, &mut frames)
+                        }
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                        Err(e) => {
+                            warn!("Failed to read from {:?}, dropping it: {}", device, e);
+                            Self::forget(epfd, device, watched);
+                            devices.remove(&device);
+                            events.push(InputEvent::DeviceError {
+                                device,
+                                error: e.to_string(),
+                                timestamp: SystemTime::now(),
+                            });
+                        }
+                    }
+                }
+            }
+
+            // Stable, so frames of one device keep their order even with equal timestamps
+            frames.sort_by_key(|frame| frame.timestamp);
+            for frame in frames {
+                for ev in &frame.events {
+                    events.extend(source::decode(frame.device, ev, &frame.state));
+                }
+            }
+            for event in events {
+                if let Err(e) = tx.send(event) {
+                    error!("Failed to write InputEvent into the channel: {}", e);
+                }
+            }
+        }
+    }
+
+    fn split_frames(
+        device: InputDevice,
+        watched: &mut WatchedDevice,
+        fetched: Vec<evdev::InputEvent>,
+        recorder: &SharedInputRecorder,
+        frames: &mut Vec<Frame>,
+    ) {
+        for ev in fetched {
+            if let Some(ref mut recorder) = *recorder.lock().unwrap() {
+                if let Err(e) = recorder.record(device, &ev) {
+                    warn!("Failed to record input event: {}", e);
+                }
+            }
+            watched.pending.push(ev);
+            if ev.event_type() == EventType::SYNCHRONIZATION && ev.code() == ecodes::SYN_REPORT {
+                frames.push(Frame {
+                    timestamp: ev.timestamp(),
+                    device,
+                    state: watched.state.clone(),
+                    events: std::mem::take(&mut watched.pending),
+                });
+            }
+        }
+    }
+}
+
+impl Drop for InputReactor {
+    fn drop(&mut self) {
+        self.stop();
+        let _ = epoll::close(self.epfd);
+    }
+}
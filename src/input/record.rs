@@ -0,0 +1,395 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use evdev::{EventType, InputEvent as EvInputEvent};
+use log::error;
+
+use crate::input::scan::InputGeometry;
+use crate::input::source::{InputSource, ReplaySource};
+use crate::input::{InputDevice, InputEvent};
+
+/// Every recording starts with these bytes, followed by a format version byte
+const MAGIC: &[u8; 8] = b"LRMINPUT";
+const VERSION: u8 = 1;
+
+/// device u8, type u16, code u16, value i32, timestamp u64 (all little endian)
+const RECORD_LEN: usize = 17;
+
+/// A recorder shared between the threads reading the input devices. Events are
+/// recorded whenever it contains an `InputRecorder`.
+pub type SharedInputRecorder = Arc<Mutex<Option<InputRecorder<Box<dyn Write + Send>>>>>;
+
+fn device_to_byte(device: InputDevice) -> u8 {
+    match device {
+        InputDevice::Wacom => 0,
+        InputDevice::Multitouch => 1,
+        InputDevice::GPIO => 2,
+        InputDevice::Keyboard => 3,
+        InputDevice::Unknown => 255,
+    }
+}
+
+fn byte_to_device(byte: u8) -> InputDevice {
+    match byte {
+        0 => InputDevice::Wacom,
+        1 => InputDevice::Multitouch,
+        2 => InputDevice::GPIO,
+        3 => InputDevice::Keyboard,
+        _ => InputDevice::Unknown,
+    }
+}
+
+/// A raw evdev event read back from a recording
+#[derive(Copy, Clone, Debug)]
+pub struct RecordedEvent {
+    pub device: InputDevice,
+    /// Time since the first recorded event
+    pub timestamp: Duration,
+    pub event: EvInputEvent,
+}
+
+impl RecordedEvent {
+    /// The event with its time set to `time`, since recordings only keep the time
+    /// relative to the first event
+    pub fn event_at(&self, time: SystemTime) -> EvInputEvent {
+        let since_epoch = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        EvInputEvent::from(libc::input_event {
+            // The types of these fields differ between targets, and are 32 bit on some
+            time: libc::timeval {
+                tv_sec: since_epoch.as_secs().try_into().unwrap_or_default(),
+                tv_usec: u64::from(since_epoch.subsec_micros())
+                    .try_into()
+                    .unwrap_or_default(),
+            },
+            type_: self.event.event_type().0,
+            code: self.event.code(),
+            value: self.event.value(),
+        })
+    }
+}
+
+/// Writes raw evdev events to a compact binary recording that `InputReplayer` can read back.
+pub struct InputRecorder<W: Write> {
+    writer: W,
+    first_event: Option<SystemTime>,
+}
+
+impl InputRecorder<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        InputRecorder::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> InputRecorder<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        Ok(InputRecorder {
+            writer,
+            first_event: None,
+        })
+    }
+
+    pub fn record(&mut self, device: InputDevice, event: &EvInputEvent) -> io::Result<()> {
+        let first_event = *self.first_event.get_or_insert(event.timestamp());
+        let timestamp = event
+            .timestamp()
+            .duration_since(first_event)
+            .unwrap_or_default();
+
+        let mut record = [0u8; RECORD_LEN];
+        record[0] = device_to_byte(device);
+        record[1..3].copy_from_slice(&event.event_type().0.to_le_bytes());
+        record[3..5].copy_from_slice(&event.code().to_le_bytes());
+        record[5..9].copy_from_slice(&event.value().to_le_bytes());
+        record[9..17].copy_from_slice(&(timestamp.as_micros() as u64).to_le_bytes());
+        self.writer.write_all(&record)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Flushes and returns the underlying writer
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ReplaySpeed {
+    /// Keep the time between events as it was recorded
+    RealTime,
+    AsFastAsPossible,
+}
+
+/// Reads back the events written by an `InputRecorder`
+pub struct InputReplayer<R: Read> {
+    reader: R,
+}
+
+impl InputReplayer<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        InputReplayer::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> InputReplayer<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0u8; 9];
+        reader.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not an input recording",
+            ));
+        }
+        if header[8] != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported input recording version {}", header[8]),
+            ));
+        }
+        Ok(InputReplayer { reader })
+    }
+
+    /// Returns `Ok(None)` at the end of the recording
+    pub fn next_event(&mut self) -> io::Result<Option<RecordedEvent>> {
+        let mut record = [0u8; RECORD_LEN];
+        let mut read = 0;
+        while read < RECORD_LEN {
+            match self.reader.read(&mut record[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => read += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let event_type = u16::from_le_bytes([record[1], record[2]]);
+        let code = u16::from_le_bytes([record[3], record[4]]);
+        let value = i32::from_le_bytes([record[5], record[6], record[7], record[8]]);
+        let mut timestamp = [0u8; 8];
+        timestamp.copy_from_slice(&record[9..17]);
+        Ok(Some(RecordedEvent {
+            device: byte_to_device(record[0]),
+            timestamp: Duration::from_micros(u64::from_le_bytes(timestamp)),
+            event: EvInputEvent::new(EventType(event_type), code, value),
+        }))
+    }
+
+    /// Feeds the recording through the decoders of the devices it was recorded from and
+    /// sends the resulting `InputEvent`s to `tx`, just like an `EvDevContext` would.
+    ///
+    /// The decoders map positions using the geometry of the device they run on, so
+    /// this needs to run on the same model the recording was made on. Elsewhere, use
+    /// `replay_with_geometry`.
+    pub fn replay(self, speed: ReplaySpeed, tx: &Sender<InputEvent>) -> io::Result<()>
+    where
+        R: Send,
+    {
+        send_all(ReplaySource::new(self, speed), tx)
+    }
+
+    /// Same as `replay`, but the positions are mapped with the geometry of the devices
+    /// the recording was made with, so no digitizer or touchscreen has to be present.
+    pub fn replay_with_geometry(
+        self,
+        speed: ReplaySpeed,
+        wacom: InputGeometry,
+        multitouch: InputGeometry,
+        tx: &Sender<InputEvent>,
+    ) -> io::Result<()>
+    where
+        R: Send,
+    {
+        send_all(
+            ReplaySource::new(self, speed).with_geometry(wacom, multitouch),
+            tx,
+        )
+    }
+}
+
+fn send_all<R: Read + Send>(
+    mut source: ReplaySource<R>,
+    tx: &Sender<InputEvent>,
+) -> io::Result<()> {
+    while let Some(events) = source.read_events()? {
+        for event in events {
+            if let Err(e) = tx.send(event) {
+                error!("Failed to write InputEvent into the channel: {}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
+impl<R: Read> Iterator for InputReplayer<R> {
+    type Item = io::Result<RecordedEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event().transpose()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::ecodes;
+
+    #[test]
+    fn test_roundtrip() {
+        let mut recorder = InputRecorder::new(Vec::new()).unwrap();
+        let events = [
+            (InputDevice::GPIO, EventType::KEY, ecodes::KEY_HOME, 1),
+            (InputDevice::Wacom, EventType::ABSOLUTE, ecodes::ABS_X, -42),
+            (InputDevice::Multitouch, EventType::SYNCHRONIZATION, 0, 0),
+        ];
+        for (device, event_type, code, value) in events {
+            recorder
+                .record(device, &EvInputEvent::new(event_type, code, value))
+                .unwrap();
+        }
+        let bytes = recorder.into_inner().unwrap();
+        assert_eq!(bytes.len(), 9 + 3 * RECORD_LEN);
+
+        let replayed: Vec<RecordedEvent> = InputReplayer::new(&bytes[..])
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(replayed.len(), 3);
+        for (recorded, (device, event_type, code, value)) in replayed.iter().zip(events) {
+            assert_eq!(recorded.device, device);
+            assert_eq!(recorded.event.event_type(), event_type);
+            assert_eq!(recorded.event.code(), code);
+            assert_eq!(recorded.event.value(), value);
+        }
+
+        // A truncated record is an error rather than the end of the recording
+        let mut replayer = InputReplayer::new(&bytes[..bytes.len() - 1]).unwrap();
+        assert!(replayer.nth(2).unwrap().is_err());
+        assert!(InputReplayer::new(&b"NOTINPUT\x01"[..]).is_err());
+    }
+
+    #[test]
+    fn test_replay_with_geometry() {
+        use crate::device::rotate::InputDeviceRotation;
+        use crate::device::InputDevicePlacement;
+        use crate::input::{MultitouchEvent, WacomEvent};
+
+        let mut recorder = InputRecorder::new(Vec::new()).unwrap();
+        let events = [
+            (InputDevice::Wacom, EventType::KEY, ecodes::BTN_TOUCH, 1),
+            (InputDevice::Wacom, EventType::ABSOLUTE, ecodes::ABS_X, 0),
+            (
+                InputDevice::Wacom,
+                EventType::ABSOLUTE,
+                ecodes::ABS_Y,
+                15725,
+            ),
+            (
+                InputDevice::Wacom,
+                EventType::ABSOLUTE,
+                ecodes::ABS_PRESSURE,
+                2048,
+            ),
+            (InputDevice::Wacom, EventType::SYNCHRONIZATION, 0, 0),
+            (
+                InputDevice::Multitouch,
+                EventType::ABSOLUTE,
+                ecodes::ABS_MT_TRACKING_ID,
+                7,
+            ),
+            (
+                InputDevice::Multitouch,
+                EventType::ABSOLUTE,
+                ecodes::ABS_MT_POSITION_X,
+                0,
+            ),
+            (
+                InputDevice::Multitouch,
+                EventType::ABSOLUTE,
+                ecodes::ABS_MT_POSITION_Y,
+                1023,
+            ),
+            (
+                InputDevice::Multitouch,
+                EventType::ABSOLUTE,
+                ecodes::ABS_MT_PRESSURE,
+                100,
+            ),
+            (InputDevice::Multitouch, EventType::SYNCHRONIZATION, 0, 0),
+        ];
+        for (device, event_type, code, value) in events {
+            recorder
+                .record(device, &EvInputEvent::new(event_type, code, value))
+                .unwrap();
+        }
+        let bytes = recorder.into_inner().unwrap();
+
+        // The digitizer of the rM2 and the touchscreen of the rM1
+        let wacom = InputGeometry {
+            orig_size: cgmath::Vector2 { x: 20967, y: 15725 },
+            placement: InputDevicePlacement {
+                rotation: InputDeviceRotation::Rot270,
+                invert_x: false,
+                invert_y: false,
+            },
+            max_pressure: 4095,
+        };
+        let multitouch = InputGeometry {
+            orig_size: cgmath::Vector2 { x: 767, y: 1023 },
+            placement: InputDevicePlacement {
+                rotation: InputDeviceRotation::Rot180,
+                invert_x: false,
+                invert_y: false,
+            },
+            max_pressure: 255,
+        };
+        let (tx, rx) = std::sync::mpsc::channel();
+        InputReplayer::new(&bytes[..])
+            .unwrap()
+            .replay_with_geometry(ReplaySpeed::AsFastAsPossible, wacom, multitouch, &tx)
+            .unwrap();
+        drop(tx);
+        let replayed: Vec<InputEvent> = rx.into_iter().collect();
+        assert_eq!(replayed.len(), 3);
+        match replayed[1] {
+            InputEvent::WacomEvent {
+                event:
+                    WacomEvent::Draw {
+                        position,
+                        pressure,
+                        normalized_pressure,
+                        ..
+                    },
+                ..
+            } => {
+                assert!((position.x - 1404.0).abs() < 0.5);
+                assert!((position.y - 1872.0).abs() < 0.5);
+                assert_eq!(pressure, 2048);
+                assert!((normalized_pressure - 0.5).abs() < 0.001);
+            }
+            ref other => panic!("Expected a draw, got {:?}", other),
+        }
+        match replayed[2] {
+            InputEvent::MultitouchEvent {
+                event: MultitouchEvent::Press { finger },
+                ..
+            } => {
+                assert_eq!(finger.tracking_id, 7);
+                assert!(finger.pos.x.abs_diff(1404) <= 1);
+                assert_eq!(finger.pos.y, 0);
+            }
+            ref other => panic!("Expected a press, got {:?}", other),
+        }
+    }
+}
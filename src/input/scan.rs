@@ -1,5 +1,8 @@
 use super::ecodes;
 use super::InputDevice;
+use crate::device::rotate::CoordinatePart;
+use crate::device::InputDevicePlacement;
+use crate::dimensions::{DISPLAYHEIGHT, DISPLAYWIDTH};
 use cgmath::Vector2;
 use log::debug;
 use std::path::{Path, PathBuf};
@@ -9,6 +12,53 @@ use std::time::Duration;
 
 pub const INITIAL_DEVS_AVAILABLE_FOR: Duration = Duration::from_millis(150);
 
+/// Whether the device looks like a keyboard with letter keys. Keyboards might also
+/// have a power key, which the GPIO buttons are identified by.
+pub fn is_keyboard(dev: &evdev::Device) -> bool {
+    dev.supported_keys().is_some_and(|keys| {
+        keys.contains(evdev::Key::KEY_A)
+            && keys.contains(evdev::Key::KEY_Z)
+            && keys.contains(evdev::Key::KEY_SPACE)
+    })
+}
+
+/// Where the raw positions of the Wacom digitizer or the touchscreen end up on the
+/// display. The decoders use the geometry of the scanned devices, unless they are
+/// given another one (e.g. to replay a recording on a different machine).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct InputGeometry {
+    /// The size the device reports, without the placement applied
+    pub orig_size: Vector2<u16>,
+    pub placement: InputDevicePlacement,
+    pub max_pressure: u16,
+}
+
+impl InputGeometry {
+    /// The size in portrait rotation, before it gets scaled to the display
+    pub fn size(&self) -> Vector2<u16> {
+        self.placement.rotation.rotated_size(&self.orig_size)
+    }
+
+    /// Rotates and inverts a raw coordinate part into portrait rotation
+    pub fn place_part(&self, part: CoordinatePart) -> CoordinatePart {
+        let size = self.size();
+        match self.placement.rotation.rotate_part(part, &self.orig_size) {
+            CoordinatePart::X(x) if self.placement.invert_x => CoordinatePart::X(size.x - x),
+            CoordinatePart::Y(y) if self.placement.invert_y => CoordinatePart::Y(size.y - y),
+            part => part,
+        }
+    }
+
+    /// What a position in portrait rotation is multiplied with to get display coordinates
+    pub fn scale(&self) -> Vector2<f32> {
+        let size = self.size();
+        Vector2 {
+            x: f32::from(DISPLAYWIDTH) / f32::from(size.x),
+            y: f32::from(DISPLAYHEIGHT) / f32::from(size.y),
+        }
+    }
+}
+
 /// A singleton of the EvDevsScan object
 pub static SCANNED: LazyLock<EvDevs> = LazyLock::new(EvDevs::new);
 
@@ -33,6 +83,10 @@ pub struct EvDevs {
     pub wacom_orig_size: Vector2<u16>,
     pub multitouch_orig_size: Vector2<u16>,
 
+    /// The maximum pressure reported by the wacom and the touchscreen
+    pub wacom_max_pressure: u16,
+    pub mt_max_pressure: u16,
+
     // Those will be preserved in case they are needed fairly fast
     // to prevent any additional delay of re-opening the fds.
     // They will get removed fairly quickly though.
@@ -88,6 +142,7 @@ impl EvDevs {
                     .supported_keys()
                     .map(|s| s.contains(evdev::Key::KEY_POWER))
                     .unwrap_or(false)
+                    && !is_keyboard(&dev)
                 {
                     // The device for buttons has the KEY_POWER button and support KEY event types
                     gpio = Some((evdev_path.clone(), dev));
@@ -119,6 +174,7 @@ impl EvDevs {
             x: wacom_state[ecodes::ABS_X as usize].maximum as u16,
             y: wacom_state[ecodes::ABS_Y as usize].maximum as u16,
         };
+        let wacom_max_pressure = (wacom_state[ecodes::ABS_PRESSURE as usize].maximum as u16).max(1);
         // X and Y are swapped for the wacom since rM1 and probably also rM2 have it rotated
         let (wacom_width, wacom_height) = crate::device::CURRENT_DEVICE
             .get_wacom_placement()
@@ -131,6 +187,7 @@ impl EvDevs {
             x: mt_state[ecodes::ABS_MT_POSITION_X as usize].maximum as u16,
             y: mt_state[ecodes::ABS_MT_POSITION_Y as usize].maximum as u16,
         };
+        let mt_max_pressure = (mt_state[ecodes::ABS_MT_PRESSURE as usize].maximum as u16).max(1);
         // Axes are swapped on the rM2 (see InputDeviceRotation for more)
         let (mt_width, mt_height) = crate::device::CURRENT_DEVICE
             .get_multitouch_placement()
@@ -170,6 +227,9 @@ impl EvDevs {
             multitouch_orig_size,
             wacom_orig_size,
 
+            wacom_max_pressure,
+            mt_max_pressure,
+
             wacom_initial_dev,
             multitouch_initial_dev,
             gpio_initial_dev,
@@ -182,6 +242,7 @@ impl EvDevs {
             InputDevice::Wacom => &self.wacom_path,
             InputDevice::Multitouch => &self.multitouch_path,
             InputDevice::GPIO => &self.gpio_path,
+            InputDevice::Keyboard => panic!("Keyboards are not scanned, see KeyboardSource"),
             InputDevice::Unknown => panic!("\"InputDevice::Unkown\" is no device!"),
         }
     }
@@ -193,6 +254,7 @@ impl EvDevs {
             InputDevice::Wacom => self.wacom_initial_dev.clone(),
             InputDevice::Multitouch => self.multitouch_initial_dev.clone(),
             InputDevice::GPIO => self.gpio_initial_dev.clone(),
+            InputDevice::Keyboard => panic!("Keyboards are not scanned, see KeyboardSource"),
             InputDevice::Unknown => panic!("\"InputDevice::Unkown\" is no device!"),
         };
 
@@ -203,4 +265,20 @@ impl EvDevs {
             evdev::Device::open(self.get_path(device))
         }
     }
+
+    pub fn wacom_geometry(&self) -> InputGeometry {
+        InputGeometry {
+            orig_size: self.wacom_orig_size,
+            placement: crate::device::CURRENT_DEVICE.get_wacom_placement(),
+            max_pressure: self.wacom_max_pressure,
+        }
+    }
+
+    pub fn multitouch_geometry(&self) -> InputGeometry {
+        InputGeometry {
+            orig_size: self.multitouch_orig_size,
+            placement: crate::device::CURRENT_DEVICE.get_multitouch_placement(),
+            max_pressure: self.mt_max_pressure,
+        }
+    }
 }
@@ -0,0 +1,549 @@
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::prelude::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use log::{debug, error, info, warn};
+
+use crate::input::ev::SharedDeviceFd;
+use crate::input::record::{InputReplayer, RecordedEvent, ReplaySpeed, SharedInputRecorder};
+use crate::input::scan::{InputGeometry, SCANNED};
+use crate::input::{self, InputDevice, InputDeviceState, InputEvent};
+
+/// Something an `EvDevContext` can read `InputEvent`s from.
+///
+/// `read_events` is called in a loop on the context's thread, so it should block
+/// until there is something to return. Returning `Ok(None)` ends the thread, and an
+/// error ends it after reporting an `InputEvent::DeviceError`.
+pub trait InputSource: Send {
+    fn read_events(&mut self) -> io::Result<Option<Vec<InputEvent>>>;
+
+    /// Called by the `EvDevContext` every time before it starts reading. Sources that
+    /// block for a while should return from `read_events` soon after the signal is
+    /// raised, as stopping the context waits for that.
+    fn set_stop_signal(&mut self, _stop: StopSignal) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An eventfd that becomes readable once the `EvDevContext` running a source is stopped.
+/// Sources waiting in epoll can add it to their set.
+#[derive(Clone)]
+pub struct StopSignal(Arc<OwnedFd>);
+
+impl StopSignal {
+    pub fn new() -> io::Result<StopSignal> {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(StopSignal(Arc::new(unsafe { OwnedFd::from_raw_fd(fd) })))
+    }
+
+    /// Stays raised for good, a new signal is used for every start
+    pub fn raise(&self) {
+        let one: u64 = 1;
+        let res = unsafe {
+            libc::write(
+                self.as_raw_fd(),
+                &one as *const u64 as *const libc::c_void,
+                std::mem::size_of::<u64>(),
+            )
+        };
+        if res < 0 {
+            warn!(
+                "Failed to raise stop signal: {}",
+                io::Error::last_os_error()
+            );
+        }
+    }
+
+    pub fn is_raised(&self) -> bool {
+        self.wait(None, Some(Duration::ZERO)).unwrap_or(false)
+    }
+
+    /// Waits until either the signal is raised, `fd` becomes readable or `timeout`
+    /// passes. Returns whether the signal was raised.
+    pub fn wait(&self, fd: Option<RawFd>, timeout: Option<Duration>) -> io::Result<bool> {
+        let mut fds = [
+            libc::pollfd {
+                fd: self.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: fd.unwrap_or(-1),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        let timeout = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+        let res = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+        if res < 0 {
+            let err = io::Error::last_os_error();
+            return match err.kind() {
+                io::ErrorKind::Interrupted => Ok(false),
+                _ => Err(err),
+            };
+        }
+        Ok(fds[0].revents & libc::POLLIN != 0)
+    }
+}
+
+impl AsRawFd for StopSignal {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+/// Keeps one decoder state per device, for sources of raw events from several devices
+struct Decoders {
+    wacom: InputDeviceState,
+    multitouch: InputDeviceState,
+    gpio: InputDeviceState,
+    keyboard: InputDeviceState,
+}
+
+impl Default for Decoders {
+    fn default() -> Self {
+        Decoders {
+            wacom: InputDeviceState::new(InputDevice::Wacom),
+            multitouch: InputDeviceState::new(InputDevice::Multitouch),
+            gpio: InputDeviceState::new(InputDevice::GPIO),
+            keyboard: InputDeviceState::new(InputDevice::Keyboard),
+        }
+    }
+}
+
+impl Decoders {
+    fn with_geometry(wacom: InputGeometry, multitouch: InputGeometry) -> Decoders {
+        Decoders {
+            wacom: InputDeviceState::with_geometry(InputDevice::Wacom, wacom),
+            multitouch: InputDeviceState::with_geometry(InputDevice::Multitouch, multitouch),
+            ..Default::default()
+        }
+    }
+
+    /// Decodes `recorded` as if the kernel reported it at `time`
+    fn decode(&self, recorded: &RecordedEvent, time: SystemTime) -> Vec<InputEvent> {
+        let state = match recorded.device {
+            InputDevice::Wacom => &self.wacom,
+            InputDevice::Multitouch => &self.multitouch,
+            InputDevice::GPIO => &self.gpio,
+            InputDevice::Keyboard => &self.keyboard,
+            InputDevice::Unknown => return vec![],
+        };
+        decode(recorded.device, &recorded.event_at(time), state)
+    }
+}
+
+pub(crate) fn decode(
+    device: InputDevice,
+    ev: &evdev::InputEvent,
+    state: &InputDeviceState,
+) -> Vec<InputEvent> {
+    match device {
+        InputDevice::Multitouch => input::multitouch::decode(ev, state),
+        InputDevice::Wacom => input::wacom::decode(ev, state).into_iter().collect(),
+        InputDevice::GPIO => input::gpio::decode(ev, state).into_iter().collect(),
+        InputDevice::Keyboard => input::keyboard::decode(ev, state).into_iter().collect(),
+        InputDevice::Unknown => vec![],
+    }
+}
+
+/// Epoll data of the device fd in `EvdevSource`
+const DEVICE_TOKEN: u64 = 0;
+/// Epoll data of the `StopSignal` in the sources waiting in epoll
+pub(crate) const STOP_TOKEN: u64 = u64::MAX - 1;
+
+const REOPEN_ATTEMPTS: u32 = 10;
+const REOPEN_DELAY: Duration = Duration::from_millis(500);
+
+/// Reads and decodes the events of one of the devices found by `SCANNED`.
+///
+/// When reading fails (e.g. with ENODEV after a suspend), the device is reopened.
+/// Only if that keeps failing for a few seconds the error is returned.
+pub struct EvdevSource {
+    device: InputDevice,
+    dev: evdev::Device,
+    /// Follows `dev` when it is reopened, so grabs move along
+    fd: SharedDeviceFd,
+    epfd: i32,
+    state: InputDeviceState,
+    recorder: SharedInputRecorder,
+    stop: Option<StopSignal>,
+}
+
+pub(crate) fn epoll_add(epfd: i32, fd: RawFd, token: u64) -> io::Result<()> {
+    let event = epoll::Event {
+        events: (epoll::Events::EPOLLIN | epoll::Events::EPOLLPRI).bits(),
+        data: token,
+    };
+    epoll::ctl(epfd, epoll::ControlOptions::EPOLL_CTL_ADD, fd, event)
+}
+
+/// Needed before closing an fd that may have been duplicated, as epoll only forgets
+/// about it once every duplicate is closed
+pub(crate) fn epoll_del(epfd: i32, fd: RawFd) {
+    let event = epoll::Event { events: 0, data: 0 };
+    if let Err(e) = epoll::ctl(epfd, epoll::ControlOptions::EPOLL_CTL_DEL, fd, event) {
+        debug!("Failed to remove fd {} from the epoll set: {}", fd, e);
+    }
+}
+
+impl EvdevSource {
+    pub fn open(
+        device: InputDevice,
+        state: InputDeviceState,
+        recorder: SharedInputRecorder,
+    ) -> io::Result<EvdevSource> {
+        let dev = SCANNED.get_device(device).map_err(io::Error::other)?;
+        let epfd = epoll::create(true)?;
+        if let Err(e) = epoll_add(epfd, dev.as_raw_fd(), DEVICE_TOKEN) {
+            let _ = epoll::close(epfd);
+            return Err(e);
+        }
+
+        info!("Init complete for {:?}", SCANNED.get_path(device));
+        let source = EvdevSource {
+            device,
+            dev,
+            fd: SharedDeviceFd::default(),
+            epfd,
+            state,
+            recorder,
+            stop: None,
+        };
+        source.share_fd();
+        Ok(source)
+    }
+
+    fn reopen(&mut self, cause: io::Error) -> io::Result<()> {
+        let path = SCANNED.get_path(self.device);
+        warn!("Failed to read from {:?}, reopening it: {}", path, cause);
+        for _ in 0..REOPEN_ATTEMPTS {
+            match self.stop {
+                Some(ref stop) => {
+                    if stop.wait(None, Some(REOPEN_DELAY))? {
+                        return Ok(());
+                    }
+                }
+                None => std::thread::sleep(REOPEN_DELAY),
+            }
+            match evdev::Device::open(path) {
+                Ok(dev) => {
+                    epoll_del(self.epfd, self.dev.as_raw_fd());
+                    epoll_add(self.epfd, dev.as_raw_fd(), DEVICE_TOKEN)?;
+                    self.dev = dev;
+                    self.share_fd();
+                    info!("Reopened {:?}", path);
+                    return Ok(());
+                }
+                Err(e) => debug!("Failed to reopen {:?}: {}", path, e),
+            }
+        }
+        Err(cause)
+    }
+
+    /// The fd of the device being read, to grab it from another thread
+    pub(crate) fn device_fd(&self) -> SharedDeviceFd {
+        self.fd.clone()
+    }
+
+    /// Puts a duplicate of the fd of `dev` into `fd`
+    fn share_fd(&self) {
+        // The device keeps its fd open for as long as it exists
+        match unsafe { BorrowedFd::borrow_raw(self.dev.as_raw_fd()) }.try_clone_to_owned() {
+            Ok(fd) => self.fd.set(self.device, Some(fd)),
+            Err(e) => {
+                warn!("Grabbing {:?} won't be possible: {}", self.device, e);
+                self.fd.set(self.device, None);
+            }
+        }
+    }
+}
+
+impl InputSource for EvdevSource {
+    fn read_events(&mut self) -> io::Result<Option<Vec<InputEvent>>> {
+        let mut ready = [epoll::Event { events: 0, data: 0 }; 2];
+        // Waiting indefinitely is fine, stopping wakes us up through the stop signal
+        let count = loop {
+            match epoll::wait(self.epfd, -1, &mut ready) {
+                Ok(count) => break count,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        };
+        if ready[..count].iter().any(|e| e.data == STOP_TOKEN) {
+            return Ok(Some(vec![]));
+        }
+
+        let fetched: io::Result<Vec<evdev::InputEvent>> =
+            self.dev.fetch_events().map(Iterator::collect);
+        let fetched = match fetched {
+            Ok(fetched) => fetched,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => vec![],
+            Err(e) => {
+                self.reopen(e)?;
+                vec![]
+            }
+        };
+        let mut events = vec![];
+        for ev in fetched {
+            if let Some(ref mut recorder) = *self.recorder.lock().unwrap() {
+                if let Err(e) = recorder.record(self.device, &ev) {
+                    error!("Failed to record input event: {}", e);
+                }
+            }
+            events.extend(decode(self.device, &ev, &self.state));
+        }
+        Ok(Some(events))
+    }
+
+    fn set_stop_signal(&mut self, stop: StopSignal) -> io::Result<()> {
+        epoll_add(self.epfd, stop.as_raw_fd(), STOP_TOKEN)?;
+        // A previous signal leaves the epoll set once its last copy is dropped
+        self.stop = Some(stop);
+        Ok(())
+    }
+}
+
+impl Drop for EvdevSource {
+    fn drop(&mut self) {
+        let _ = epoll::close(self.epfd);
+    }
+}
+
+/// Replays a recording made with `InputRecorder` through the decoders
+pub struct ReplaySource<R: Read + Send> {
+    replayer: InputReplayer<R>,
+    speed: ReplaySpeed,
+    decoders: Decoders,
+    started: Option<(Instant, SystemTime)>,
+    stop: Option<StopSignal>,
+}
+
+impl<R: Read + Send> ReplaySource<R> {
+    pub fn new(replayer: InputReplayer<R>, speed: ReplaySpeed) -> ReplaySource<R> {
+        ReplaySource {
+            replayer,
+            speed,
+            decoders: Decoders::default(),
+            started: None,
+            stop: None,
+        }
+    }
+
+    /// Maps the positions with the given geometry instead of the one of the devices
+    /// found by `SCANNED`, so recordings can be replayed on other machines
+    pub fn with_geometry(mut self, wacom: InputGeometry, multitouch: InputGeometry) -> Self {
+        self.decoders = Decoders::with_geometry(wacom, multitouch);
+        self
+    }
+}
+
+impl<R: Read + Send> InputSource for ReplaySource<R> {
+    fn read_events(&mut self) -> io::Result<Option<Vec<InputEvent>>> {
+        let recorded = match self.replayer.next_event()? {
+            Some(recorded) => recorded,
+            None => return Ok(None),
+        };
+        let (started, started_at) = *self
+            .started
+            .get_or_insert_with(|| (Instant::now(), SystemTime::now()));
+        if self.speed == ReplaySpeed::RealTime {
+            let delay = (started + recorded.timestamp).saturating_duration_since(Instant::now());
+            match self.stop {
+                // Stopping cuts the delay short, the event is still delivered
+                Some(ref stop) => {
+                    stop.wait(None, Some(delay))?;
+                }
+                None => std::thread::sleep(delay),
+            }
+        }
+        // The events keep their recorded spacing, even when replayed faster
+        let time = started_at + recorded.timestamp;
+        Ok(Some(self.decoders.decode(&recorded, time)))
+    }
+
+    fn set_stop_signal(&mut self, stop: StopSignal) -> io::Result<()> {
+        self.stop = Some(stop);
+        Ok(())
+    }
+}
+
+/// Listens on a Unix socket for clients sending raw events in the format written by
+/// `InputRecorder`. Clients are served one after another and their events are decoded
+/// as soon as they arrive, regardless of the recorded timestamps.
+pub struct UnixSocketSource {
+    path: PathBuf,
+    listener: UnixListener,
+    client: Option<InputReplayer<UnixStream>>,
+    client_fd: RawFd,
+    decoders: Decoders,
+    stop: Option<StopSignal>,
+}
+
+impl UnixSocketSource {
+    /// Binds to `path`, replacing a socket left behind by a process that is gone. The
+    /// socket is removed again when the source is dropped.
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<UnixSocketSource> {
+        let path = path.as_ref();
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            // Nobody is listening on it anymore if connecting fails
+            if metadata.file_type().is_socket() && UnixStream::connect(path).is_err() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(UnixSocketSource {
+            listener: UnixListener::bind(path)?,
+            path: path.to_owned(),
+            client: None,
+            client_fd: -1,
+            decoders: Decoders::default(),
+            stop: None,
+        })
+    }
+}
+
+impl Drop for UnixSocketSource {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Failed to remove the input socket {:?}: {}", self.path, e);
+        }
+    }
+}
+
+impl InputSource for UnixSocketSource {
+    fn read_events(&mut self) -> io::Result<Option<Vec<InputEvent>>> {
+        if let Some(ref stop) = self.stop {
+            let fd = match self.client {
+                Some(_) => self.client_fd,
+                None => self.listener.as_raw_fd(),
+            };
+            if stop.wait(Some(fd), None)? {
+                return Ok(Some(vec![]));
+            }
+        }
+
+        let client = match self.client {
+            Some(ref mut client) => client,
+            None => {
+                let (stream, _) = self.listener.accept()?;
+                self.client_fd = stream.as_raw_fd();
+                match InputReplayer::new(stream) {
+                    Ok(client) => self.client.insert(client),
+                    Err(e) => {
+                        warn!("Rejected input socket client: {}", e);
+                        return Ok(Some(vec![]));
+                    }
+                }
+            }
+        };
+        match client.next_event() {
+            Ok(Some(recorded)) => Ok(Some(self.decoders.decode(&recorded, SystemTime::now()))),
+            Ok(None) => {
+                self.client = None;
+                Ok(Some(vec![]))
+            }
+            Err(e) => {
+                warn!("Dropping input socket client: {}", e);
+                self.client = None;
+                Ok(Some(vec![]))
+            }
+        }
+    }
+
+    fn set_stop_signal(&mut self, stop: StopSignal) -> io::Result<()> {
+        self.stop = Some(stop);
+        Ok(())
+    }
+}
+
+/// Produces already decoded events from a closure, until it returns `None`.
+/// Stopping the context waits for the closure to return.
+pub struct SyntheticSource<F: FnMut() -> Option<Vec<InputEvent>> + Send> {
+    generator: F,
+}
+
+impl<F: FnMut() -> Option<Vec<InputEvent>> + Send> SyntheticSource<F> {
+    pub fn new(generator: F) -> SyntheticSource<F> {
+        SyntheticSource { generator }
+    }
+}
+
+impl<F: FnMut() -> Option<Vec<InputEvent>> + Send> InputSource for SyntheticSource<F> {
+    fn read_events(&mut self) -> io::Result<Option<Vec<InputEvent>>> {
+        Ok((self.generator)())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::ecodes;
+    use crate::input::record::InputRecorder;
+    use crate::input::{GPIOEvent, PhysicalButton};
+    use evdev::EventType;
+
+    #[test]
+    fn test_stop_signal() {
+        let stop = StopSignal::new().unwrap();
+        assert!(!stop.is_raised());
+        assert!(!stop.wait(None, Some(Duration::from_millis(1))).unwrap());
+
+        let waiter = {
+            let stop = stop.clone();
+            std::thread::spawn(move || stop.wait(None, None).unwrap())
+        };
+        stop.raise();
+        assert!(waiter.join().unwrap());
+        // Stays raised
+        assert!(stop.is_raised());
+    }
+
+    #[test]
+    fn test_replay_source_decodes() {
+        let mut recorder = InputRecorder::new(Vec::new()).unwrap();
+        for value in [1, 0] {
+            let ev = evdev::InputEvent::new(EventType::KEY, ecodes::KEY_POWER, value);
+            recorder.record(InputDevice::GPIO, &ev).unwrap();
+        }
+        let bytes = recorder.into_inner().unwrap();
+
+        let replayer = InputReplayer::new(&bytes[..]).unwrap();
+        let mut source = ReplaySource::new(replayer, ReplaySpeed::AsFastAsPossible);
+        let button = PhysicalButton::POWER;
+        let mut next_gpio = || match source.read_events().unwrap().as_deref() {
+            Some([InputEvent::GPIO { event, timestamp }]) => Some((*event, *timestamp)),
+            Some(events) => panic!("Unexpected events {events:?}"),
+            None => None,
+        };
+        let (press, pressed_at) = next_gpio().unwrap();
+        assert_eq!(press, GPIOEvent::Press { button });
+        let (unpress, unpressed_at) = next_gpio().unwrap();
+        assert_eq!(unpress, GPIOEvent::Unpress { button });
+        assert!(pressed_at <= unpressed_at);
+        assert_eq!(next_gpio(), None);
+    }
+
+    #[test]
+    fn test_unix_socket_replaces_stale_socket() {
+        let path =
+            std::env::temp_dir().join(format!("libremarkable-input-{}.sock", std::process::id()));
+        let _ = fs::remove_file(&path);
+        // A listener that goes away without removing its socket
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let source = UnixSocketSource::bind(&path).unwrap();
+        // It is still in use now
+        assert!(UnixSocketSource::bind(&path).is_err());
+        drop(source);
+        assert!(!path.exists());
+    }
+}
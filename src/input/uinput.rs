@@ -0,0 +1,318 @@
+use std::io;
+use std::thread::sleep;
+use std::time::Duration;
+
+use evdev::uinput::{VirtualDevice, VirtualDeviceBuilder};
+use evdev::{
+    AbsInfo, AbsoluteAxisType, AttributeSet, EventType, InputEvent as EvInputEvent, Key,
+    RelativeAxisType, UinputAbsSetup,
+};
+
+use super::ecodes;
+use crate::input::scan::{InputGeometry, SCANNED};
+use crate::input::PhysicalButton;
+
+fn abs(code: u16, minimum: i32, maximum: i32) -> UinputAbsSetup {
+    UinputAbsSetup::new(
+        AbsoluteAxisType(code),
+        AbsInfo::new(0, minimum, maximum, 0, 0, 0),
+    )
+}
+
+fn ev(event_type: EventType, code: u16, value: i32) -> EvInputEvent {
+    EvInputEvent::new(event_type, code, value)
+}
+
+/// Nothing may be emitted for an empty path, as the lift would have no matching press
+fn check_points<T>(points: &[T]) -> io::Result<()> {
+    match points.is_empty() {
+        true => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "At least one point is needed",
+        )),
+        false => Ok(()),
+    }
+}
+
+/// Maps a framebuffer coordinate back into the raw coordinates of a digitizer,
+/// undoing what the decoders do.
+fn display_to_raw(position: cgmath::Point2<f32>, geometry: &InputGeometry) -> cgmath::Point2<u16> {
+    let size = geometry.size();
+    let scale = geometry.scale();
+    let unscale = |value: f32, scale: f32, raw: u16| {
+        (value / scale).round().clamp(0.0, f32::from(raw)) as u16
+    };
+    let rotated = cgmath::Point2 {
+        x: unscale(position.x, scale.x, size.x),
+        y: unscale(position.y, scale.y, size.y),
+    };
+    geometry.placement.unapply(&rotated, &geometry.orig_size)
+}
+
+/// A virtual Wacom digitizer, multitouch screen and set of buttons created through
+/// `/dev/uinput`. They advertise the capabilities `input::scan::EvDevs` looks for and
+/// the axis ranges of the real devices, so other apps (including xochitl) can't tell
+/// their events apart from real input.
+///
+/// The high level calls block for as long as the simulated input takes.
+pub struct VirtualInput {
+    wacom: VirtualDevice,
+    multitouch: VirtualDevice,
+    buttons: VirtualDevice,
+    next_tracking_id: i32,
+    /// Time between two reported points of a stroke or swipe
+    pub step: Duration,
+    /// How long taps and button presses are held
+    pub hold: Duration,
+}
+
+impl VirtualInput {
+    pub fn new() -> io::Result<VirtualInput> {
+        Ok(VirtualInput {
+            wacom: Self::build_wacom()?,
+            multitouch: Self::build_multitouch()?,
+            buttons: Self::build_buttons()?,
+            next_tracking_id: 0,
+            step: Duration::from_millis(8),
+            hold: Duration::from_millis(60),
+        })
+    }
+
+    fn build_wacom() -> io::Result<VirtualDevice> {
+        let size = SCANNED.wacom_orig_size;
+        let mut keys = AttributeSet::<Key>::new();
+        for key in [
+            ecodes::BTN_TOOL_PEN,
+            ecodes::BTN_TOOL_RUBBER,
+            ecodes::BTN_TOUCH,
+            ecodes::BTN_STYLUS,
+            ecodes::BTN_STYLUS2,
+        ] {
+            keys.insert(Key::new(key));
+        }
+        VirtualDeviceBuilder::new()?
+            .name("libremarkable virtual wacom")
+            .with_keys(&keys)?
+            .with_absolute_axis(&abs(ecodes::ABS_X, 0, i32::from(size.x)))?
+            .with_absolute_axis(&abs(ecodes::ABS_Y, 0, i32::from(size.y)))?
+            .with_absolute_axis(&abs(
+                ecodes::ABS_PRESSURE,
+                0,
+                i32::from(SCANNED.wacom_max_pressure),
+            ))?
+            .with_absolute_axis(&abs(ecodes::ABS_DISTANCE, 0, 255))?
+            .with_absolute_axis(&abs(ecodes::ABS_TILT_X, -9000, 9000))?
+            .with_absolute_axis(&abs(ecodes::ABS_TILT_Y, -9000, 9000))?
+            .build()
+    }
+
+    fn build_multitouch() -> io::Result<VirtualDevice> {
+        let size = SCANNED.multitouch_orig_size;
+        // The scan identifies the touchscreen by it supporting relative events
+        let mut rel = AttributeSet::<RelativeAxisType>::new();
+        rel.insert(RelativeAxisType::REL_X);
+        VirtualDeviceBuilder::new()?
+            .name("libremarkable virtual multitouch")
+            .with_relative_axes(&rel)?
+            .with_absolute_axis(&abs(ecodes::ABS_MT_SLOT, 0, 31))?
+            .with_absolute_axis(&abs(ecodes::ABS_MT_TOUCH_MAJOR, 0, 255))?
+            .with_absolute_axis(&abs(ecodes::ABS_MT_TOUCH_MINOR, 0, 255))?
+            .with_absolute_axis(&abs(ecodes::ABS_MT_ORIENTATION, -127, 127))?
+            .with_absolute_axis(&abs(ecodes::ABS_MT_POSITION_X, 0, i32::from(size.x)))?
+            .with_absolute_axis(&abs(ecodes::ABS_MT_POSITION_Y, 0, i32::from(size.y)))?
+            .with_absolute_axis(&abs(ecodes::ABS_MT_TRACKING_ID, 0, 65535))?
+            .with_absolute_axis(&abs(
+                ecodes::ABS_MT_PRESSURE,
+                0,
+                i32::from(SCANNED.mt_max_pressure),
+            ))?
+            .build()
+    }
+
+    fn build_buttons() -> io::Result<VirtualDevice> {
+        let mut keys = AttributeSet::<Key>::new();
+        for key in [
+            ecodes::KEY_HOME,
+            ecodes::KEY_LEFT,
+            ecodes::KEY_RIGHT,
+            ecodes::KEY_POWER,
+            ecodes::KEY_WAKEUP,
+        ] {
+            keys.insert(Key::new(key));
+        }
+        VirtualDeviceBuilder::new()?
+            .name("libremarkable virtual buttons")
+            .with_keys(&keys)?
+            .build()
+    }
+
+    /// Draws a stroke through `points`, given in framebuffer coordinates along with
+    /// a pressure between 0 and 1. Fails with `ErrorKind::InvalidInput` without points.
+    pub fn draw_stroke(&mut self, points: &[(cgmath::Point2<f32>, f32)]) -> io::Result<()> {
+        check_points(points)?;
+        let geometry = SCANNED.wacom_geometry();
+
+        self.wacom
+            .emit(&[ev(EventType::KEY, ecodes::BTN_TOOL_PEN, 1)])?;
+        for (i, &(position, pressure)) in points.iter().enumerate() {
+            let raw = display_to_raw(position, &geometry);
+            let pressure = (pressure.clamp(0.0, 1.0) * f32::from(geometry.max_pressure)) as i32;
+            let mut events = vec![
+                ev(EventType::ABSOLUTE, ecodes::ABS_X, i32::from(raw.x)),
+                ev(EventType::ABSOLUTE, ecodes::ABS_Y, i32::from(raw.y)),
+                ev(EventType::ABSOLUTE, ecodes::ABS_PRESSURE, pressure),
+            ];
+            if i == 0 {
+                events.push(ev(EventType::KEY, ecodes::BTN_TOUCH, 1));
+            }
+            self.wacom.emit(&events)?;
+            sleep(self.step);
+        }
+        self.wacom.emit(&[
+            ev(EventType::ABSOLUTE, ecodes::ABS_PRESSURE, 0),
+            ev(EventType::KEY, ecodes::BTN_TOUCH, 0),
+        ])?;
+        self.wacom
+            .emit(&[ev(EventType::KEY, ecodes::BTN_TOOL_PEN, 0)])
+    }
+
+    /// Touches the display at `position` (in framebuffer coordinates) and lifts the finger again
+    pub fn tap(&mut self, position: cgmath::Point2<u16>) -> io::Result<()> {
+        let position = position.cast().unwrap();
+        self.swipe(&[position])
+    }
+
+    /// Moves a single finger along `points`, given in framebuffer coordinates. Fails with
+    /// `ErrorKind::InvalidInput` without points.
+    pub fn swipe(&mut self, points: &[cgmath::Point2<f32>]) -> io::Result<()> {
+        check_points(points)?;
+        let geometry = SCANNED.multitouch_geometry();
+        let tracking_id = self.next_tracking_id;
+        self.next_tracking_id = (self.next_tracking_id + 1) % 65536;
+
+        for (i, &position) in points.iter().enumerate() {
+            let raw = display_to_raw(position, &geometry);
+            let mut events = vec![ev(EventType::ABSOLUTE, ecodes::ABS_MT_SLOT, 0)];
+            if i == 0 {
+                events.extend([
+                    ev(EventType::ABSOLUTE, ecodes::ABS_MT_TRACKING_ID, tracking_id),
+                    ev(EventType::ABSOLUTE, ecodes::ABS_MT_TOUCH_MAJOR, 10),
+                    ev(EventType::ABSOLUTE, ecodes::ABS_MT_TOUCH_MINOR, 10),
+                    ev(
+                        EventType::ABSOLUTE,
+                        ecodes::ABS_MT_PRESSURE,
+                        i32::from(geometry.max_pressure / 2),
+                    ),
+                ]);
+            }
+            events.extend([
+                ev(
+                    EventType::ABSOLUTE,
+                    ecodes::ABS_MT_POSITION_X,
+                    i32::from(raw.x),
+                ),
+                ev(
+                    EventType::ABSOLUTE,
+                    ecodes::ABS_MT_POSITION_Y,
+                    i32::from(raw.y),
+                ),
+            ]);
+            self.multitouch.emit(&events)?;
+            sleep(if points.len() == 1 {
+                self.hold
+            } else {
+                self.step
+            });
+        }
+        self.multitouch.emit(&[
+            ev(EventType::ABSOLUTE, ecodes::ABS_MT_SLOT, 0),
+            ev(EventType::ABSOLUTE, ecodes::ABS_MT_TRACKING_ID, -1),
+        ])
+    }
+
+    /// Presses and releases `button`
+    pub fn press_button(&mut self, button: PhysicalButton) -> io::Result<()> {
+        let key = match button {
+            PhysicalButton::LEFT => ecodes::KEY_LEFT,
+            PhysicalButton::MIDDLE => ecodes::KEY_HOME,
+            PhysicalButton::RIGHT => ecodes::KEY_RIGHT,
+            PhysicalButton::POWER => ecodes::KEY_POWER,
+            PhysicalButton::WAKEUP => ecodes::KEY_WAKEUP,
+        };
+        self.buttons.emit(&[ev(EventType::KEY, key, 1)])?;
+        sleep(self.hold);
+        self.buttons.emit(&[ev(EventType::KEY, key, 0)])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::rotate::{CoordinatePart, InputDeviceRotation};
+    use crate::device::InputDevicePlacement;
+
+    /// What the decoders do with the raw position
+    fn raw_to_display(raw: cgmath::Point2<u16>, geometry: &InputGeometry) -> cgmath::Point2<f32> {
+        let mut placed = cgmath::Point2 { x: 0, y: 0 };
+        for part in [CoordinatePart::X(raw.x), CoordinatePart::Y(raw.y)] {
+            match geometry.place_part(part) {
+                CoordinatePart::X(x) => placed.x = x,
+                CoordinatePart::Y(y) => placed.y = y,
+            }
+        }
+        let scale = geometry.scale();
+        cgmath::Point2 {
+            x: f32::from(placed.x) * scale.x,
+            y: f32::from(placed.y) * scale.y,
+        }
+    }
+
+    #[test]
+    fn test_display_to_raw_roundtrip() {
+        let geometry = |x, y, rotation, invert_x| InputGeometry {
+            orig_size: cgmath::Vector2 { x, y },
+            placement: InputDevicePlacement {
+                rotation,
+                invert_x,
+                invert_y: false,
+            },
+            max_pressure: 255,
+        };
+        // The digitizer of both models and the touchscreens of the rM1 and rM2
+        let geometries = [
+            geometry(20967, 15725, InputDeviceRotation::Rot270, false),
+            geometry(767, 1023, InputDeviceRotation::Rot180, false),
+            geometry(1403, 1871, InputDeviceRotation::Rot180, true),
+        ];
+        let points = [
+            (0.0, 0.0),
+            (100.0, 1800.0),
+            (702.0, 936.0),
+            (1404.0, 1872.0),
+        ];
+        for geometry in &geometries {
+            for (x, y) in points {
+                let raw = display_to_raw(cgmath::Point2 { x, y }, geometry);
+                let decoded = raw_to_display(raw, geometry);
+                let tolerance = geometry.scale().x.max(geometry.scale().y);
+                assert!(
+                    (decoded.x - x).abs() <= tolerance && (decoded.y - y).abs() <= tolerance,
+                    "{:?} came back as {:?} with {:?}",
+                    (x, y),
+                    decoded,
+                    geometry
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_paths_are_rejected() {
+        let points: [cgmath::Point2<f32>; 0] = [];
+        assert_eq!(
+            check_points(&points).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert!(check_points(&[cgmath::Point2 { x: 1.0, y: 1.0 }]).is_ok());
+    }
+}
@@ -1,29 +1,30 @@
 use super::ecodes;
 use crate::device::rotate::CoordinatePart;
-use crate::device::CURRENT_DEVICE;
-use crate::input::scan::SCANNED;
+use crate::input::calibration;
+use crate::input::scan::{InputGeometry, SCANNED};
 use crate::input::{InputDeviceState, InputEvent, WacomEvent, WacomPen};
 use evdev::InputEvent as EvInputEvent;
 use log::debug;
-use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU16, AtomicU32, Ordering};
 use std::sync::LazyLock;
 
 use crate::cgmath;
-use crate::dimensions::{DISPLAYHEIGHT, DISPLAYWIDTH, WACOMHEIGHT, WACOMWIDTH};
 
-static WACOM_HSCALAR: LazyLock<f32> =
-    LazyLock::new(|| f32::from(DISPLAYWIDTH) / f32::from(*WACOMWIDTH));
-static WACOM_VSCALAR: LazyLock<f32> =
-    LazyLock::new(|| f32::from(DISPLAYHEIGHT) / f32::from(*WACOMHEIGHT));
+static WACOM_GEOMETRY: LazyLock<InputGeometry> = LazyLock::new(|| SCANNED.wacom_geometry());
 
 pub struct WacomState {
     last_x: AtomicU16,
     last_y: AtomicU16,
-    last_xtilt: AtomicU16,
-    last_ytilt: AtomicU16,
+    last_xtilt: AtomicI32,
+    last_ytilt: AtomicI32,
     last_dist: AtomicU16,
     last_pressure: AtomicU16,
     last_touch_state: AtomicBool,
+    /// 0 while the digitizer reports none
+    last_tool_id: AtomicU32,
+    last_serial: AtomicU32,
+    /// `None` to use the geometry of the scanned digitizer
+    geometry: Option<InputGeometry>,
 }
 
 impl ::std::default::Default for WacomState {
@@ -31,48 +32,97 @@ impl ::std::default::Default for WacomState {
         WacomState {
             last_x: AtomicU16::new(0),
             last_y: AtomicU16::new(0),
-            last_xtilt: AtomicU16::new(0),
-            last_ytilt: AtomicU16::new(0),
+            last_xtilt: AtomicI32::new(0),
+            last_ytilt: AtomicI32::new(0),
             last_dist: AtomicU16::new(0),
             last_pressure: AtomicU16::new(0),
             last_touch_state: AtomicBool::new(false),
+            last_tool_id: AtomicU32::new(0),
+            last_serial: AtomicU32::new(0),
+            geometry: None,
         }
     }
 }
 
+impl WacomState {
+    /// A state that maps positions with `geometry` instead of the one of the scanned digitizer
+    pub fn with_geometry(geometry: InputGeometry) -> WacomState {
+        WacomState {
+            geometry: Some(geometry),
+            ..Default::default()
+        }
+    }
+
+    fn geometry(&self) -> InputGeometry {
+        self.geometry.unwrap_or_else(|| *WACOM_GEOMETRY)
+    }
+
+    /// In display coordinates, with the calibration applied
+    fn position(&self) -> cgmath::Point2<f32> {
+        let scale = self.geometry().scale();
+        let position = cgmath::Point2 {
+            x: (f32::from(self.last_x.load(Ordering::Relaxed)) * scale.x),
+            y: (f32::from(self.last_y.load(Ordering::Relaxed)) * scale.y),
+        };
+        let calibration = calibration::current().wacom;
+        if calibration.is_identity() {
+            position
+        } else {
+            calibration.apply(position)
+        }
+    }
+
+    /// The digitizer reports tilt in hundredths of a degree
+    fn tilt(&self) -> cgmath::Vector2<f32> {
+        cgmath::Vector2 {
+            x: self.last_xtilt.load(Ordering::Relaxed) as f32 / 100.0,
+            y: self.last_ytilt.load(Ordering::Relaxed) as f32 / 100.0,
+        }
+    }
+
+    fn tool_id(&self) -> Option<u32> {
+        Some(self.last_tool_id.load(Ordering::Relaxed)).filter(|&id| id != 0)
+    }
+
+    fn serial(&self) -> Option<u32> {
+        Some(self.last_serial.load(Ordering::Relaxed)).filter(|&serial| serial != 0)
+    }
+}
+
 pub fn decode(ev: &EvInputEvent, outer_state: &InputDeviceState) -> Option<InputEvent> {
     let state = match outer_state {
         InputDeviceState::WacomState(ref state_arc) => state_arc,
         _ => unreachable!(),
     };
+    let timestamp = ev.timestamp();
     match ev.event_type().0 {
         ecodes::EV_SYN => match state.last_touch_state.load(Ordering::Relaxed) {
             false => Some(InputEvent::WacomEvent {
                 event: WacomEvent::Hover {
-                    position: cgmath::Point2 {
-                        x: (f32::from(state.last_x.load(Ordering::Relaxed)) * *WACOM_HSCALAR),
-                        y: (f32::from(state.last_y.load(Ordering::Relaxed)) * *WACOM_VSCALAR),
-                    },
+                    position: state.position(),
                     distance: state.last_dist.load(Ordering::Relaxed),
-                    tilt: cgmath::Vector2 {
-                        x: state.last_xtilt.load(Ordering::Relaxed),
-                        y: state.last_ytilt.load(Ordering::Relaxed),
-                    },
+                    tilt: state.tilt(),
+                    tool_id: state.tool_id(),
+                    serial: state.serial(),
                 },
+                timestamp,
             }),
-            true => Some(InputEvent::WacomEvent {
-                event: WacomEvent::Draw {
-                    position: cgmath::Point2 {
-                        x: (f32::from(state.last_x.load(Ordering::Relaxed)) * *WACOM_HSCALAR),
-                        y: (f32::from(state.last_y.load(Ordering::Relaxed)) * *WACOM_VSCALAR),
+            true => {
+                let pressure = state.last_pressure.load(Ordering::Relaxed);
+                Some(InputEvent::WacomEvent {
+                    event: WacomEvent::Draw {
+                        position: state.position(),
+                        pressure,
+                        normalized_pressure: (f32::from(pressure)
+                            / f32::from(state.geometry().max_pressure))
+                        .min(1.0),
+                        tilt: state.tilt(),
+                        tool_id: state.tool_id(),
+                        serial: state.serial(),
                     },
-                    pressure: state.last_pressure.load(Ordering::Relaxed),
-                    tilt: cgmath::Vector2 {
-                        x: state.last_xtilt.load(Ordering::Relaxed),
-                        y: state.last_ytilt.load(Ordering::Relaxed),
-                    },
-                },
-            }),
+                    timestamp,
+                })
+            }
         },
         ecodes::EV_KEY => {
             /* key (device detected - device out of range etc.) */
@@ -92,8 +142,17 @@ pub fn decode(ev: &EvInputEvent, outer_state: &InputDeviceState) -> Option<Input
                     pen,
                     state: pen_state,
                 },
+                timestamp,
             })
         }
+        ecodes::EV_MSC => {
+            if ev.code() == ecodes::MSC_SERIAL {
+                state
+                    .last_serial
+                    .store(ev.value() as u32, Ordering::Relaxed);
+            }
+            None
+        }
         ecodes::EV_ABS => {
             // Absolute
             match ev.code() {
@@ -114,11 +173,16 @@ pub fn decode(ev: &EvInputEvent, outer_state: &InputDeviceState) -> Option<Input
                 }
                 ecodes::ABS_TILT_X => {
                     // xtilt -9000 to 9000
-                    state.last_xtilt.store(ev.value() as u16, Ordering::Relaxed);
+                    state.last_xtilt.store(ev.value(), Ordering::Relaxed);
                 }
                 ecodes::ABS_TILT_Y => {
                     // ytilt -9000 to 9000
-                    state.last_ytilt.store(ev.value() as u16, Ordering::Relaxed);
+                    state.last_ytilt.store(ev.value(), Ordering::Relaxed);
+                }
+                ecodes::ABS_MISC => {
+                    state
+                        .last_tool_id
+                        .store(ev.value() as u32, Ordering::Relaxed);
                 }
                 ecodes::ABS_PRESSURE => {
                     // contact made with pressure val up to 4095
@@ -126,53 +190,17 @@ pub fn decode(ev: &EvInputEvent, outer_state: &InputDeviceState) -> Option<Input
                         .last_pressure
                         .store(ev.value() as u16, Ordering::Relaxed);
                 }
-                ecodes::ABS_X => {
-                    let placement = CURRENT_DEVICE.get_wacom_placement();
-                    let mut rotated_part = placement.rotation.rotate_part(
-                        CoordinatePart::X(ev.value() as u16),
-                        &SCANNED.wacom_orig_size,
-                    );
-                    if placement.invert_x {
-                        if let CoordinatePart::X(ref mut x_value) = rotated_part {
-                            *x_value = *WACOMWIDTH - *x_value;
-                        }
-                    }
-                    if placement.invert_y {
-                        if let CoordinatePart::Y(ref mut y_value) = rotated_part {
-                            *y_value = *WACOMHEIGHT - *y_value;
-                        }
-                    }
-                    match rotated_part {
-                        CoordinatePart::X(rotated_value) => {
-                            state.last_x.store(rotated_value, Ordering::Relaxed);
-                        }
-                        CoordinatePart::Y(rotated_value) => {
-                            state.last_y.store(rotated_value, Ordering::Relaxed);
-                        }
-                    }
-                }
-                ecodes::ABS_Y => {
-                    let placement = CURRENT_DEVICE.get_wacom_placement();
-                    let mut rotated_part = placement.rotation.rotate_part(
-                        CoordinatePart::Y(ev.value() as u16),
-                        &SCANNED.wacom_orig_size,
-                    );
-                    if placement.invert_x {
-                        if let CoordinatePart::X(ref mut x_value) = rotated_part {
-                            *x_value = *WACOMWIDTH - *x_value;
-                        }
-                    }
-                    if placement.invert_y {
-                        if let CoordinatePart::Y(ref mut y_value) = rotated_part {
-                            *y_value = *WACOMHEIGHT - *y_value;
-                        }
-                    }
-                    match rotated_part {
-                        CoordinatePart::X(rotated_value) => {
-                            state.last_x.store(rotated_value, Ordering::Relaxed);
+                ecodes::ABS_X | ecodes::ABS_Y => {
+                    let part = match ev.code() {
+                        ecodes::ABS_X => CoordinatePart::X(ev.value() as u16),
+                        _ => CoordinatePart::Y(ev.value() as u16),
+                    };
+                    match state.geometry().place_part(part) {
+                        CoordinatePart::X(placed_value) => {
+                            state.last_x.store(placed_value, Ordering::Relaxed);
                         }
-                        CoordinatePart::Y(rotated_value) => {
-                            state.last_y.store(rotated_value, Ordering::Relaxed);
+                        CoordinatePart::Y(placed_value) => {
+                            state.last_y.store(placed_value, Ordering::Relaxed);
                         }
                     }
                 }
@@ -32,7 +32,7 @@ macro_rules! end_bench {
 }
 
 #[cfg(feature = "framebuffer-types")]
-#[macro_use(io, ioc, iow, iowr)]
+#[macro_use(io, ioc, ior, iow, iowr)]
 extern crate ioctl_gen;
 
 pub use cgmath;
@@ -63,6 +63,9 @@ pub mod dimensions;
 #[cfg(feature = "battery")]
 pub mod battery;
 
+/// Suspending the device to RAM
+pub mod power;
+
 // TODO: Docs
 pub mod device;
 
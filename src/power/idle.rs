@@ -0,0 +1,145 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::power::SuspendConfig;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum IdleEvent {
+    /// There was no activity for `timeout`, one of the configured timeouts
+    Timeout { timeout: Duration },
+    /// The first activity after at least one timeout elapsed
+    Active { idle_for: Duration },
+}
+
+/// The timeouts after which `ApplicationContext` reports an `IdleEvent`, e.g. to dim
+/// the UI after 1 minute and show a clock after 5.
+#[derive(Clone, Debug, Default)]
+pub struct IdleConfig {
+    pub timeouts: Vec<Duration>,
+    /// Suspends the device after this long without activity, right after reporting
+    /// the timeout
+    pub suspend_after: Option<Duration>,
+    pub suspend: SuspendConfig,
+}
+
+/// Keeps an idle inhibited while it exists, e.g. for the duration of a download
+pub struct IdleInhibitor(Arc<AtomicUsize>);
+
+impl Drop for IdleInhibitor {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Reports the timeouts that elapse without any activity, in increasing order. While
+/// an `IdleInhibitor` exists no timeout elapses, and the idle time starts over once
+/// the last one is dropped.
+pub struct IdleTracker {
+    timeouts: Vec<Duration>,
+    last_activity: Instant,
+    /// How many of the timeouts were reported since the last activity
+    reported: usize,
+    inhibitors: Arc<AtomicUsize>,
+}
+
+impl IdleTracker {
+    pub fn new(mut timeouts: Vec<Duration>, now: Instant) -> IdleTracker {
+        timeouts.sort();
+        timeouts.dedup();
+        IdleTracker {
+            timeouts,
+            last_activity: now,
+            reported: 0,
+            inhibitors: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn timeouts(&self) -> &[Duration] {
+        &self.timeouts
+    }
+
+    pub fn inhibit(&self) -> IdleInhibitor {
+        self.inhibitors.fetch_add(1, Ordering::Relaxed);
+        IdleInhibitor(Arc::clone(&self.inhibitors))
+    }
+
+    pub fn is_inhibited(&self) -> bool {
+        self.inhibitors.load(Ordering::Relaxed) > 0
+    }
+
+    pub fn idle_time(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.last_activity)
+    }
+
+    /// Restarts the idle time. Returns `IdleEvent::Active` if a timeout had elapsed.
+    pub fn activity(&mut self, now: Instant) -> Option<IdleEvent> {
+        let idle_for = self.idle_time(now);
+        self.last_activity = now;
+        match std::mem::take(&mut self.reported) {
+            0 => None,
+            _ => Some(IdleEvent::Active { idle_for }),
+        }
+    }
+
+    /// The timeouts that elapsed since the last call
+    pub fn poll(&mut self, now: Instant) -> Vec<IdleEvent> {
+        if self.is_inhibited() {
+            self.last_activity = now;
+            return vec![];
+        }
+        let idle_for = self.idle_time(now);
+        let elapsed = self.timeouts.iter().take_while(|&&t| t <= idle_for).count();
+        let events = self.timeouts[self.reported.min(elapsed)..elapsed]
+            .iter()
+            .map(|&timeout| IdleEvent::Timeout { timeout })
+            .collect();
+        self.reported = self.reported.max(elapsed);
+        events
+    }
+
+    /// When the next timeout elapses, if there's one left
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.timeouts
+            .get(self.reported)
+            .map(|&timeout| self.last_activity + timeout)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_timeouts() {
+        let start = Instant::now();
+        let s = Duration::from_secs;
+        let mut idle = IdleTracker::new(vec![s(300), s(60)], start);
+        assert_eq!(idle.next_deadline(), Some(start + s(60)));
+        assert!(idle.poll(start + s(59)).is_empty());
+        assert_eq!(
+            idle.poll(start + s(400)),
+            vec![
+                IdleEvent::Timeout { timeout: s(60) },
+                IdleEvent::Timeout { timeout: s(300) }
+            ]
+        );
+        assert!(idle.poll(start + s(500)).is_empty());
+        assert_eq!(idle.next_deadline(), None);
+        assert_eq!(
+            idle.activity(start + s(500)),
+            Some(IdleEvent::Active { idle_for: s(500) })
+        );
+        assert_eq!(idle.activity(start + s(510)), None);
+
+        // Nothing elapses while inhibited, and the idle time starts over afterwards
+        let inhibitor = idle.inhibit();
+        assert!(idle.poll(start + s(600)).is_empty());
+        drop(inhibitor);
+        assert!(idle.poll(start + s(650)).is_empty());
+        assert_eq!(
+            idle.poll(start + s(660)),
+            vec![IdleEvent::Timeout { timeout: s(60) }]
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use std::fs;
+use std::io;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// Tracks the time since the last user input
+pub mod idle;
+
+/// Writing `mem` here suspends to RAM until a wakeup source (e.g. the power button) fires
+pub const POWER_STATE_PATH: &str = "/sys/power/state";
+
+/// How long `SuspendMethod::Logind` waits for the device to suspend and resume
+const LOGIND_SUSPEND_TIMEOUT: Duration = Duration::from_secs(60);
+const RESUME_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// More than the time between reading both clocks, less than any real suspend
+const MIN_SLEEP: Duration = Duration::from_millis(10);
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Default)]
+pub enum SuspendMethod {
+    /// Writes `mem` to `/sys/power/state`, which needs root
+    #[default]
+    Sysfs,
+    /// Runs `systemctl suspend`, so that logind and the systemd sleep hooks are involved
+    Logind,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum PowerEvent {
+    /// The device woke up again after `slept`
+    Resumed { slept: Duration },
+}
+
+/// What is shown on the display while the device sleeps
+#[derive(Clone, Debug, Default)]
+pub enum SleepScreen {
+    /// Leaves the display as it is
+    Keep,
+    #[default]
+    Blank,
+    /// Centered on a blank display
+    Text(String),
+    /// Centered on a blank display
+    #[cfg(feature = "image")]
+    Image(image::DynamicImage),
+}
+
+/// How `ApplicationContext::suspend` puts the device to sleep
+#[derive(Clone, Debug)]
+pub struct SuspendConfig {
+    pub method: SuspendMethod,
+    pub sleep_screen: SleepScreen,
+    /// Brings back what was displayed before the sleep screen after resuming
+    pub restore_screen: bool,
+}
+
+impl ::std::default::Default for SuspendConfig {
+    fn default() -> Self {
+        SuspendConfig {
+            method: SuspendMethod::default(),
+            sleep_screen: SleepScreen::default(),
+            restore_screen: true,
+        }
+    }
+}
+
+fn clock(id: libc::clockid_t) -> io::Result<Duration> {
+    let mut ts: libc::timespec = unsafe { std::mem::zeroed() };
+    if unsafe { libc::clock_gettime(id, &mut ts) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let secs = u64::try_from(ts.tv_sec).map_err(io::Error::other)?;
+    let nanos = u32::try_from(ts.tv_nsec).map_err(io::Error::other)?;
+    Ok(Duration::new(secs, nanos))
+}
+
+/// The time spent suspended since boot. CLOCK_BOOTTIME keeps counting while
+/// suspended, CLOCK_MONOTONIC doesn't.
+fn time_suspended() -> io::Result<Duration> {
+    Ok(clock(libc::CLOCK_BOOTTIME)?.saturating_sub(clock(libc::CLOCK_MONOTONIC)?))
+}
+
+/// Suspends the device to RAM and returns after it resumed, with the time it slept.
+///
+/// `systemctl suspend` only queues the suspend with logind, so with
+/// `SuspendMethod::Logind` this waits until the time spent suspended has grown. If
+/// that doesn't happen within a minute (e.g. because an inhibitor blocks it), or
+/// systemd refuses to suspend, an error is returned.
+pub fn suspend(method: SuspendMethod) -> io::Result<Duration> {
+    let before = time_suspended()?;
+    match method {
+        SuspendMethod::Sysfs => fs::write(POWER_STATE_PATH, "mem")?,
+        SuspendMethod::Logind => {
+            let status = Command::new("systemctl").arg("suspend").status()?;
+            if !status.success() {
+                return Err(io::Error::other(format!(
+                    "systemctl suspend failed: {status}"
+                )));
+            }
+            // Sleeping on the monotonic clock, which stands still while suspended
+            let started = Instant::now();
+            while time_suspended()?.saturating_sub(before) < MIN_SLEEP {
+                if started.elapsed() > LOGIND_SUSPEND_TIMEOUT {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "logind didn't suspend the device",
+                    ));
+                }
+                std::thread::sleep(RESUME_POLL_INTERVAL);
+            }
+        }
+    }
+    Ok(time_suspended()?.saturating_sub(before))
+}
@@ -0,0 +1,73 @@
+use crate::framebuffer::common::mxcfb_rect;
+
+/// Collects the screen areas that need to be redrawn and refreshed for the next frame.
+/// Overlapping areas are merged into their bounding rectangle, so that every pixel is
+/// covered by at most one of the resulting refreshes.
+#[derive(Clone, Debug, Default)]
+pub struct Damage {
+    rects: Vec<mxcfb_rect>,
+}
+
+impl Damage {
+    pub fn add(&mut self, rect: &mxcfb_rect) {
+        if rect.width == 0 || rect.height == 0 {
+            return;
+        }
+
+        // Merging can make the result overlap rects that didn't overlap the original,
+        // so keep going until nothing overlaps anymore.
+        let mut merged = *rect;
+        while let Some(i) = self.rects.iter().position(|r| r.intersects(&merged)) {
+            merged = merged.merge_rect(&self.rects.swap_remove(i));
+        }
+        self.rects.push(merged);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    pub fn intersects(&self, rect: &mxcfb_rect) -> bool {
+        self.rects.iter().any(|r| r.intersects(rect))
+    }
+
+    /// The damaged areas. None of them overlap each other.
+    pub fn rects(&self) -> &[mxcfb_rect] {
+        &self.rects
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rect(left: u32, top: u32, width: u32, height: u32) -> mxcfb_rect {
+        mxcfb_rect {
+            top,
+            left,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn test_disjoint_rects_stay_separate() {
+        let mut damage = Damage::default();
+        damage.add(&rect(0, 0, 10, 10));
+        damage.add(&rect(10, 0, 10, 10));
+        damage.add(&rect(0, 0, 0, 10));
+        assert_eq!(damage.rects(), &[rect(0, 0, 10, 10), rect(10, 0, 10, 10)]);
+    }
+
+    #[test]
+    fn test_chained_overlaps_collapse() {
+        let mut damage = Damage::default();
+        damage.add(&rect(0, 0, 10, 10));
+        damage.add(&rect(30, 0, 10, 10));
+        // Bridges both of the above
+        damage.add(&rect(5, 5, 30, 2));
+        assert_eq!(damage.rects(), &[rect(0, 0, 40, 10)]);
+        assert!(damage.intersects(&rect(20, 8, 1, 1)));
+        assert!(!damage.intersects(&rect(20, 10, 1, 1)));
+    }
+}
@@ -4,11 +4,16 @@ use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use crate::framebuffer::cgmath;
 use crate::framebuffer::common;
 use crate::framebuffer::common::{color, mxcfb_rect};
+use crate::framebuffer::request::RefreshPreset;
 use crate::framebuffer::FramebufferDraw;
 use crate::framebuffer::FramebufferRefresh;
 use crate::framebuffer::PartialRefreshMode;
 
 use crate::appctx;
+use crate::ui_extensions::pointer::{EventPropagation, PointerEvent, PointerEventKind};
+
+/// Gap between the text and the border of a bordered `UIElement::Text`
+const TEXT_BORDER_PADDING: u32 = 8;
 
 pub type ActiveRegionFunction = fn(&mut appctx::ApplicationContext<'_>, UIElementHandle);
 
@@ -24,6 +29,89 @@ impl std::fmt::Debug for ActiveRegionHandler {
     }
 }
 
+pub type PointerHandler = Arc<
+    dyn Fn(&mut appctx::ApplicationContext<'_>, UIElementHandle, &PointerEvent) -> EventPropagation
+        + Send
+        + Sync,
+>;
+
+/// Implemented by every closure that can be turned into a `PointerHandler`
+pub trait PointerHandlerFn:
+    Fn(&mut appctx::ApplicationContext<'_>, UIElementHandle, &PointerEvent) -> EventPropagation
+    + Send
+    + Sync
+    + 'static
+{
+}
+
+impl<F> PointerHandlerFn for F where
+    F: Fn(&mut appctx::ApplicationContext<'_>, UIElementHandle, &PointerEvent) -> EventPropagation
+        + Send
+        + Sync
+        + 'static
+{
+}
+
+/// Closures called with the `PointerEvent`s targeting an element. The element that is
+/// topmost under the pointer gets to handle an event first. Unless its handler returns
+/// `EventPropagation::Stop`, the event bubbles down to the elements below it.
+///
+/// Once pressed, an element keeps receiving the events of that pointer until it is lifted,
+/// even if the pointer leaves the element in the meantime.
+#[derive(Clone, Default)]
+pub struct UIElementHandlers {
+    pub press: Option<PointerHandler>,
+    pub release: Option<PointerHandler>,
+    pub cancel: Option<PointerHandler>,
+    pub long_press: Option<PointerHandler>,
+    pub drag: Option<PointerHandler>,
+}
+
+impl UIElementHandlers {
+    pub fn on_press(mut self, f: impl PointerHandlerFn) -> Self {
+        self.press = Some(Arc::new(f));
+        self
+    }
+
+    pub fn on_release(mut self, f: impl PointerHandlerFn) -> Self {
+        self.release = Some(Arc::new(f));
+        self
+    }
+
+    pub fn on_cancel(mut self, f: impl PointerHandlerFn) -> Self {
+        self.cancel = Some(Arc::new(f));
+        self
+    }
+
+    pub fn on_long_press(mut self, f: impl PointerHandlerFn) -> Self {
+        self.long_press = Some(Arc::new(f));
+        self
+    }
+
+    pub fn on_drag(mut self, f: impl PointerHandlerFn) -> Self {
+        self.drag = Some(Arc::new(f));
+        self
+    }
+
+    pub fn get(&self, kind: PointerEventKind) -> Option<&PointerHandler> {
+        match kind {
+            PointerEventKind::Press => self.press.as_ref(),
+            PointerEventKind::Release => self.release.as_ref(),
+            PointerEventKind::Cancel => self.cancel.as_ref(),
+            PointerEventKind::LongPress => self.long_press.as_ref(),
+            PointerEventKind::Drag { .. } => self.drag.as_ref(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.press.is_none()
+            && self.release.is_none()
+            && self.cancel.is_none()
+            && self.long_press.is_none()
+            && self.drag.is_none()
+    }
+}
+
 #[derive(Clone, Copy, Default)]
 pub enum UIConstraintRefresh {
     NoRefresh,
@@ -41,7 +129,13 @@ pub struct UIElementWrapper {
     pub refresh: UIConstraintRefresh,
     pub last_drawn_rect: Option<common::mxcfb_rect>,
     pub onclick: Option<ActiveRegionFunction>,
+    pub handlers: UIElementHandlers,
     pub inner: UIElement,
+    /// Elements with a higher `z_index` are drawn on top of those with a lower one
+    pub z_index: i32,
+    /// Whether the element changed since it was last drawn. Set this after modifying
+    /// `inner` to have it redrawn by the next `ApplicationContext::render_frame`.
+    pub dirty: bool,
 }
 
 impl Default for UIElementWrapper {
@@ -51,7 +145,10 @@ impl Default for UIElementWrapper {
             refresh: UIConstraintRefresh::default(),
             last_drawn_rect: Option::default(),
             onclick: Option::default(),
+            handlers: UIElementHandlers::default(),
             inner: UIElement::default(),
+            z_index: 0,
+            dirty: true,
         }
     }
 }
@@ -105,6 +202,50 @@ impl UIElementHandle {
 }
 
 impl UIElementWrapper {
+    /// Returns the offset from `position` to the top left corner of the area this element
+    /// covers when drawn, along with the size of that area. Nothing gets drawn.
+    /// `None` is returned for `UIElement::Unspecified`.
+    pub fn measure(
+        &self,
+        app: &mut appctx::ApplicationContext<'_>,
+    ) -> Option<(cgmath::Vector2<i32>, cgmath::Vector2<u32>)> {
+        match self.inner {
+            UIElement::Text {
+                ref text,
+                scale,
+                border_px,
+                ..
+            } => {
+                // Text is drawn with its baseline at `position`, so probe far enough away from
+                // the screen edges to not have the glyphs clamped to them.
+                let probe = cgmath::Point2 {
+                    x: 100.0 + scale,
+                    y: 100.0 + 2.0 * scale,
+                };
+                let framebuffer = app.get_framebuffer_ref();
+                let mut area = framebuffer.draw_text(probe, text, scale, color::BLACK, true);
+                if border_px > 0 {
+                    area = area.expand(TEXT_BORDER_PADDING).expand(border_px);
+                }
+                let offset = cgmath::Vector2 {
+                    x: area.left as i32 - probe.x as i32,
+                    y: area.top as i32 - probe.y as i32,
+                };
+                Some((offset, area.size()))
+            }
+            #[cfg(feature = "image")]
+            UIElement::Image { ref img } => Some((
+                cgmath::Vector2 { x: 0, y: 0 },
+                cgmath::Vector2 {
+                    x: img.width(),
+                    y: img.height(),
+                },
+            )),
+            UIElement::Region { size, .. } => Some((cgmath::Vector2 { x: 0, y: 0 }, size)),
+            UIElement::Unspecified => None,
+        }
+    }
+
     pub fn draw(
         &mut self,
         app: &mut appctx::ApplicationContext<'_>,
@@ -122,16 +263,13 @@ impl UIElementWrapper {
                 // only if it isn't at the same spot. Otherwise we will be refreshing it for no
                 // reason and showing a blank frame. There is of course still a caveat since we don't
                 // know the dimensions of a drawn text before it is actually drawn.
-                // TODO: Take care of the point above ^
+                // `ApplicationContext::render_frame` avoids this by refreshing the cleared and the
+                // redrawn area together.
                 if rect.top_left() != self.position.cast().unwrap() {
-                    framebuffer.partial_refresh(
-                        &rect,
-                        PartialRefreshMode::Wait,
-                        common::waveform_mode::WAVEFORM_MODE_DU,
-                        common::display_temp::TEMP_USE_REMARKABLE_DRAW,
-                        common::dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
-                        0,
-                        false,
+                    framebuffer.refresh(
+                        &app.refresh_request(RefreshPreset::FastDraw)
+                            .region(rect)
+                            .mode(PartialRefreshMode::Wait),
                     );
                 }
 
@@ -140,6 +278,49 @@ impl UIElementWrapper {
             None => mxcfb_rect::invalid(),
         };
 
+        let rect = match self.draw_inner(app, refresh) {
+            Some(rect) => rect,
+            None => return,
+        };
+        self.update_active_region(app, old_filled_rect, rect, handler);
+
+        if let Some(last_rect) = self.last_drawn_rect {
+            if last_rect != rect {
+                framebuffer.refresh(
+                    &app.refresh_request(RefreshPreset::FastDraw)
+                        .region(last_rect)
+                        .mode(PartialRefreshMode::Async),
+                );
+            }
+        }
+
+        // We need to wait until now because we don't know the size of the active region before we
+        // actually go ahead and draw it.
+        self.last_drawn_rect = Some(rect);
+        self.dirty = false;
+    }
+
+    /// Draws the element without clearing the area it previously covered and without
+    /// refreshing anything. `ApplicationContext::render_frame` takes care of both for all
+    /// dirty elements at once. Returns the area that has been drawn.
+    pub fn render(
+        &mut self,
+        app: &mut appctx::ApplicationContext<'_>,
+        handler: &Option<ActiveRegionHandler>,
+    ) -> Option<mxcfb_rect> {
+        let old_filled_rect = self.last_drawn_rect.unwrap_or_else(mxcfb_rect::invalid);
+        let rect = self.draw_inner(app, UIConstraintRefresh::NoRefresh)?;
+        self.update_active_region(app, old_filled_rect, rect, handler);
+        self.last_drawn_rect = Some(rect);
+        self.dirty = false;
+        Some(rect)
+    }
+
+    fn draw_inner(
+        &self,
+        app: &mut appctx::ApplicationContext<'_>,
+        refresh: UIConstraintRefresh,
+    ) -> Option<mxcfb_rect> {
         // TODO: Move this to inside the app and then have it call the UIElement's draw
         // TODO: Also perhaps make border_padding configurable
         let rect = match self.inner {
@@ -153,7 +334,7 @@ impl UIElementWrapper {
                 foreground,
                 scale,
                 border_px,
-                8,
+                TEXT_BORDER_PADDING,
                 text,
                 refresh,
             ),
@@ -172,9 +353,18 @@ impl UIElementWrapper {
                 border_color,
                 refresh,
             ),
-            UIElement::Unspecified => return,
+            UIElement::Unspecified => return None,
         };
+        Some(rect)
+    }
 
+    fn update_active_region(
+        &self,
+        app: &mut appctx::ApplicationContext<'_>,
+        old_filled_rect: mxcfb_rect,
+        rect: mxcfb_rect,
+        handler: &Option<ActiveRegionHandler>,
+    ) {
         // If no changes, no need to change the active region
         if old_filled_rect != rect {
             if let Some(ref h) = handler {
@@ -200,23 +390,5 @@ impl UIElementWrapper {
                 }
             }
         }
-
-        if let Some(last_rect) = self.last_drawn_rect {
-            if last_rect != rect {
-                framebuffer.partial_refresh(
-                    &last_rect,
-                    PartialRefreshMode::Async,
-                    common::waveform_mode::WAVEFORM_MODE_DU,
-                    common::display_temp::TEMP_USE_REMARKABLE_DRAW,
-                    common::dither_mode::EPDC_FLAG_USE_DITHERING_PASSTHROUGH,
-                    0,
-                    false,
-                );
-            }
-        }
-
-        // We need to wait until now because we don't know the size of the active region before we
-        // actually go ahead and draw it.
-        self.last_drawn_rect = Some(rect);
     }
 }
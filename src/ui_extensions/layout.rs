@@ -0,0 +1,626 @@
+use crate::framebuffer::cgmath;
+use crate::framebuffer::common::mxcfb_rect;
+
+/// Space reserved on each side of a box. Used both for the `margin` outside of a
+/// `LayoutNode` and the `padding` between a container and its children.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Insets {
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+    pub left: u32,
+}
+
+impl Insets {
+    pub fn uniform(v: u32) -> Insets {
+        Insets {
+            top: v,
+            right: v,
+            bottom: v,
+            left: v,
+        }
+    }
+
+    pub fn symmetric(vertical: u32, horizontal: u32) -> Insets {
+        Insets {
+            top: vertical,
+            right: horizontal,
+            bottom: vertical,
+            left: horizontal,
+        }
+    }
+
+    fn horizontal(&self) -> u32 {
+        self.left + self.right
+    }
+
+    fn vertical(&self) -> u32 {
+        self.top + self.bottom
+    }
+
+    fn grow(&self, size: cgmath::Vector2<u32>) -> cgmath::Vector2<u32> {
+        cgmath::Vector2 {
+            x: size.x + self.horizontal(),
+            y: size.y + self.vertical(),
+        }
+    }
+
+    fn shrink(&self, rect: &mxcfb_rect) -> mxcfb_rect {
+        mxcfb_rect {
+            top: rect.top + self.top,
+            left: rect.left + self.left,
+            width: rect.width.saturating_sub(self.horizontal()),
+            height: rect.height.saturating_sub(self.vertical()),
+        }
+    }
+}
+
+/// Placement of a child along the cross axis of a stack or inside a grid cell.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Alignment {
+    #[default]
+    Start,
+    Center,
+    End,
+    /// Gives the child the entire available space. Only `UIElement::Region` elements
+    /// are resized by this, the others keep their natural size.
+    Stretch,
+}
+
+impl Alignment {
+    /// Returns the offset and length of a child of length `desired` inside `available`.
+    fn place(&self, desired: u32, available: u32) -> (u32, u32) {
+        let desired = desired.min(available);
+        match self {
+            Alignment::Start => (0, desired),
+            Alignment::Center => ((available - desired) / 2, desired),
+            Alignment::End => (available - desired, desired),
+            Alignment::Stretch => (0, available),
+        }
+    }
+}
+
+/// Corner, edge or center of a container that an `Anchored` child gets pinned to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    fn alignments(&self) -> (Alignment, Alignment) {
+        let horizontal = match self {
+            Anchor::TopLeft | Anchor::Left | Anchor::BottomLeft => Alignment::Start,
+            Anchor::Top | Anchor::Center | Anchor::Bottom => Alignment::Center,
+            Anchor::TopRight | Anchor::Right | Anchor::BottomRight => Alignment::End,
+        };
+        let vertical = match self {
+            Anchor::TopLeft | Anchor::Top | Anchor::TopRight => Alignment::Start,
+            Anchor::Left | Anchor::Center | Anchor::Right => Alignment::Center,
+            Anchor::BottomLeft | Anchor::Bottom | Anchor::BottomRight => Alignment::End,
+        };
+        (horizontal, vertical)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Axis {
+    Horizontal,
+    Vertical,
+}
+
+impl Axis {
+    fn main(&self, v: cgmath::Vector2<u32>) -> u32 {
+        match self {
+            Axis::Horizontal => v.x,
+            Axis::Vertical => v.y,
+        }
+    }
+
+    fn cross(&self, v: cgmath::Vector2<u32>) -> u32 {
+        match self {
+            Axis::Horizontal => v.y,
+            Axis::Vertical => v.x,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum LayoutKind {
+    /// A `UIElementWrapper` registered in the `ApplicationContext` under this name
+    Element(String),
+    /// Children laid out top to bottom
+    VStack {
+        children: Vec<LayoutNode>,
+        spacing: u32,
+        align: Alignment,
+    },
+    /// Children laid out left to right
+    HStack {
+        children: Vec<LayoutNode>,
+        spacing: u32,
+        align: Alignment,
+    },
+    /// Children laid out row by row in `columns` equally wide columns
+    Grid {
+        children: Vec<LayoutNode>,
+        columns: usize,
+        spacing: cgmath::Vector2<u32>,
+        align: Alignment,
+    },
+    /// Every child is pinned to its anchor inside the same area
+    Anchored { children: Vec<(Anchor, LayoutNode)> },
+    /// Empty space of at least `size`. Inside a stack, spacers share any space left
+    /// over along the main axis.
+    Spacer { size: cgmath::Vector2<u32> },
+}
+
+/// A node in a layout tree. The tree is set on the `ApplicationContext` with
+/// `set_layout(..)`, which then computes the position of every element it references
+/// from the measured size of the elements and the size of the screen.
+#[derive(Clone, Debug)]
+pub struct LayoutNode {
+    pub kind: LayoutKind,
+    pub margin: Insets,
+    pub padding: Insets,
+}
+
+impl LayoutNode {
+    fn new(kind: LayoutKind) -> LayoutNode {
+        LayoutNode {
+            kind,
+            margin: Insets::default(),
+            padding: Insets::default(),
+        }
+    }
+
+    pub fn element(name: &str) -> LayoutNode {
+        LayoutNode::new(LayoutKind::Element(name.to_owned()))
+    }
+
+    pub fn vstack(children: Vec<LayoutNode>) -> LayoutNode {
+        LayoutNode::new(LayoutKind::VStack {
+            children,
+            spacing: 0,
+            align: Alignment::default(),
+        })
+    }
+
+    pub fn hstack(children: Vec<LayoutNode>) -> LayoutNode {
+        LayoutNode::new(LayoutKind::HStack {
+            children,
+            spacing: 0,
+            align: Alignment::default(),
+        })
+    }
+
+    pub fn grid(columns: usize, children: Vec<LayoutNode>) -> LayoutNode {
+        LayoutNode::new(LayoutKind::Grid {
+            children,
+            columns: columns.max(1),
+            spacing: cgmath::Vector2 { x: 0, y: 0 },
+            align: Alignment::default(),
+        })
+    }
+
+    pub fn anchored(children: Vec<(Anchor, LayoutNode)>) -> LayoutNode {
+        LayoutNode::new(LayoutKind::Anchored { children })
+    }
+
+    /// A spacer without a minimum size, only useful to soak up space inside a stack.
+    pub fn spacer() -> LayoutNode {
+        LayoutNode::fixed_spacer(cgmath::Vector2 { x: 0, y: 0 })
+    }
+
+    pub fn fixed_spacer(size: cgmath::Vector2<u32>) -> LayoutNode {
+        LayoutNode::new(LayoutKind::Spacer { size })
+    }
+
+    pub fn margin(mut self, margin: Insets) -> LayoutNode {
+        self.margin = margin;
+        self
+    }
+
+    pub fn padding(mut self, padding: Insets) -> LayoutNode {
+        self.padding = padding;
+        self
+    }
+
+    /// Sets the gap between children. Has no effect on elements, spacers and anchors.
+    pub fn spacing(mut self, gap: u32) -> LayoutNode {
+        match self.kind {
+            LayoutKind::VStack {
+                ref mut spacing, ..
+            }
+            | LayoutKind::HStack {
+                ref mut spacing, ..
+            } => *spacing = gap,
+            LayoutKind::Grid {
+                ref mut spacing, ..
+            } => *spacing = cgmath::Vector2 { x: gap, y: gap },
+            _ => {}
+        }
+        self
+    }
+
+    /// Sets the alignment of children. Has no effect on elements, spacers and anchors.
+    pub fn align(mut self, alignment: Alignment) -> LayoutNode {
+        match self.kind {
+            LayoutKind::VStack { ref mut align, .. }
+            | LayoutKind::HStack { ref mut align, .. }
+            | LayoutKind::Grid { ref mut align, .. } => *align = alignment,
+            _ => {}
+        }
+        self
+    }
+
+    /// Names of all elements referenced in this tree
+    pub fn element_names(&self) -> Vec<&str> {
+        let mut names = vec![];
+        self.collect_names(&mut names);
+        names
+    }
+
+    fn collect_names<'a>(&'a self, names: &mut Vec<&'a str>) {
+        match self.kind {
+            LayoutKind::Element(ref name) => names.push(name),
+            LayoutKind::VStack { ref children, .. }
+            | LayoutKind::HStack { ref children, .. }
+            | LayoutKind::Grid { ref children, .. } => {
+                children.iter().for_each(|c| c.collect_names(names))
+            }
+            LayoutKind::Anchored { ref children } => {
+                children.iter().for_each(|(_, c)| c.collect_names(names))
+            }
+            LayoutKind::Spacer { .. } => {}
+        }
+    }
+
+    /// Returns the size this node wants including its margin. `measure` is asked
+    /// for the natural size of every element. Unknown elements take up no space.
+    pub fn measure<F: Fn(&str) -> Option<cgmath::Vector2<u32>>>(
+        &self,
+        measure: &F,
+    ) -> cgmath::Vector2<u32> {
+        let content = match self.kind {
+            LayoutKind::Element(ref name) => {
+                measure(name).unwrap_or(cgmath::Vector2 { x: 0, y: 0 })
+            }
+            LayoutKind::VStack {
+                ref children,
+                spacing,
+                ..
+            } => measure_stack(Axis::Vertical, children, spacing, measure),
+            LayoutKind::HStack {
+                ref children,
+                spacing,
+                ..
+            } => measure_stack(Axis::Horizontal, children, spacing, measure),
+            LayoutKind::Grid {
+                ref children,
+                columns,
+                spacing,
+                ..
+            } => {
+                // The fields are public, so a zero column count can still get here
+                let columns = columns.max(1);
+                let sizes: Vec<_> = children.iter().map(|c| c.measure(measure)).collect();
+                let cell_width = sizes.iter().map(|s| s.x).max().unwrap_or(0);
+                let rows: Vec<u32> = sizes
+                    .chunks(columns)
+                    .map(|row| row.iter().map(|s| s.y).max().unwrap_or(0))
+                    .collect();
+                let columns = columns.min(sizes.len()) as u32;
+                cgmath::Vector2 {
+                    x: cell_width * columns + spacing.x * columns.saturating_sub(1),
+                    y: rows.iter().sum::<u32>() + spacing.y * (rows.len() as u32).saturating_sub(1),
+                }
+            }
+            LayoutKind::Anchored { ref children } => {
+                children
+                    .iter()
+                    .fold(cgmath::Vector2 { x: 0, y: 0 }, |acc, (_, c)| {
+                        let s = c.measure(measure);
+                        cgmath::Vector2 {
+                            x: acc.x.max(s.x),
+                            y: acc.y.max(s.y),
+                        }
+                    })
+            }
+            LayoutKind::Spacer { size } => size,
+        };
+        self.margin.grow(self.padding.grow(content))
+    }
+
+    /// Places this node inside `area` (which includes its margin) and returns the
+    /// rectangle assigned to every element in the tree.
+    pub fn arrange<F: Fn(&str) -> Option<cgmath::Vector2<u32>>>(
+        &self,
+        area: &mxcfb_rect,
+        measure: &F,
+    ) -> Vec<(String, mxcfb_rect)> {
+        let mut placed = vec![];
+        self.arrange_into(area, measure, &mut placed);
+        placed
+    }
+
+    fn arrange_into<F: Fn(&str) -> Option<cgmath::Vector2<u32>>>(
+        &self,
+        area: &mxcfb_rect,
+        measure: &F,
+        placed: &mut Vec<(String, mxcfb_rect)>,
+    ) {
+        let inner = self.padding.shrink(&self.margin.shrink(area));
+        match self.kind {
+            LayoutKind::Element(ref name) => placed.push((name.clone(), inner)),
+            LayoutKind::VStack {
+                ref children,
+                spacing,
+                align,
+            } => arrange_stack(
+                Axis::Vertical,
+                children,
+                spacing,
+                align,
+                &inner,
+                measure,
+                placed,
+            ),
+            LayoutKind::HStack {
+                ref children,
+                spacing,
+                align,
+            } => arrange_stack(
+                Axis::Horizontal,
+                children,
+                spacing,
+                align,
+                &inner,
+                measure,
+                placed,
+            ),
+            LayoutKind::Grid {
+                ref children,
+                columns,
+                spacing,
+                align,
+            } => {
+                let columns = columns.max(1);
+                let sizes: Vec<_> = children.iter().map(|c| c.measure(measure)).collect();
+                let cols = columns as u32;
+                let cell_width = inner.width.saturating_sub(spacing.x * (cols - 1)) / cols;
+                let mut top = inner.top;
+                for (row, row_sizes) in children.chunks(columns).zip(sizes.chunks(columns)) {
+                    let row_height = row_sizes.iter().map(|s| s.y).max().unwrap_or(0);
+                    for (col, (child, size)) in row.iter().zip(row_sizes).enumerate() {
+                        let (dx, w) = align.place(size.x, cell_width);
+                        let (dy, h) = align.place(size.y, row_height);
+                        let cell_left = inner.left + col as u32 * (cell_width + spacing.x);
+                        child.arrange_into(
+                            &mxcfb_rect {
+                                top: top + dy,
+                                left: cell_left + dx,
+                                width: w,
+                                height: h,
+                            },
+                            measure,
+                            placed,
+                        );
+                    }
+                    top += row_height + spacing.y;
+                }
+            }
+            LayoutKind::Anchored { ref children } => {
+                for (anchor, child) in children {
+                    let size = child.measure(measure);
+                    let (horizontal, vertical) = anchor.alignments();
+                    let (dx, w) = horizontal.place(size.x, inner.width);
+                    let (dy, h) = vertical.place(size.y, inner.height);
+                    child.arrange_into(
+                        &mxcfb_rect {
+                            top: inner.top + dy,
+                            left: inner.left + dx,
+                            width: w,
+                            height: h,
+                        },
+                        measure,
+                        placed,
+                    );
+                }
+            }
+            LayoutKind::Spacer { .. } => {}
+        }
+    }
+}
+
+fn measure_stack<F: Fn(&str) -> Option<cgmath::Vector2<u32>>>(
+    axis: Axis,
+    children: &[LayoutNode],
+    spacing: u32,
+    measure: &F,
+) -> cgmath::Vector2<u32> {
+    let sizes: Vec<_> = children.iter().map(|c| c.measure(measure)).collect();
+    let main = sizes.iter().map(|s| axis.main(*s)).sum::<u32>()
+        + spacing * (sizes.len() as u32).saturating_sub(1);
+    let cross = sizes.iter().map(|s| axis.cross(*s)).max().unwrap_or(0);
+    match axis {
+        Axis::Horizontal => cgmath::Vector2 { x: main, y: cross },
+        Axis::Vertical => cgmath::Vector2 { x: cross, y: main },
+    }
+}
+
+fn arrange_stack<F: Fn(&str) -> Option<cgmath::Vector2<u32>>>(
+    axis: Axis,
+    children: &[LayoutNode],
+    spacing: u32,
+    align: Alignment,
+    inner: &mxcfb_rect,
+    measure: &F,
+    placed: &mut Vec<(String, mxcfb_rect)>,
+) {
+    let sizes: Vec<_> = children.iter().map(|c| c.measure(measure)).collect();
+    let available = axis.main(inner.size());
+    let used = sizes.iter().map(|s| axis.main(*s)).sum::<u32>()
+        + spacing * (sizes.len() as u32).saturating_sub(1);
+
+    // Whatever is left along the main axis is shared by the spacers
+    let spacers = children
+        .iter()
+        .filter(|c| matches!(c.kind, LayoutKind::Spacer { .. }))
+        .count() as u32;
+    let mut leftover = available.saturating_sub(used);
+    let share = leftover.checked_div(spacers).unwrap_or(0);
+
+    let mut offset = 0;
+    let mut spacers_seen = 0;
+    for (child, size) in children.iter().zip(sizes) {
+        let mut main = axis.main(size);
+        if let LayoutKind::Spacer { .. } = child.kind {
+            // The last spacer also takes the remainder of the division
+            spacers_seen += 1;
+            let extra = if spacers_seen == spacers {
+                leftover
+            } else {
+                share
+            };
+            leftover -= extra;
+            main += extra;
+        }
+        let main = main.min(available.saturating_sub(offset));
+        let (cross_offset, cross) = align.place(axis.cross(size), axis.cross(inner.size()));
+        let rect = match axis {
+            Axis::Horizontal => mxcfb_rect {
+                top: inner.top + cross_offset,
+                left: inner.left + offset,
+                width: main,
+                height: cross,
+            },
+            Axis::Vertical => mxcfb_rect {
+                top: inner.top + offset,
+                left: inner.left + cross_offset,
+                width: cross,
+                height: main,
+            },
+        };
+        child.arrange_into(&rect, measure, placed);
+        offset += main + spacing;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sizes(name: &str) -> Option<cgmath::Vector2<u32>> {
+        match name {
+            "a" => Some(cgmath::Vector2 { x: 100, y: 20 }),
+            "b" => Some(cgmath::Vector2 { x: 50, y: 40 }),
+            "c" => Some(cgmath::Vector2 { x: 30, y: 30 }),
+            _ => None,
+        }
+    }
+
+    fn screen() -> mxcfb_rect {
+        mxcfb_rect {
+            top: 0,
+            left: 0,
+            width: 1404,
+            height: 1872,
+        }
+    }
+
+    fn rect(left: u32, top: u32, width: u32, height: u32) -> mxcfb_rect {
+        mxcfb_rect {
+            top,
+            left,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn test_vstack_with_padding_and_spacing() {
+        let layout = LayoutNode::vstack(vec![LayoutNode::element("a"), LayoutNode::element("b")])
+            .spacing(10)
+            .padding(Insets::uniform(5))
+            .align(Alignment::Center);
+        assert_eq!(layout.measure(&sizes), cgmath::Vector2 { x: 110, y: 80 });
+        let placed = layout.arrange(&rect(0, 0, 110, 80), &sizes);
+        assert_eq!(
+            placed,
+            vec![
+                ("a".to_owned(), rect(5, 5, 100, 20)),
+                ("b".to_owned(), rect(30, 35, 50, 40)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hstack_spacers_share_leftover() {
+        let layout = LayoutNode::hstack(vec![
+            LayoutNode::element("a"),
+            LayoutNode::spacer(),
+            LayoutNode::element("b"),
+            LayoutNode::spacer(),
+            LayoutNode::element("c").margin(Insets::symmetric(0, 10)),
+        ]);
+        let placed = layout.arrange(&rect(0, 100, 401, 50), &sizes);
+        assert_eq!(
+            placed,
+            vec![
+                ("a".to_owned(), rect(0, 100, 100, 20)),
+                ("b".to_owned(), rect(200, 100, 50, 40)),
+                ("c".to_owned(), rect(361, 100, 30, 30)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_grid_and_anchors() {
+        let grid = LayoutNode::grid(
+            2,
+            vec![
+                LayoutNode::element("a"),
+                LayoutNode::element("b"),
+                LayoutNode::element("c"),
+            ],
+        )
+        .spacing(4);
+        assert_eq!(grid.measure(&sizes), cgmath::Vector2 { x: 204, y: 74 });
+
+        let layout = LayoutNode::anchored(vec![
+            (Anchor::BottomRight, grid),
+            (Anchor::Top, LayoutNode::element("c")),
+        ]);
+        let placed = layout.arrange(&screen(), &sizes);
+        assert_eq!(
+            placed,
+            vec![
+                ("a".to_owned(), rect(1200, 1798, 100, 20)),
+                ("b".to_owned(), rect(1304, 1798, 50, 40)),
+                ("c".to_owned(), rect(1200, 1842, 30, 30)),
+                ("c".to_owned(), rect(687, 0, 30, 30)),
+            ]
+        );
+
+        let mut single = LayoutNode::grid(1, vec![LayoutNode::element("a")]);
+        if let LayoutKind::Grid {
+            ref mut columns, ..
+        } = single.kind
+        {
+            *columns = 0;
+        }
+        assert_eq!(single.measure(&sizes), cgmath::Vector2 { x: 100, y: 20 });
+        assert_eq!(
+            single.arrange(&screen(), &sizes),
+            vec![("a".to_owned(), rect(0, 0, 100, 20))]
+        );
+    }
+}
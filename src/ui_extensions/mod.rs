@@ -7,3 +7,13 @@ pub mod luaext;
 /// `ApplicationContext` and `ui_extensions` and choose to interact with the `framebuffer`
 /// and `input` devices directly.
 pub mod element;
+
+/// Containers that compute the position of `UIElementWrapper`s in the `ApplicationContext`
+/// instead of placing each of them at a hardcoded position.
+pub mod layout;
+
+/// Tracks the screen areas changed by dirty elements, see `ApplicationContext::render_frame`
+pub mod damage;
+
+/// Turns touch and pen input into press, release, long press and drag events for elements
+pub mod pointer;
@@ -0,0 +1,315 @@
+use std::time::{Duration, Instant};
+
+use fxhash::FxHashMap;
+
+use crate::framebuffer::cgmath;
+use crate::input::{InputEvent, MultitouchEvent, WacomEvent, WacomPen};
+
+/// Where a pointer event originated from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PointerSource {
+    Touch { tracking_id: i32 },
+    Pen,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PointerEventKind {
+    Press,
+    /// The pointer was lifted inside the element it pressed
+    Release,
+    /// The pointer was lifted outside the element it pressed
+    Cancel,
+    /// The pointer stayed pressed without dragging for `PointerDispatcher::long_press_after`
+    LongPress,
+    /// The pointer moved after having left the `PointerDispatcher::drag_threshold`
+    /// around the point it pressed
+    Drag {
+        delta: cgmath::Vector2<i32>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PointerEvent {
+    pub source: PointerSource,
+    pub kind: PointerEventKind,
+    pub position: cgmath::Point2<u16>,
+    /// Where the pointer was pressed
+    pub origin: cgmath::Point2<u16>,
+    /// Time since the pointer was pressed
+    pub elapsed: Duration,
+}
+
+/// Returned by pointer handlers to decide whether the elements below the
+/// handling one get to see the event as well.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventPropagation {
+    Stop,
+    Continue,
+}
+
+struct PointerState {
+    origin: cgmath::Point2<u16>,
+    last: cgmath::Point2<u16>,
+    pressed_at: Instant,
+    dragging: bool,
+    long_pressed: bool,
+}
+
+/// Turns the raw multitouch and wacom `InputEvent`s into `PointerEvent`s. The
+/// `ApplicationContext` feeds every event it receives through one of these and
+/// delivers the results to the handlers of the elements under the pointer.
+pub struct PointerDispatcher {
+    pub long_press_after: Duration,
+    pub drag_threshold: u16,
+    /// Whether the pen making contact with the display is treated as a pointer
+    pub pen_as_pointer: bool,
+    pointers: FxHashMap<PointerSource, PointerState>,
+}
+
+impl Default for PointerDispatcher {
+    fn default() -> Self {
+        PointerDispatcher {
+            long_press_after: Duration::from_millis(600),
+            drag_threshold: 15,
+            pen_as_pointer: true,
+            pointers: FxHashMap::default(),
+        }
+    }
+}
+
+impl PointerDispatcher {
+    pub fn process(&mut self, event: &InputEvent, now: Instant) -> Vec<PointerEvent> {
+        match event {
+            InputEvent::MultitouchEvent { event, .. } => match event {
+                MultitouchEvent::Press { finger } => self.press(
+                    PointerSource::Touch {
+                        tracking_id: finger.tracking_id,
+                    },
+                    finger.pos,
+                    now,
+                ),
+                MultitouchEvent::Move { finger } => self.motion(
+                    PointerSource::Touch {
+                        tracking_id: finger.tracking_id,
+                    },
+                    finger.pos,
+                    now,
+                ),
+                MultitouchEvent::Release { finger } => self.release(
+                    PointerSource::Touch {
+                        tracking_id: finger.tracking_id,
+                    },
+                    now,
+                ),
+                MultitouchEvent::Unknown => vec![],
+            },
+            InputEvent::WacomEvent { event, .. } if self.pen_as_pointer => match event {
+                WacomEvent::Draw { position, .. } => {
+                    let pos = cgmath::Point2 {
+                        x: position.x as u16,
+                        y: position.y as u16,
+                    };
+                    if self.pointers.contains_key(&PointerSource::Pen) {
+                        self.motion(PointerSource::Pen, pos, now)
+                    } else {
+                        self.press(PointerSource::Pen, pos, now)
+                    }
+                }
+                WacomEvent::InstrumentChange {
+                    pen: WacomPen::Touch,
+                    state: false,
+                } => self.release(PointerSource::Pen, now),
+                _ => vec![],
+            },
+            _ => vec![],
+        }
+    }
+
+    /// The next point in time at which `expire` may produce a `LongPress`
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pointers
+            .values()
+            .filter(|p| !p.dragging && !p.long_pressed)
+            .map(|p| p.pressed_at + self.long_press_after)
+            .min()
+    }
+
+    /// Emits the long presses that are due without the pointer having moved.
+    pub fn expire(&mut self, now: Instant) -> Vec<PointerEvent> {
+        let mut events = vec![];
+        for (source, pointer) in self.pointers.iter_mut() {
+            if Self::long_press_due(pointer, self.long_press_after, now) {
+                pointer.long_pressed = true;
+                events.push(PointerEvent {
+                    source: *source,
+                    kind: PointerEventKind::LongPress,
+                    position: pointer.last,
+                    origin: pointer.origin,
+                    elapsed: now - pointer.pressed_at,
+                });
+            }
+        }
+        events
+    }
+
+    fn long_press_due(pointer: &PointerState, after: Duration, now: Instant) -> bool {
+        !pointer.dragging && !pointer.long_pressed && now - pointer.pressed_at >= after
+    }
+
+    fn press(
+        &mut self,
+        source: PointerSource,
+        pos: cgmath::Point2<u16>,
+        now: Instant,
+    ) -> Vec<PointerEvent> {
+        self.pointers.insert(
+            source,
+            PointerState {
+                origin: pos,
+                last: pos,
+                pressed_at: now,
+                dragging: false,
+                long_pressed: false,
+            },
+        );
+        vec![PointerEvent {
+            source,
+            kind: PointerEventKind::Press,
+            position: pos,
+            origin: pos,
+            elapsed: Duration::ZERO,
+        }]
+    }
+
+    fn motion(
+        &mut self,
+        source: PointerSource,
+        pos: cgmath::Point2<u16>,
+        now: Instant,
+    ) -> Vec<PointerEvent> {
+        let long_press_after = self.long_press_after;
+        let drag_threshold = i32::from(self.drag_threshold);
+        let pointer = match self.pointers.get_mut(&source) {
+            Some(pointer) => pointer,
+            None => return vec![],
+        };
+
+        let mut events = vec![];
+        if Self::long_press_due(pointer, long_press_after, now) {
+            pointer.long_pressed = true;
+            events.push(PointerEvent {
+                source,
+                kind: PointerEventKind::LongPress,
+                position: pointer.last,
+                origin: pointer.origin,
+                elapsed: now - pointer.pressed_at,
+            });
+        }
+
+        let from_origin = pos.cast::<i32>().unwrap() - pointer.origin.cast().unwrap();
+        if !pointer.dragging && from_origin.x.abs().max(from_origin.y.abs()) > drag_threshold {
+            pointer.dragging = true;
+        }
+        if pointer.dragging && pos != pointer.last {
+            events.push(PointerEvent {
+                source,
+                kind: PointerEventKind::Drag {
+                    delta: pos.cast::<i32>().unwrap() - pointer.last.cast().unwrap(),
+                },
+                position: pos,
+                origin: pointer.origin,
+                elapsed: now - pointer.pressed_at,
+            });
+        }
+        pointer.last = pos;
+        events
+    }
+
+    fn release(&mut self, source: PointerSource, now: Instant) -> Vec<PointerEvent> {
+        match self.pointers.remove(&source) {
+            Some(pointer) => vec![PointerEvent {
+                source,
+                kind: PointerEventKind::Release,
+                position: pointer.last,
+                origin: pointer.origin,
+                elapsed: now - pointer.pressed_at,
+            }],
+            None => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::Finger;
+
+    fn touch(kind: fn(Finger) -> MultitouchEvent, x: u16, y: u16) -> InputEvent {
+        InputEvent::MultitouchEvent {
+            event: kind(Finger {
+                tracking_id: 7,
+                pos: cgmath::Point2 { x, y },
+                pressed: true,
+                ..Default::default()
+            }),
+            timestamp: std::time::SystemTime::UNIX_EPOCH,
+        }
+    }
+
+    fn kinds(events: Vec<PointerEvent>) -> Vec<PointerEventKind> {
+        events.into_iter().map(|e| e.kind).collect()
+    }
+
+    #[test]
+    fn test_long_press_then_release() {
+        let mut dispatcher = PointerDispatcher::default();
+        let start = Instant::now();
+        let press = |finger| MultitouchEvent::Press { finger };
+        let moved = |finger| MultitouchEvent::Move { finger };
+        let release = |finger| MultitouchEvent::Release { finger };
+
+        let events = dispatcher.process(&touch(press, 100, 100), start);
+        assert_eq!(kinds(events), vec![PointerEventKind::Press]);
+        // Jitter below the threshold is not a drag
+        let events = dispatcher.process(&touch(moved, 105, 98), start);
+        assert!(events.is_empty());
+
+        let deadline = dispatcher.next_deadline().unwrap();
+        assert_eq!(deadline, start + dispatcher.long_press_after);
+        assert_eq!(
+            kinds(dispatcher.expire(deadline)),
+            vec![PointerEventKind::LongPress]
+        );
+        assert!(dispatcher.expire(deadline).is_empty());
+        assert_eq!(dispatcher.next_deadline(), None);
+
+        let events = dispatcher.process(&touch(release, 105, 98), deadline);
+        assert_eq!(kinds(events), vec![PointerEventKind::Release]);
+    }
+
+    #[test]
+    fn test_drag_reports_deltas() {
+        let mut dispatcher = PointerDispatcher::default();
+        let start = Instant::now();
+        let press = |finger| MultitouchEvent::Press { finger };
+        let moved = |finger| MultitouchEvent::Move { finger };
+
+        dispatcher.process(&touch(press, 100, 100), start);
+        let events = dispatcher.process(&touch(moved, 130, 100), start);
+        assert_eq!(
+            kinds(events),
+            vec![PointerEventKind::Drag {
+                delta: cgmath::Vector2 { x: 30, y: 0 }
+            }]
+        );
+        let events = dispatcher.process(&touch(moved, 131, 95), start);
+        assert_eq!(
+            kinds(events),
+            vec![PointerEventKind::Drag {
+                delta: cgmath::Vector2 { x: 1, y: -5 }
+            }]
+        );
+        // Once dragging, holding still doesn't turn into a long press
+        assert_eq!(dispatcher.next_deadline(), None);
+    }
+}